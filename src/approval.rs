@@ -0,0 +1,609 @@
+//! Approvals needing votes from several people
+//!
+//! An [`ApprovalPolicy`] in an approval step's [`APPROVAL_POLICY_KEY`]
+//! config entry names the approvers, how many of them must approve and who
+//! may veto. [`Approvals::vote`] records each vote and resolves the step as
+//! soon as the policy is met, or as soon as it can no longer be met: an
+//! approved step is completed, and a rejected one is handled as the
+//! policy's [`RejectionAction`] says.
+
+use crate::authorization::{Actor, Operation};
+use crate::{WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Utc};
+use cim_domain_workflow::value_objects::{StepId, StepType, WorkflowId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Step config key holding an [`ApprovalPolicy`]
+pub const APPROVAL_POLICY_KEY: &str = "approval_policy";
+
+/// How many approvers must approve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quorum {
+    /// Any one approver
+    Any,
+    /// A fixed number of approvers
+    AtLeast(usize),
+    /// More than half of the approvers
+    Majority,
+    /// Every approver
+    All,
+}
+
+impl Quorum {
+    /// Approvals needed out of `approvers`
+    pub fn required(&self, approvers: usize) -> usize {
+        match self {
+            Self::Any => 1,
+            Self::AtLeast(count) => *count,
+            Self::Majority => approvers / 2 + 1,
+            Self::All => approvers,
+        }
+    }
+}
+
+/// What a rejected approval does to the workflow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionAction {
+    /// Cancel the workflow
+    #[default]
+    CancelWorkflow,
+    /// Complete the step with `approved: false` and carry on
+    CompleteRejected,
+}
+
+/// Who approves a step and when it counts as approved or rejected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    pub approvers: Vec<String>,
+    pub quorum: Quorum,
+    /// Approvers whose rejection alone rejects the step
+    #[serde(default)]
+    pub vetoers: Vec<String>,
+    #[serde(default)]
+    pub on_rejection: RejectionAction,
+}
+
+impl ApprovalPolicy {
+    /// Policy with approvers and a quorum, without vetoes
+    pub fn new(approvers: impl IntoIterator<Item = impl Into<String>>, quorum: Quorum) -> Self {
+        Self {
+            approvers: approvers.into_iter().map(Into::into).collect(),
+            quorum,
+            vetoers: Vec::new(),
+            on_rejection: RejectionAction::default(),
+        }
+    }
+
+    /// Let an approver reject the step alone
+    pub fn with_veto(mut self, approver: impl Into<String>) -> Self {
+        self.vetoers.push(approver.into());
+        self
+    }
+
+    /// Set what a rejection does
+    pub fn on_rejection(mut self, action: RejectionAction) -> Self {
+        self.on_rejection = action;
+        self
+    }
+
+    /// Check that the policy can be met
+    pub fn validate(&self) -> Result<(), WorkflowGraphError> {
+        let required = self.quorum.required(self.approvers.len());
+        if required == 0 || required > self.approvers.len() {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Approval policy needs {required} of {} approvers",
+                self.approvers.len()
+            )));
+        }
+        if let Some(approver) =
+            self.approvers.iter().enumerate().find_map(|(i, approver)| {
+                self.approvers[..i].contains(approver).then_some(approver)
+            })
+        {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Approver {approver} is listed more than once"
+            )));
+        }
+        if let Some(vetoer) = self
+            .vetoers
+            .iter()
+            .find(|vetoer| !self.approvers.contains(vetoer))
+        {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Vetoer {vetoer} is not an approver"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Policy of a step from its config, if it has one
+    pub fn of_step(step: &WorkflowStep) -> Result<Option<Self>, WorkflowGraphError> {
+        let Some(value) = step.config.get(APPROVAL_POLICY_KEY) else {
+            return Ok(None);
+        };
+        let policy: Self = serde_json::from_value(value.clone()).map_err(|e| {
+            WorkflowGraphError::InvalidOperation(format!(
+                "Step {} has an invalid approval policy: {e}",
+                step.name
+            ))
+        })?;
+        policy.validate()?;
+        Ok(Some(policy))
+    }
+
+    /// Outcome of a set of votes, if decided
+    pub fn outcome(&self, votes: &[Vote]) -> Option<ApprovalOutcome> {
+        let approvals = votes
+            .iter()
+            .filter(|vote| vote.decision == Decision::Approve)
+            .count();
+        let rejections = votes.len() - approvals;
+        let required = self.quorum.required(self.approvers.len());
+
+        let vetoed = votes
+            .iter()
+            .any(|vote| vote.decision == Decision::Reject && self.vetoers.contains(&vote.approver));
+        if vetoed || self.approvers.len() - rejections < required {
+            Some(ApprovalOutcome::Rejected)
+        } else if approvals >= required {
+            Some(ApprovalOutcome::Approved)
+        } else {
+            None
+        }
+    }
+}
+
+/// An approver's decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Approve,
+    Reject,
+}
+
+/// How an approval ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalOutcome {
+    Approved,
+    Rejected,
+}
+
+/// One approver's vote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    pub approver: String,
+    pub decision: Decision,
+    pub comment: Option<String>,
+    pub voted_at: DateTime<Utc>,
+}
+
+/// An approver voted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteCast {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub vote: Vote,
+}
+
+/// The votes decided an approval
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalResolved {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub outcome: ApprovalOutcome,
+    pub votes: Vec<Vote>,
+    pub resolved_at: DateTime<Utc>,
+}
+
+/// Events raised by approval votes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApprovalEvent {
+    VoteCast(VoteCast),
+    ApprovalResolved(ApprovalResolved),
+}
+
+/// Votes on approval steps across workflows
+#[derive(Debug, Clone, Default)]
+pub struct Approvals {
+    votes: HashMap<(WorkflowId, StepId), Vec<Vote>>,
+}
+
+impl Approvals {
+    /// No votes yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Votes recorded on a step, oldest first
+    pub fn votes(&self, workflow_id: WorkflowId, step_id: StepId) -> &[Vote] {
+        self.votes
+            .get(&(workflow_id, step_id))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Record an approver's vote on an executable approval step, resolving
+    /// the step when the votes decide it
+    ///
+    /// Each approver votes once, and must be allowed to approve by the
+    /// graph's authorization policy. A rejection that cancels the workflow
+    /// must also be allowed to cancel it, or the vote is refused.
+    pub fn vote(
+        &mut self,
+        graph: &mut WorkflowGraph,
+        step_id: StepId,
+        actor: &Actor,
+        decision: Decision,
+        comment: Option<String>,
+    ) -> Result<Vec<ApprovalEvent>, WorkflowGraphError> {
+        if !graph.workflow.steps.contains_key(&step_id) {
+            return Err(WorkflowGraphError::StepNotFound(
+                step_id.as_uuid().to_string(),
+            ));
+        }
+        graph.authorize(actor, Operation::Approve, Some(step_id))?;
+
+        let step = &graph.workflow.steps[&step_id];
+        if step.step_type != StepType::Approval || !graph.get_executable_steps().contains(&step_id)
+        {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Step {} is not an open approval",
+                step.name
+            )));
+        }
+        let Some(policy) = ApprovalPolicy::of_step(step)? else {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Step {} has no approval policy",
+                step.name
+            )));
+        };
+        let approver = actor.id.as_str();
+        if !policy.approvers.iter().any(|name| name == approver) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "{approver} is not an approver of step {}",
+                step.name
+            )));
+        }
+        let step_name = step.name.clone();
+
+        let key = (graph.id(), step_id);
+        let mut votes = self.votes(key.0, step_id).to_vec();
+        if votes.iter().any(|vote| vote.approver == approver) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "{approver} has already voted on step {step_name}"
+            )));
+        }
+
+        let now = graph.environment().now();
+        let vote = Vote {
+            approver: approver.to_string(),
+            decision,
+            comment,
+            voted_at: now,
+        };
+        votes.push(vote.clone());
+        let outcome = policy.outcome(&votes);
+        let cancels = outcome == Some(ApprovalOutcome::Rejected)
+            && policy.on_rejection == RejectionAction::CancelWorkflow;
+        // A rejecting vote that cancels the workflow needs the right to cancel
+        if cancels {
+            graph.authorize(actor, Operation::Cancel, None)?;
+        }
+
+        let mut events = vec![ApprovalEvent::VoteCast(VoteCast {
+            workflow_id: key.0,
+            step_id,
+            vote,
+        })];
+        let Some(outcome) = outcome else {
+            self.votes.insert(key, votes);
+            return Ok(events);
+        };
+        self.votes.remove(&key);
+        if cancels {
+            graph.cancel_unchecked(actor, format!("Approval {step_name} was rejected"))?;
+        } else {
+            let data = HashMap::from([
+                (
+                    "approved".to_string(),
+                    serde_json::json!(outcome == ApprovalOutcome::Approved),
+                ),
+                ("votes".to_string(), serde_json::json!(votes)),
+            ]);
+            graph.complete_step_unchecked(step_id, approver.to_string(), data)?;
+        }
+
+        events.push(ApprovalEvent::ApprovalResolved(ApprovalResolved {
+            workflow_id: key.0,
+            step_id,
+            outcome,
+            votes,
+            resolved_at: now,
+        }));
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WorkflowEnvironment;
+    use cim_domain_workflow::value_objects::{StepStatus, WorkflowStatus};
+
+    /// Started workflow: Approve (approval with a policy) -> Pay
+    fn purchase(policy: &ApprovalPolicy) -> (WorkflowGraph, StepId, StepId) {
+        let environment =
+            WorkflowEnvironment::deterministic("2025-03-03T09:00:00Z".parse().unwrap());
+        let mut graph =
+            WorkflowGraph::new_in("Purchase".to_string(), String::new(), environment).unwrap();
+        let approve = graph
+            .add_step(
+                "Approve".to_string(),
+                String::new(),
+                StepType::Approval,
+                HashMap::from([(
+                    APPROVAL_POLICY_KEY.to_string(),
+                    serde_json::to_value(policy).unwrap(),
+                )]),
+                vec![],
+                None,
+                None,
+            )
+            .unwrap();
+        let pay = graph
+            .add_step(
+                "Pay".to_string(),
+                String::new(),
+                StepType::Automated,
+                HashMap::new(),
+                vec![approve],
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        (graph, approve, pay)
+    }
+
+    #[test]
+    fn test_quorum_approves_step() {
+        let policy = ApprovalPolicy::new(["ann", "ben", "cat"], Quorum::AtLeast(2));
+        let (mut graph, approve, pay) = purchase(&policy);
+        let mut approvals = Approvals::new();
+
+        let events = approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ann"),
+                Decision::Approve,
+                None,
+            )
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(approvals.votes(graph.id(), approve).len(), 1);
+        assert!(approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ann"),
+                Decision::Approve,
+                None
+            )
+            .is_err());
+        assert!(approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("zed"),
+                Decision::Approve,
+                None
+            )
+            .is_err());
+
+        // A single rejection still leaves two possible approvals
+        approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ben"),
+                Decision::Reject,
+                None,
+            )
+            .unwrap();
+        let events = approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("cat"),
+                Decision::Approve,
+                Some("Within budget".to_string()),
+            )
+            .unwrap();
+        let Some(ApprovalEvent::ApprovalResolved(resolved)) = events.last() else {
+            panic!("approval should be resolved");
+        };
+        assert_eq!(resolved.outcome, ApprovalOutcome::Approved);
+        assert_eq!(resolved.votes.len(), 3);
+        assert_eq!(
+            resolved.resolved_at,
+            "2025-03-03T09:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(graph.workflow.steps[&approve].status, StepStatus::Completed);
+        assert_eq!(graph.get_executable_steps(), vec![pay]);
+    }
+
+    #[test]
+    fn test_unreachable_unanimity_and_veto_reject() {
+        let policy = ApprovalPolicy::new(["legal", "finance"], Quorum::All)
+            .on_rejection(RejectionAction::CompleteRejected);
+        let (mut graph, approve, _) = purchase(&policy);
+        let events = Approvals::new()
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("finance"),
+                Decision::Reject,
+                None,
+            )
+            .unwrap();
+        assert!(matches!(
+            events.last(),
+            Some(ApprovalEvent::ApprovalResolved(ApprovalResolved {
+                outcome: ApprovalOutcome::Rejected,
+                ..
+            }))
+        ));
+        assert_eq!(graph.workflow.steps[&approve].status, StepStatus::Completed);
+
+        let policy = ApprovalPolicy::new(["ceo", "cfo", "cto"], Quorum::Majority).with_veto("ceo");
+        let (mut graph, approve, _) = purchase(&policy);
+        let mut approvals = Approvals::new();
+        approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("cfo"),
+                Decision::Approve,
+                None,
+            )
+            .unwrap();
+        approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ceo"),
+                Decision::Reject,
+                None,
+            )
+            .unwrap();
+        assert_eq!(graph.status(), &WorkflowStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_approvers_see_approval_in_worklist() {
+        let policy = ApprovalPolicy::new(["ann", "ben"], Quorum::Any);
+        let (mut graph, approve, _) = purchase(&policy);
+        let mut worklist = crate::Worklist::new();
+
+        for approver in ["ann", "ben"] {
+            let inbox = worklist.tasks_for([&graph], approver).unwrap();
+            assert_eq!(inbox.len(), 1);
+            assert_eq!(inbox[0].step_id, approve);
+        }
+        assert!(worklist.unassigned([&graph]).unwrap().is_empty());
+
+        worklist.claim(&graph, approve, "ann").unwrap();
+        assert!(worklist
+            .complete(&mut graph, approve, &Actor::new("ann"), HashMap::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        assert_eq!(Quorum::Majority.required(4), 3);
+        assert!(ApprovalPolicy::new(["ann"], Quorum::AtLeast(2))
+            .validate()
+            .is_err());
+        assert!(ApprovalPolicy::new(["ann", "ben"], Quorum::Any)
+            .with_veto("zed")
+            .validate()
+            .is_err());
+        assert!(ApprovalPolicy::new(Vec::<String>::new(), Quorum::All)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_policies_that_can_never_be_met() {
+        // Each approver votes once, so a repeated name could never count twice
+        assert!(ApprovalPolicy::new(["ann", "ann"], Quorum::All)
+            .validate()
+            .is_err());
+        assert!(ApprovalPolicy::new(["ann"], Quorum::AtLeast(0))
+            .validate()
+            .is_err());
+
+        let (mut graph, approve, _) = purchase(&ApprovalPolicy::new(["ann"], Quorum::Any));
+        graph
+            .workflow
+            .steps
+            .get_mut(&approve)
+            .unwrap()
+            .config
+            .insert(APPROVAL_POLICY_KEY.to_string(), serde_json::json!("ann"));
+        assert!(Approvals::new()
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ann"),
+                Decision::Approve,
+                None
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_votes_after_resolution_and_on_other_steps() {
+        let policy = ApprovalPolicy::new(["ann", "ben", "cat"], Quorum::Majority).with_veto("cat");
+        let (mut graph, approve, pay) = purchase(&policy);
+        let mut approvals = Approvals::new();
+        let mut vote = |graph: &mut WorkflowGraph, step_id, approver: &str, decision| {
+            approvals.vote(graph, step_id, &Actor::new(approver), decision, None)
+        };
+
+        assert!(vote(&mut graph, pay, "ann", Decision::Approve).is_err());
+        assert!(matches!(
+            vote(&mut graph, StepId::new(), "ann", Decision::Approve),
+            Err(WorkflowGraphError::StepNotFound(_))
+        ));
+        // A vetoer approving counts like anyone else
+        vote(&mut graph, approve, "cat", Decision::Approve).unwrap();
+        let events = vote(&mut graph, approve, "ann", Decision::Approve).unwrap();
+        assert_eq!(events.len(), 2);
+
+        // The veto comes after the quorum decided the step
+        assert!(vote(&mut graph, approve, "cat", Decision::Reject).is_err());
+        assert_eq!(graph.status(), &WorkflowStatus::Running);
+        assert!(approvals.votes(graph.id(), approve).is_empty());
+    }
+
+    #[test]
+    fn test_votes_before_the_step_is_open() {
+        let policy = ApprovalPolicy::new(["ann"], Quorum::Any);
+        let environment =
+            WorkflowEnvironment::deterministic("2025-03-03T09:00:00Z".parse().unwrap());
+        let mut graph =
+            WorkflowGraph::new_in("Draft".to_string(), String::new(), environment).unwrap();
+        let approve = graph
+            .add_step(
+                "Approve".to_string(),
+                String::new(),
+                StepType::Approval,
+                HashMap::from([(
+                    APPROVAL_POLICY_KEY.to_string(),
+                    serde_json::to_value(&policy).unwrap(),
+                )]),
+                vec![],
+                None,
+                None,
+            )
+            .unwrap();
+
+        let mut approvals = Approvals::new();
+        assert!(approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ann"),
+                Decision::Approve,
+                None
+            )
+            .is_err());
+        assert!(approvals.votes(graph.id(), approve).is_empty());
+    }
+}
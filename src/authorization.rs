@@ -0,0 +1,463 @@
+//! Who may change a workflow
+//!
+//! Mutations are performed by an [`Actor`]. The graph's
+//! [`AuthorizationPolicy`] grants each [`Operation`] to everyone, to actors
+//! with a role, or to the step's assignee, and every attempt is written to
+//! the graph's audit log. Methods without an actor act as
+//! [`Actor::system`], even where they record who did the work.
+
+use crate::approval::ApprovalPolicy;
+use crate::{WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Utc};
+use cim_domain_workflow::value_objects::StepId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// ID and role of the actor used when none is given
+pub const SYSTEM_ACTOR: &str = "system";
+
+/// Someone acting on a workflow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub id: String,
+    #[serde(default)]
+    pub roles: BTreeSet<String>,
+}
+
+impl Actor {
+    /// Actor without roles
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            roles: BTreeSet::new(),
+        }
+    }
+
+    /// The library acting on its own, with the `system` role
+    pub fn system() -> Self {
+        Self::new(SYSTEM_ACTOR).with_role(SYSTEM_ACTOR)
+    }
+
+    /// Add a role
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.insert(role.into());
+        self
+    }
+
+    /// Whether the actor has a role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+}
+
+/// Something an actor can do to a workflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    AddStep,
+    Start,
+    CompleteStep,
+    Approve,
+    Complete,
+    Cancel,
+}
+
+impl Operation {
+    /// Every operation
+    pub const ALL: [Operation; 6] = [
+        Operation::AddStep,
+        Operation::Start,
+        Operation::CompleteStep,
+        Operation::Approve,
+        Operation::Complete,
+        Operation::Cancel,
+    ];
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::AddStep => "add steps",
+            Operation::Start => "start the workflow",
+            Operation::CompleteStep => "complete step",
+            Operation::Approve => "approve step",
+            Operation::Complete => "complete the workflow",
+            Operation::Cancel => "cancel the workflow",
+        })
+    }
+}
+
+/// Who an operation is granted to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Grant {
+    Anyone,
+    /// Actors with a role
+    Role(String),
+    /// The step's assignee, or for approvals any approver in its policy
+    Assignee,
+}
+
+/// Which actors may perform each operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizationPolicy {
+    grants: HashMap<Operation, Vec<Grant>>,
+}
+
+impl AuthorizationPolicy {
+    /// Policy granting nothing, to build on with [`Self::allow`]
+    pub fn deny_all() -> Self {
+        Self {
+            grants: HashMap::new(),
+        }
+    }
+
+    /// Policy granting every operation to anyone
+    pub fn allow_all() -> Self {
+        Operation::ALL
+            .into_iter()
+            .fold(Self::deny_all(), |policy, operation| {
+                policy.allow(operation, Grant::Anyone)
+            })
+    }
+
+    /// Grant an operation
+    pub fn allow(mut self, operation: Operation, grant: Grant) -> Self {
+        let grants = self.grants.entry(operation).or_default();
+        if !grants.contains(&grant) {
+            grants.push(grant);
+        }
+        self
+    }
+
+    /// Grants of an operation
+    pub fn grants(&self, operation: Operation) -> &[Grant] {
+        self.grants
+            .get(&operation)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Whether an actor may perform an operation, on a step if it concerns one
+    pub fn permits(
+        &self,
+        graph: &WorkflowGraph,
+        actor: &Actor,
+        operation: Operation,
+        step_id: Option<StepId>,
+    ) -> bool {
+        let step = step_id.and_then(|id| graph.workflow.steps.get(&id));
+        self.grants(operation).iter().any(|grant| match grant {
+            Grant::Anyone => true,
+            Grant::Role(role) => actor.has_role(role),
+            Grant::Assignee => step.is_some_and(|step| {
+                step.assigned_to.as_deref() == Some(actor.id.as_str())
+                    || operation == Operation::Approve
+                        && ApprovalPolicy::of_step(step)
+                            .ok()
+                            .flatten()
+                            .is_some_and(|policy| policy.approvers.contains(&actor.id))
+            }),
+        })
+    }
+}
+
+impl Default for AuthorizationPolicy {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+/// An attempted operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub actor: String,
+    pub operation: Operation,
+    pub step_id: Option<StepId>,
+    pub allowed: bool,
+    pub at: DateTime<Utc>,
+}
+
+impl WorkflowGraph {
+    /// Set the policy deciding who may change the workflow
+    pub fn set_authorization_policy(&mut self, policy: AuthorizationPolicy) {
+        self.authorization_policy = policy;
+    }
+
+    /// Get the policy deciding who may change the workflow
+    pub fn authorization_policy(&self) -> &AuthorizationPolicy {
+        &self.authorization_policy
+    }
+
+    /// Attempted operations, oldest first
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit_log
+    }
+
+    /// Check an operation against the policy and record the attempt
+    pub(crate) fn authorize(
+        &mut self,
+        actor: &Actor,
+        operation: Operation,
+        step_id: Option<StepId>,
+    ) -> Result<(), WorkflowGraphError> {
+        let allowed = self
+            .authorization_policy
+            .permits(self, actor, operation, step_id);
+        self.audit_log.push(AuditEntry {
+            actor: actor.id.clone(),
+            operation,
+            step_id,
+            allowed,
+            at: self.environment.now(),
+        });
+        if allowed {
+            return Ok(());
+        }
+
+        let step_name = step_id
+            .and_then(|id| self.workflow.steps.get(&id))
+            .map(|step| format!(" {}", step.name))
+            .unwrap_or_default();
+        Err(WorkflowGraphError::Unauthorized {
+            actor: actor.id.clone(),
+            operation: format!("{operation}{step_name}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApprovalPolicy, Approvals, Decision, Quorum, APPROVAL_POLICY_KEY};
+    use cim_domain_workflow::value_objects::{StepStatus, StepType, WorkflowStatus};
+
+    fn policy() -> AuthorizationPolicy {
+        AuthorizationPolicy::deny_all()
+            .allow(Operation::AddStep, Grant::Role("designer".to_string()))
+            .allow(Operation::Start, Grant::Role("manager".to_string()))
+            .allow(Operation::CompleteStep, Grant::Assignee)
+            .allow(Operation::Approve, Grant::Assignee)
+    }
+
+    fn add(
+        graph: &mut WorkflowGraph,
+        actor: &Actor,
+        name: &str,
+    ) -> Result<StepId, WorkflowGraphError> {
+        graph.add_step_as(
+            actor,
+            name.to_string(),
+            String::new(),
+            StepType::Manual,
+            HashMap::new(),
+            vec![],
+            None,
+            Some("erin".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_default_policy_allows_system_and_audits() {
+        let mut graph = WorkflowGraph::new("Open".to_string(), String::new()).unwrap();
+        let step = graph
+            .add_step(
+                "Task".to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                vec![],
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        graph
+            .complete_step(step, "anyone".to_string(), HashMap::new())
+            .unwrap();
+
+        let log: Vec<(&str, Operation, bool)> = graph
+            .audit_log()
+            .iter()
+            .map(|entry| (entry.actor.as_str(), entry.operation, entry.allowed))
+            .collect();
+        assert_eq!(
+            log,
+            [
+                (SYSTEM_ACTOR, Operation::AddStep, true),
+                (SYSTEM_ACTOR, Operation::Start, true),
+                (SYSTEM_ACTOR, Operation::CompleteStep, true),
+            ]
+        );
+        // The name is recorded as who did the work, not trusted as an identity
+        assert!(matches!(
+            graph.uncommitted_events.last(),
+            Some(cim_domain_workflow::WorkflowDomainEvent::TaskCompleted(done))
+                if done.completed_by == "anyone"
+        ));
+    }
+
+    #[test]
+    fn test_roles_and_assignee_are_enforced() {
+        let mut graph = WorkflowGraph::new("Guarded".to_string(), String::new()).unwrap();
+        graph.set_authorization_policy(policy());
+        let designer = Actor::new("dave").with_role("designer");
+        let manager = Actor::new("mia").with_role("manager");
+
+        let error = add(&mut graph, &manager, "Sneaky").unwrap_err();
+        assert!(matches!(
+            error,
+            WorkflowGraphError::Unauthorized { ref actor, .. } if actor == "mia"
+        ));
+        assert!(graph.workflow.steps.is_empty());
+        let step = add(&mut graph, &designer, "Review").unwrap();
+
+        assert!(graph.start_as(&designer, HashMap::new()).is_err());
+        assert!(graph.start(HashMap::new()).is_err());
+        graph.start_as(&manager, HashMap::new()).unwrap();
+
+        assert!(graph
+            .complete_step_as(&manager, step, HashMap::new())
+            .is_err());
+        assert!(graph.cancel_as(&manager, "No".to_string()).is_err());
+        graph
+            .complete_step_as(&Actor::new("erin"), step, HashMap::new())
+            .unwrap();
+        assert_eq!(graph.workflow.steps[&step].status, StepStatus::Completed);
+
+        let denied: Vec<(&str, Operation)> = graph
+            .audit_log()
+            .iter()
+            .filter(|entry| !entry.allowed)
+            .map(|entry| (entry.actor.as_str(), entry.operation))
+            .collect();
+        assert_eq!(
+            denied,
+            [
+                ("mia", Operation::AddStep),
+                ("dave", Operation::Start),
+                (SYSTEM_ACTOR, Operation::Start),
+                ("mia", Operation::CompleteStep),
+                ("mia", Operation::Cancel),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_approvers_are_authorized_to_approve() {
+        let mut graph = WorkflowGraph::new("Spend".to_string(), String::new()).unwrap();
+        let policy = ApprovalPolicy::new(["ann", "ben"], Quorum::All);
+        let approve = graph
+            .add_step(
+                "Approve".to_string(),
+                String::new(),
+                StepType::Approval,
+                HashMap::from([(
+                    APPROVAL_POLICY_KEY.to_string(),
+                    serde_json::to_value(&policy).unwrap(),
+                )]),
+                vec![],
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        graph.set_authorization_policy(
+            AuthorizationPolicy::deny_all().allow(Operation::Approve, Grant::Assignee),
+        );
+
+        let mut approvals = Approvals::new();
+        for approver in ["ann", "ben"] {
+            approvals
+                .vote(
+                    &mut graph,
+                    approve,
+                    &Actor::new(approver),
+                    Decision::Approve,
+                    None,
+                )
+                .unwrap();
+        }
+        // Resolving the approval completes the step on the policy's behalf
+        assert_eq!(graph.workflow.steps[&approve].status, StepStatus::Completed);
+
+        graph.set_authorization_policy(AuthorizationPolicy::deny_all());
+        assert!(!graph.authorization_policy().permits(
+            &graph,
+            &Actor::new("ann"),
+            Operation::Approve,
+            Some(approve)
+        ));
+    }
+
+    #[test]
+    fn test_votes_are_authorized_before_they_count() {
+        let mut graph = WorkflowGraph::new("Veto".to_string(), String::new()).unwrap();
+        let policy = ApprovalPolicy::new(["ann", "ben"], Quorum::All).with_veto("ann");
+        let approve = graph
+            .add_step(
+                "Approve".to_string(),
+                String::new(),
+                StepType::Approval,
+                HashMap::from([(
+                    APPROVAL_POLICY_KEY.to_string(),
+                    serde_json::to_value(&policy).unwrap(),
+                )]),
+                vec![],
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        graph.set_authorization_policy(
+            AuthorizationPolicy::deny_all()
+                .allow(Operation::Approve, Grant::Assignee)
+                .allow(Operation::Cancel, Grant::Role("owner".to_string())),
+        );
+        let mut approvals = Approvals::new();
+
+        // An outsider is refused by the policy, and the attempt is audited
+        let error = approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("zed"),
+                Decision::Approve,
+                None,
+            )
+            .unwrap_err();
+        assert!(matches!(error, WorkflowGraphError::Unauthorized { .. }));
+        let last = graph.audit_log().last().unwrap();
+        assert_eq!((last.actor.as_str(), last.allowed), ("zed", false));
+
+        // A veto that would cancel the workflow needs the right to cancel
+        let error = approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ann"),
+                Decision::Reject,
+                None,
+            )
+            .unwrap_err();
+        assert!(matches!(error, WorkflowGraphError::Unauthorized { .. }));
+        assert_eq!(
+            graph.audit_log().last().unwrap().operation,
+            Operation::Cancel
+        );
+        assert!(approvals.votes(graph.id(), approve).is_empty());
+        assert_eq!(graph.status(), &WorkflowStatus::Running);
+
+        approvals
+            .vote(
+                &mut graph,
+                approve,
+                &Actor::new("ann").with_role("owner"),
+                Decision::Reject,
+                None,
+            )
+            .unwrap();
+        assert_eq!(graph.status(), &WorkflowStatus::Cancelled);
+    }
+}
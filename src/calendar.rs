@@ -0,0 +1,421 @@
+//! Business hours and due dates
+//!
+//! Estimated durations count working minutes: a [`BusinessCalendar`] only
+//! lets time pass on working days, between the start and end of the
+//! working day in its time zone, and never on holidays. Working days are
+//! walked in local time, so a daylight saving change during working hours
+//! shifts that day's end by the change.
+
+use crate::impact::remaining_minutes;
+use crate::{topology, WorkflowGraph, WorkflowGraphError};
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use cim_domain_workflow::value_objects::StepId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Working hours, working days and holidays in a time zone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "CalendarFields")]
+pub struct BusinessCalendar {
+    time_zone: Tz,
+    start_of_day: NaiveTime,
+    end_of_day: NaiveTime,
+    working_days: Vec<Weekday>,
+    holidays: BTreeSet<NaiveDate>,
+}
+
+/// Deserialized fields of a [`BusinessCalendar`], checked like its builders
+#[derive(Deserialize)]
+struct CalendarFields {
+    time_zone: Tz,
+    start_of_day: NaiveTime,
+    end_of_day: NaiveTime,
+    working_days: Vec<Weekday>,
+    holidays: BTreeSet<NaiveDate>,
+}
+
+impl TryFrom<CalendarFields> for BusinessCalendar {
+    type Error = WorkflowGraphError;
+
+    fn try_from(fields: CalendarFields) -> Result<Self, Self::Error> {
+        let mut calendar = Self::new(fields.time_zone)
+            .with_hours(fields.start_of_day, fields.end_of_day)?
+            .with_working_days(fields.working_days)?;
+        calendar.holidays = fields.holidays;
+        Ok(calendar)
+    }
+}
+
+impl BusinessCalendar {
+    /// Monday to Friday, 09:00 to 17:00, in a time zone
+    pub fn new(time_zone: Tz) -> Self {
+        Self {
+            time_zone,
+            start_of_day: NaiveTime::from_hms_opt(9, 0, 0).expect("valid time"),
+            end_of_day: NaiveTime::from_hms_opt(17, 0, 0).expect("valid time"),
+            working_days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            holidays: BTreeSet::new(),
+        }
+    }
+
+    /// Set the working hours of each working day
+    pub fn with_hours(
+        mut self,
+        start: NaiveTime,
+        end: NaiveTime,
+    ) -> Result<Self, WorkflowGraphError> {
+        if start >= end {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Working day must start before it ends, got {start} to {end}"
+            )));
+        }
+        self.start_of_day = start;
+        self.end_of_day = end;
+        Ok(self)
+    }
+
+    /// Set the days of the week that are worked
+    pub fn with_working_days(
+        mut self,
+        days: impl IntoIterator<Item = Weekday>,
+    ) -> Result<Self, WorkflowGraphError> {
+        let mut working_days: Vec<Weekday> = days.into_iter().collect();
+        working_days.sort_by_key(Weekday::num_days_from_monday);
+        working_days.dedup();
+        if working_days.is_empty() {
+            return Err(WorkflowGraphError::InvalidOperation(
+                "A business calendar needs at least one working day".to_string(),
+            ));
+        }
+        self.working_days = working_days;
+        Ok(self)
+    }
+
+    /// Add a day on which nothing is worked
+    pub fn with_holiday(mut self, date: NaiveDate) -> Self {
+        self.holidays.insert(date);
+        self
+    }
+
+    /// Time zone of the working hours
+    pub fn time_zone(&self) -> Tz {
+        self.time_zone
+    }
+
+    /// Whether work happens on a local date
+    pub fn is_working_day(&self, date: NaiveDate) -> bool {
+        self.working_days.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    /// Working minutes in one working day
+    pub fn minutes_per_day(&self) -> u64 {
+        (self.end_of_day - self.start_of_day).num_minutes() as u64
+    }
+
+    /// The moment `minutes` of working time after `from`
+    ///
+    /// With zero minutes this is `from` itself during working hours, or the
+    /// start of the next working day outside them.
+    pub fn add_working_minutes(&self, from: DateTime<Utc>, minutes: u64) -> DateTime<Utc> {
+        let mut now = from.with_timezone(&self.time_zone).naive_local();
+        let mut left = minutes;
+
+        loop {
+            let date = now.date();
+            if !self.is_working_day(date) || now.time() >= self.end_of_day {
+                now = self.next_working_day(date).and_time(self.start_of_day);
+                continue;
+            }
+            if now.time() < self.start_of_day {
+                now = date.and_time(self.start_of_day);
+            }
+
+            let available = (date.and_time(self.end_of_day) - now).num_minutes() as u64;
+            if left <= available {
+                return self.to_utc(now + Duration::minutes(left as i64));
+            }
+            left -= available;
+            now = self.next_working_day(date).and_time(self.start_of_day);
+        }
+    }
+
+    fn next_working_day(&self, date: NaiveDate) -> NaiveDate {
+        date.iter_days()
+            .skip(1)
+            .find(|day| self.is_working_day(*day))
+            .expect("some day after any date is a working day")
+    }
+
+    /// Convert local time, moving out of a daylight saving gap if needed
+    fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let mut local = local;
+        loop {
+            if let Some(at) = self.time_zone.from_local_datetime(&local).earliest() {
+                return at.with_timezone(&Utc);
+            }
+            local += Duration::minutes(30);
+        }
+    }
+}
+
+/// When a step is expected to start and be done
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepDue {
+    pub step_id: StepId,
+    pub starts_at: DateTime<Utc>,
+    pub due_at: DateTime<Utc>,
+}
+
+/// Due dates of the remaining work
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Eta {
+    /// Steps in topological order
+    pub steps: Vec<StepDue>,
+    /// When the last step is done
+    pub due_at: DateTime<Utc>,
+}
+
+impl Eta {
+    /// Due dates of one step
+    pub fn get(&self, step_id: StepId) -> Option<&StepDue> {
+        self.steps.iter().find(|step| step.step_id == step_id)
+    }
+}
+
+impl WorkflowGraph {
+    /// Due dates of the remaining work, starting at `start` and counting
+    /// estimated durations in working time
+    ///
+    /// Every step starts as soon as its dependencies are done, so the
+    /// workflow's due date is the end of its remaining critical path.
+    pub fn eta(&self, calendar: &BusinessCalendar, start: DateTime<Utc>) -> Eta {
+        let steps = &self.workflow.steps;
+        let finish = topology::finish_times(self, remaining_minutes);
+
+        let steps: Vec<StepDue> = topology::topological_order(self)
+            .into_iter()
+            .map(|id| {
+                let end = finish[&id];
+                let begin = end - remaining_minutes(&steps[&id]);
+                StepDue {
+                    step_id: id,
+                    starts_at: calendar.add_working_minutes(start, begin),
+                    due_at: calendar.add_working_minutes(start, end),
+                }
+            })
+            .collect();
+        let end = finish.values().max().copied().unwrap_or(0);
+
+        Eta {
+            steps,
+            due_at: calendar.add_working_minutes(start, end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cim_domain_workflow::value_objects::StepType;
+    use std::collections::HashMap;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_working_minutes_skip_nights_weekends_and_holidays() {
+        // 2025-07-04 is a Friday and a holiday
+        let calendar = BusinessCalendar::new(chrono_tz::UTC)
+            .with_holiday(NaiveDate::from_ymd_opt(2025, 7, 4).unwrap());
+
+        let thursday = utc("2025-07-03T16:00:00Z");
+        assert_eq!(
+            calendar.add_working_minutes(thursday, 30),
+            utc("2025-07-03T16:30:00Z")
+        );
+        assert_eq!(
+            calendar.add_working_minutes(thursday, 90),
+            utc("2025-07-07T09:30:00Z")
+        );
+        assert_eq!(
+            calendar.add_working_minutes(thursday, 60 + 480),
+            utc("2025-07-07T17:00:00Z")
+        );
+
+        let saturday = utc("2025-07-05T12:00:00Z");
+        assert_eq!(
+            calendar.add_working_minutes(saturday, 0),
+            utc("2025-07-07T09:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_time_zone_and_custom_week() {
+        let calendar = BusinessCalendar::new(chrono_tz::Europe::Berlin)
+            .with_hours(
+                NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            )
+            .unwrap()
+            .with_working_days([Weekday::Sun, Weekday::Mon])
+            .unwrap();
+        assert_eq!(calendar.minutes_per_day(), 240);
+
+        // Berlin is UTC+2 in summer; Sunday 2025-07-06 08:00 local is 06:00 UTC
+        let friday = utc("2025-07-04T10:00:00Z");
+        assert_eq!(
+            calendar.add_working_minutes(friday, 300),
+            utc("2025-07-07T07:00:00Z")
+        );
+
+        assert!(BusinessCalendar::new(chrono_tz::UTC)
+            .with_working_days([])
+            .is_err());
+    }
+
+    #[test]
+    fn test_eta_follows_critical_path() {
+        let mut graph = WorkflowGraph::new("Hire".to_string(), "Recruiting".to_string()).unwrap();
+        let mut add = |name: &str, dependencies: Vec<StepId>, minutes: u32| {
+            graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    dependencies,
+                    Some(minutes),
+                    None,
+                )
+                .unwrap()
+        };
+        let screen = add("Screen", vec![], 240);
+        let interview = add("Interview", vec![screen], 480);
+        let references = add("References", vec![screen], 60);
+        let offer = add("Offer", vec![interview, references], 60);
+
+        let calendar = BusinessCalendar::new(chrono_tz::UTC);
+        let monday = utc("2025-07-07T09:00:00Z");
+        let eta = graph.eta(&calendar, monday);
+
+        assert_eq!(eta.get(screen).unwrap().due_at, utc("2025-07-07T13:00:00Z"));
+        assert_eq!(
+            eta.get(references).unwrap().due_at,
+            utc("2025-07-07T14:00:00Z")
+        );
+        assert_eq!(
+            eta.get(interview).unwrap().due_at,
+            utc("2025-07-08T13:00:00Z")
+        );
+        assert_eq!(
+            eta.get(offer).unwrap().starts_at,
+            utc("2025-07-08T13:00:00Z")
+        );
+        assert_eq!(eta.due_at, utc("2025-07-08T14:00:00Z"));
+        assert_eq!(eta.steps.last().unwrap().step_id, offer);
+    }
+
+    #[test]
+    fn test_day_boundaries_and_daylight_saving_gap() {
+        let calendar = BusinessCalendar::new(chrono_tz::UTC);
+        let friday_close = utc("2025-07-04T17:00:00Z");
+        assert_eq!(
+            calendar.add_working_minutes(friday_close, 0),
+            utc("2025-07-07T09:00:00Z")
+        );
+        assert_eq!(
+            calendar.add_working_minutes(utc("2025-07-04T16:00:00Z"), 60),
+            friday_close
+        );
+
+        // Berlin skips 02:00 to 03:00 local on Sunday 2025-03-30; local time
+        // walks through the gap, so that day holds an hour less real time
+        let night_shift = BusinessCalendar::new(chrono_tz::Europe::Berlin)
+            .with_hours(
+                NaiveTime::from_hms_opt(1, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            )
+            .unwrap()
+            .with_working_days([Weekday::Sun])
+            .unwrap()
+            .with_holiday(NaiveDate::from_ymd_opt(2025, 4, 6).unwrap());
+        let start = utc("2025-03-30T00:00:00Z");
+        assert_eq!(
+            night_shift.add_working_minutes(start, 90),
+            utc("2025-03-30T01:00:00Z")
+        );
+        assert_eq!(
+            night_shift.add_working_minutes(start, 240),
+            utc("2025-03-30T03:00:00Z")
+        );
+        // The next Sunday is a holiday, so work resumes a fortnight later
+        assert_eq!(
+            night_shift.add_working_minutes(start, 241),
+            utc("2025-04-12T23:01:00Z")
+        );
+    }
+
+    #[test]
+    fn test_eta_over_a_dependency_cycle() {
+        let mut graph = WorkflowGraph::new("Loop".to_string(), String::new()).unwrap();
+        let mut ids = Vec::new();
+        for name in ["Ask", "Answer"] {
+            let id = graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    ids.clone(),
+                    Some(60),
+                    None,
+                )
+                .unwrap();
+            ids.push(id);
+        }
+        let (ask, answer) = (ids[0], ids[1]);
+        graph
+            .workflow
+            .steps
+            .get_mut(&ask)
+            .unwrap()
+            .dependencies
+            .push(answer);
+
+        let monday = utc("2025-07-07T09:00:00Z");
+        let eta = graph.eta(&BusinessCalendar::new(chrono_tz::UTC), monday);
+        assert_eq!(eta.steps.len(), 2);
+        assert_eq!(eta.get(answer).unwrap().due_at, utc("2025-07-07T10:00:00Z"));
+        assert_eq!(eta.get(ask).unwrap().starts_at, utc("2025-07-07T10:00:00Z"));
+        assert_eq!(eta.due_at, utc("2025-07-07T11:00:00Z"));
+    }
+
+    #[test]
+    fn test_deserialized_calendars_are_checked() {
+        let calendar = BusinessCalendar::new(chrono_tz::Europe::Berlin)
+            .with_holiday(NaiveDate::from_ymd_opt(2025, 12, 25).unwrap());
+        let json = serde_json::to_value(&calendar).unwrap();
+        assert_eq!(
+            serde_json::from_value::<BusinessCalendar>(json.clone()).unwrap(),
+            calendar
+        );
+
+        let mut reversed = json.clone();
+        reversed["start_of_day"] = serde_json::json!("18:00:00");
+        let mut idle = json;
+        idle["working_days"] = serde_json::json!([]);
+        for invalid in [reversed, idle] {
+            assert!(serde_json::from_value::<BusinessCalendar>(invalid).is_err());
+        }
+    }
+}
@@ -0,0 +1,677 @@
+//! Canonical serialization and content addressing of workflow definitions
+//!
+//! A definition's canonical form leaves out everything that differs between
+//! two copies of the same definition: step and workflow IDs, step states,
+//! timestamps and metadata properties. Steps are sorted, dependencies refer to
+//! positions in that order, and JSON objects are written with sorted keys and
+//! no whitespace. Hashing the canonical bytes with BLAKE3 gives a [`Cid`] that
+//! is identical for identical definitions on any machine.
+
+use crate::{WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepType, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Multicodec code for JSON content
+pub const JSON_CODEC: u64 = 0x0200;
+
+/// Multihash code for BLAKE3-256
+pub const BLAKE3_MULTIHASH: u64 = 0x1e;
+
+/// Binary prefix of every [`Cid`]: version 1, JSON codec, BLAKE3 multihash of
+/// 32 bytes, each as an unsigned varint
+const CID_PREFIX: [u8; 5] = [0x01, 0x80, 0x04, BLAKE3_MULTIHASH as u8, 0x20];
+
+/// Multibase prefix for lowercase, unpadded RFC 4648 base32
+const BASE32_PREFIX: char = 'b';
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Content identifier: a CIDv1 over BLAKE3-256 of canonical JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cid([u8; 32]);
+
+impl Cid {
+    /// Compute the content identifier of canonical bytes
+    pub fn for_bytes(bytes: &[u8]) -> Self {
+        Self(*blake3::hash(bytes).as_bytes())
+    }
+
+    /// Whether `bytes` hash to this identifier
+    pub fn verify(&self, bytes: &[u8]) -> bool {
+        *self == Self::for_bytes(bytes)
+    }
+
+    /// Binary CID encoding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = CID_PREFIX.to_vec();
+        bytes.extend_from_slice(&self.0);
+        bytes
+    }
+
+    /// Parse a binary CID encoding
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WorkflowGraphError> {
+        let digest = bytes
+            .strip_prefix(&CID_PREFIX[..])
+            .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+            .ok_or_else(|| {
+                WorkflowGraphError::SerializationError(
+                    "Not a CIDv1 of BLAKE3-256 over JSON".to_string(),
+                )
+            })?;
+        Ok(Self(digest))
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.to_bytes();
+        let mut out = String::with_capacity(1 + (bytes.len() * 8).div_ceil(5));
+        out.push(BASE32_PREFIX);
+        for chunk in bytes.chunks(5) {
+            let mut buffer = [0u8; 5];
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            let bits = buffer.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+            for i in 0..(chunk.len() * 8).div_ceil(5) {
+                let index = (bits >> (35 - i * 5)) & 0x1f;
+                out.push(BASE32_ALPHABET[index as usize] as char);
+            }
+        }
+        f.write_str(&out)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = WorkflowGraphError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WorkflowGraphError::SerializationError(format!("Invalid CID: {s}"));
+        let encoded = s.strip_prefix(BASE32_PREFIX).ok_or_else(invalid)?;
+
+        let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+        let (mut bits, mut count) = (0u32, 0u32);
+        for c in encoded.bytes() {
+            let value = BASE32_ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(invalid)?;
+            bits = (bits << 5) | value as u32;
+            count += 5;
+            if count >= 8 {
+                count -= 8;
+                bytes.push((bits >> count) as u8);
+                bits &= (1 << count) - 1;
+            }
+        }
+        Self::from_bytes(&bytes).map_err(|_| invalid())
+    }
+}
+
+impl Serialize for Cid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cid {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A step in canonical form
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanonicalStep {
+    pub name: String,
+    pub description: String,
+    pub step_type: StepType,
+    pub config: BTreeMap<String, serde_json::Value>,
+    /// Positions of the dependencies in [`CanonicalDefinition::steps`]
+    pub dependencies: Vec<usize>,
+    pub estimated_duration_minutes: Option<u32>,
+    pub assigned_to: Option<String>,
+}
+
+/// A workflow definition in canonical form
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanonicalDefinition {
+    pub name: String,
+    pub description: String,
+    pub steps: Vec<CanonicalStep>,
+}
+
+impl CanonicalDefinition {
+    /// Canonical form of a workflow graph's definition
+    pub fn from_graph(graph: &WorkflowGraph) -> Self {
+        let mut steps: Vec<(&WorkflowStep, CanonicalStep, String)> = graph
+            .workflow
+            .steps
+            .values()
+            .map(|step| {
+                let canonical = CanonicalStep {
+                    name: step.name.clone(),
+                    description: step.description.clone(),
+                    step_type: step.step_type.clone(),
+                    config: step
+                        .config
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                    dependencies: Vec::new(),
+                    estimated_duration_minutes: step.estimated_duration_minutes,
+                    assigned_to: step.assigned_to.clone(),
+                };
+                let sort_key =
+                    canonical_json(&serde_json::to_value(&canonical).unwrap_or_default());
+                (step, canonical, sort_key)
+            })
+            .collect();
+        // Steps with the same content are told apart by their place in the graph
+        let content: HashMap<StepId, &str> = steps
+            .iter()
+            .map(|(step, _, key)| (step.id, key.as_str()))
+            .collect();
+        let structure = structural_labels(graph, &content);
+        steps.sort_by(|a, b| {
+            a.1.name
+                .cmp(&b.1.name)
+                .then_with(|| a.2.cmp(&b.2))
+                .then_with(|| structure[&a.0.id].cmp(&structure[&b.0.id]))
+        });
+
+        let positions: HashMap<StepId, usize> = steps
+            .iter()
+            .enumerate()
+            .map(|(position, (step, _, _))| (step.id, position))
+            .collect();
+
+        Self {
+            name: graph.metadata.name.clone(),
+            description: graph.metadata.description.clone(),
+            steps: steps
+                .into_iter()
+                .map(|(step, mut canonical, _)| {
+                    canonical.dependencies = step
+                        .dependencies
+                        .iter()
+                        .filter_map(|dep| positions.get(dep).copied())
+                        .collect();
+                    canonical.dependencies.sort_unstable();
+                    canonical
+                })
+                .collect(),
+        }
+    }
+
+    /// Canonical bytes: compact JSON with sorted object keys
+    pub fn to_bytes(&self) -> Vec<u8> {
+        canonical_json(&serde_json::to_value(self).unwrap_or_default()).into_bytes()
+    }
+
+    /// Parse canonical bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WorkflowGraphError> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))
+    }
+
+    /// Content identifier of this definition
+    pub fn cid(&self) -> Cid {
+        Cid::for_bytes(&self.to_bytes())
+    }
+
+    /// Build a new workflow graph, with fresh IDs, from this definition
+    pub fn to_graph(&self) -> Result<WorkflowGraph, WorkflowGraphError> {
+        let mut graph = WorkflowGraph::new(self.name.clone(), self.description.clone())?;
+        let mut ids: Vec<Option<StepId>> = vec![None; self.steps.len()];
+
+        // Add steps once all of their dependencies exist
+        while ids.iter().any(Option::is_none) {
+            let ready = (0..self.steps.len()).find(|&i| {
+                ids[i].is_none()
+                    && self.steps[i]
+                        .dependencies
+                        .iter()
+                        .all(|&dep| ids.get(dep).copied().flatten().is_some())
+            });
+            let Some(position) = ready else {
+                return Err(WorkflowGraphError::CircularDependency(format!(
+                    "Definition {} has unresolvable dependencies",
+                    self.name
+                )));
+            };
+
+            let step = &self.steps[position];
+            let id = graph.add_step(
+                step.name.clone(),
+                step.description.clone(),
+                step.step_type.clone(),
+                step.config.clone().into_iter().collect(),
+                step.dependencies
+                    .iter()
+                    .filter_map(|&dep| ids[dep])
+                    .collect(),
+                step.estimated_duration_minutes,
+                step.assigned_to.clone(),
+            )?;
+            ids[position] = Some(id);
+        }
+
+        Ok(graph)
+    }
+}
+
+impl WorkflowGraph {
+    /// Canonical form of this workflow's definition
+    pub fn canonical_definition(&self) -> CanonicalDefinition {
+        CanonicalDefinition::from_graph(self)
+    }
+
+    /// Content identifier of this workflow's definition
+    pub fn cid(&self) -> Cid {
+        self.canonical_definition().cid()
+    }
+}
+
+/// Content-addressed store of immutable workflow definitions
+///
+/// Storing the same definition twice keeps a single copy.
+#[derive(Debug, Clone, Default)]
+pub struct DefinitionStore {
+    definitions: HashMap<Cid, CanonicalDefinition>,
+}
+
+impl DefinitionStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a workflow's definition, returning its content identifier
+    pub fn put(&mut self, graph: &WorkflowGraph) -> Cid {
+        self.put_definition(graph.canonical_definition())
+    }
+
+    /// Store a canonical definition, returning its content identifier
+    pub fn put_definition(&mut self, definition: CanonicalDefinition) -> Cid {
+        let cid = definition.cid();
+        self.definitions.entry(cid).or_insert(definition);
+        cid
+    }
+
+    /// Get a definition by content identifier
+    pub fn get(&self, cid: &Cid) -> Option<&CanonicalDefinition> {
+        self.definitions.get(cid)
+    }
+
+    /// Whether a definition is stored
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.definitions.contains_key(cid)
+    }
+
+    /// Number of distinct definitions
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    /// Whether the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+}
+
+/// Write a JSON value with sorted object keys and no whitespace
+pub fn canonical_json(value: &serde_json::Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(item, out);
+            }
+            out.push('}');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+type Label = [u8; 32];
+
+/// Labels that tell steps apart by content and by their place in the graph,
+/// independent of step IDs
+///
+/// Every step starts from its content and is repeatedly relabelled with the
+/// labels of its dependencies and dependents until no more steps can be told
+/// apart. Steps that still share a label are split by singling one of them
+/// out and refining again, trying each and keeping the split whose labels sort
+/// first. Steps that are interchangeable give the same order whichever is
+/// singled out, so the labels are distinct and depend only on the definition.
+fn structural_labels(
+    graph: &WorkflowGraph,
+    content: &HashMap<StepId, &str>,
+) -> HashMap<StepId, Label> {
+    let mut dependencies: HashMap<StepId, Vec<StepId>> = HashMap::new();
+    let mut dependents: HashMap<StepId, Vec<StepId>> = HashMap::new();
+    for &id in content.keys() {
+        dependencies.entry(id).or_default();
+        dependents.entry(id).or_default();
+        for dep in &graph.workflow.steps[&id].dependencies {
+            if content.contains_key(dep) {
+                dependencies.entry(id).or_default().push(*dep);
+                dependents.entry(*dep).or_default().push(id);
+            }
+        }
+    }
+    let neighbours = Neighbours {
+        dependencies,
+        dependents,
+    };
+
+    let mut labels: HashMap<StepId, Label> = content
+        .iter()
+        .map(|(&id, key)| (id, *blake3::hash(key.as_bytes()).as_bytes()))
+        .collect();
+    neighbours.refine(&mut labels);
+
+    while let Some(tied) = first_tie(&labels) {
+        labels = tied
+            .into_iter()
+            .map(|id| {
+                let mut split = labels.clone();
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&split[&id]);
+                hasher.update(b"*");
+                split.insert(id, *hasher.finalize().as_bytes());
+                neighbours.refine(&mut split);
+                split
+            })
+            .min_by_key(sorted_labels)
+            .unwrap_or(labels);
+    }
+    labels
+}
+
+struct Neighbours {
+    dependencies: HashMap<StepId, Vec<StepId>>,
+    dependents: HashMap<StepId, Vec<StepId>>,
+}
+
+impl Neighbours {
+    /// Relabel every step with its neighbours' labels until the number of
+    /// distinct labels stops growing
+    fn refine(&self, labels: &mut HashMap<StepId, Label>) {
+        let mut classes = distinct(labels);
+        loop {
+            let refined: HashMap<StepId, Label> = labels
+                .iter()
+                .map(|(&id, label)| {
+                    let mut hasher = blake3::Hasher::new();
+                    hasher.update(label);
+                    for (marker, neighbours) in [
+                        (b"<", &self.dependencies[&id]),
+                        (b">", &self.dependents[&id]),
+                    ] {
+                        let mut around: Vec<&Label> =
+                            neighbours.iter().map(|n| &labels[n]).collect();
+                        around.sort_unstable();
+                        hasher.update(marker);
+                        for neighbour in around {
+                            hasher.update(neighbour);
+                        }
+                    }
+                    (id, *hasher.finalize().as_bytes())
+                })
+                .collect();
+            *labels = refined;
+            let refined_classes = distinct(labels);
+            if refined_classes == classes {
+                return;
+            }
+            classes = refined_classes;
+        }
+    }
+}
+
+fn distinct(labels: &HashMap<StepId, Label>) -> usize {
+    labels.values().collect::<HashSet<_>>().len()
+}
+
+fn sorted_labels(labels: &HashMap<StepId, Label>) -> Vec<Label> {
+    let mut sorted: Vec<Label> = labels.values().copied().collect();
+    sorted.sort_unstable();
+    sorted
+}
+
+/// Steps sharing the smallest label that more than one step has
+fn first_tie(labels: &HashMap<StepId, Label>) -> Option<Vec<StepId>> {
+    let mut by_label: BTreeMap<&Label, Vec<StepId>> = BTreeMap::new();
+    for (id, label) in labels {
+        by_label.entry(label).or_default().push(*id);
+    }
+    by_label.into_values().find(|ids| ids.len() > 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(order_reversed: bool) -> WorkflowGraph {
+        let mut graph =
+            WorkflowGraph::new("CID Test".to_string(), "Content addressing".to_string()).unwrap();
+        let config = HashMap::from([
+            (
+                "b".to_string(),
+                serde_json::json!({"y": 1, "x": [true, null]}),
+            ),
+            ("a".to_string(), serde_json::json!("first")),
+        ]);
+        let (first, second) = if order_reversed {
+            ("Editorial Review", "Technical Review")
+        } else {
+            ("Technical Review", "Editorial Review")
+        };
+        let draft = graph
+            .add_step(
+                "Create Draft".to_string(),
+                "Write".to_string(),
+                StepType::Manual,
+                config,
+                Vec::new(),
+                Some(120),
+                Some("content-author".to_string()),
+            )
+            .unwrap();
+        for name in [first, second] {
+            graph
+                .add_step(
+                    name.to_string(),
+                    "Review".to_string(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    vec![draft],
+                    Some(45),
+                    None,
+                )
+                .unwrap();
+        }
+        graph
+    }
+
+    #[test]
+    fn test_identical_definitions_share_cid() {
+        let a = build(false);
+        let mut b = build(true);
+        b.set_property("priority".to_string(), serde_json::json!("high"));
+
+        assert_ne!(a.id(), b.id());
+        assert_eq!(
+            a.canonical_definition().to_bytes(),
+            b.canonical_definition().to_bytes()
+        );
+        assert_eq!(a.cid(), b.cid());
+        assert_eq!(a.content_hash(), a.cid().to_string());
+    }
+
+    #[test]
+    fn test_cid_changes_with_content() {
+        let a = build(false);
+        let mut b = build(false);
+        let step = b
+            .workflow
+            .steps
+            .values_mut()
+            .find(|s| s.name == "Create Draft")
+            .unwrap();
+        step.config
+            .insert("a".to_string(), serde_json::json!("second"));
+        assert_ne!(a.cid(), b.cid());
+    }
+
+    #[test]
+    fn test_canonical_json_and_cid_round_trip() {
+        let graph = build(false);
+        let bytes = graph.canonical_definition().to_bytes();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.contains(r#""config":{"a":"first","b":{"x":[true,null],"y":1}}"#));
+
+        let cid = graph.cid();
+        assert!(cid.verify(&bytes));
+        let parsed: Cid = cid.to_string().parse().unwrap();
+        assert_eq!(parsed, cid);
+        let json = serde_json::to_string(&cid).unwrap();
+        assert_eq!(serde_json::from_str::<Cid>(&json).unwrap(), cid);
+    }
+
+    #[test]
+    fn test_store_deduplicates_and_rebuilds() {
+        let mut store = DefinitionStore::new();
+        let cid = store.put(&build(false));
+        assert_eq!(store.put(&build(true)), cid);
+        assert_eq!(store.len(), 1);
+
+        let rebuilt = store.get(&cid).unwrap().to_graph().unwrap();
+        assert_eq!(rebuilt.cid(), cid);
+        assert!(rebuilt.validate().is_ok());
+    }
+
+    #[test]
+    fn test_identical_steps_ordered_by_their_dependencies() {
+        // Two identical "Sign" steps, one after "Legal" and one after "Finance"
+        let build = |legal_first: bool| {
+            let mut graph = WorkflowGraph::new("Contract".to_string(), String::new()).unwrap();
+            let mut add = |name: &str, dependencies: Vec<StepId>| {
+                graph
+                    .add_step(
+                        name.to_string(),
+                        String::new(),
+                        StepType::Manual,
+                        HashMap::new(),
+                        dependencies,
+                        None,
+                        None,
+                    )
+                    .unwrap()
+            };
+            let (legal, finance) = if legal_first {
+                let legal = add("Legal", vec![]);
+                (legal, add("Finance", vec![]))
+            } else {
+                let finance = add("Finance", vec![]);
+                (add("Legal", vec![]), finance)
+            };
+            if legal_first {
+                add("Sign", vec![legal]);
+                add("Sign", vec![finance]);
+            } else {
+                add("Sign", vec![finance]);
+                add("Sign", vec![legal]);
+            }
+            graph
+        };
+
+        let expected = build(true).canonical_definition().to_bytes();
+        for legal_first in [true, false, true, false, true, false] {
+            assert_eq!(
+                build(legal_first).canonical_definition().to_bytes(),
+                expected
+            );
+        }
+        let definition = build(false).canonical_definition();
+        let signs: Vec<&Vec<usize>> = definition
+            .steps
+            .iter()
+            .filter(|step| step.name == "Sign")
+            .map(|step| &step.dependencies)
+            .collect();
+        assert_eq!(signs.len(), 2);
+        assert_ne!(signs[0], signs[1]);
+    }
+
+    #[test]
+    fn test_identical_steps_ordered_by_their_dependents() {
+        // Two identical "Collect" steps, one followed by "Audit", one by "Report"
+        let build = |order: [usize; 2]| {
+            let mut graph = WorkflowGraph::new("Reporting".to_string(), String::new()).unwrap();
+            let mut add = |name: &str, dependencies: Vec<StepId>| {
+                graph
+                    .add_step(
+                        name.to_string(),
+                        String::new(),
+                        StepType::Automated,
+                        HashMap::new(),
+                        dependencies,
+                        None,
+                        None,
+                    )
+                    .unwrap()
+            };
+            let collect = [add("Collect", vec![]), add("Collect", vec![])];
+            add("Audit", vec![collect[order[0]]]);
+            add("Report", vec![collect[order[1]]]);
+            graph
+        };
+
+        let expected = build([0, 1]);
+        for order in [[1, 0], [0, 1], [1, 0], [0, 1], [1, 0], [0, 1]] {
+            let graph = build(order);
+            assert_eq!(
+                graph.canonical_definition().to_bytes(),
+                expected.canonical_definition().to_bytes()
+            );
+            assert_eq!(graph.cid(), expected.cid());
+        }
+
+        let definition = expected.canonical_definition();
+        let dependents: Vec<usize> = definition
+            .steps
+            .iter()
+            .filter(|step| step.name != "Collect")
+            .flat_map(|step| step.dependencies.clone())
+            .collect();
+        assert_eq!(dependents.len(), 2);
+        assert_ne!(dependents[0], dependents[1]);
+    }
+}
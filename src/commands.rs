@@ -0,0 +1,802 @@
+//! Routing of workflow commands to handlers
+//!
+//! Typed [`WorkflowCommand`]s travel in a [`CommandEnvelope`] carrying a
+//! correlation ID and the [`Actor`] issuing them. The
+//! [`WorkflowCommandRouter`] hands each command to the handler registered
+//! for its type, which loads the workflow graph from the router's
+//! repository, changes it as the actor and saves it back. A command the
+//! authorization policy denies is saved too, so its audit entry is kept.
+//! Commands without a handler go to the fallback handler, if one is set.
+
+use crate::authorization::Actor;
+use crate::repository::{InMemoryWorkflowRepository, WorkflowRepository, NEW_REVISION};
+use crate::{WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepType, WorkflowId};
+use cim_domain_workflow::WorkflowDomainEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Command type of [`WorkflowCommand::CreateWorkflow`]
+pub const CREATE_WORKFLOW: &str = "create_workflow";
+/// Command type of [`WorkflowCommand::AddStep`]
+pub const ADD_STEP: &str = "add_step";
+/// Command type of [`WorkflowCommand::StartWorkflow`]
+pub const START_WORKFLOW: &str = "start_workflow";
+/// Command type of [`WorkflowCommand::CompleteStep`]
+pub const COMPLETE_STEP: &str = "complete_step";
+/// Command type of [`WorkflowCommand::CancelWorkflow`]
+pub const CANCEL_WORKFLOW: &str = "cancel_workflow";
+
+/// Commands that change workflow graphs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WorkflowCommand {
+    CreateWorkflow {
+        name: String,
+        description: String,
+    },
+    AddStep {
+        workflow_id: WorkflowId,
+        name: String,
+        description: String,
+        step_type: StepType,
+        config: HashMap<String, serde_json::Value>,
+        dependencies: Vec<StepId>,
+        estimated_duration_minutes: Option<u32>,
+        assigned_to: Option<String>,
+    },
+    StartWorkflow {
+        workflow_id: WorkflowId,
+        context: HashMap<String, serde_json::Value>,
+    },
+    CompleteStep {
+        workflow_id: WorkflowId,
+        step_id: StepId,
+        data: HashMap<String, serde_json::Value>,
+    },
+    CancelWorkflow {
+        workflow_id: WorkflowId,
+        reason: String,
+    },
+    /// A command of a type this library does not define
+    Other {
+        command_type: String,
+        payload: serde_json::Value,
+    },
+}
+
+impl WorkflowCommand {
+    /// The command type handlers are registered for
+    pub fn command_type(&self) -> &str {
+        match self {
+            WorkflowCommand::CreateWorkflow { .. } => CREATE_WORKFLOW,
+            WorkflowCommand::AddStep { .. } => ADD_STEP,
+            WorkflowCommand::StartWorkflow { .. } => START_WORKFLOW,
+            WorkflowCommand::CompleteStep { .. } => COMPLETE_STEP,
+            WorkflowCommand::CancelWorkflow { .. } => CANCEL_WORKFLOW,
+            WorkflowCommand::Other { command_type, .. } => command_type,
+        }
+    }
+
+    /// The workflow the command targets, if it exists yet
+    pub fn workflow_id(&self) -> Option<WorkflowId> {
+        match self {
+            WorkflowCommand::AddStep { workflow_id, .. }
+            | WorkflowCommand::StartWorkflow { workflow_id, .. }
+            | WorkflowCommand::CompleteStep { workflow_id, .. }
+            | WorkflowCommand::CancelWorkflow { workflow_id, .. } => Some(*workflow_id),
+            WorkflowCommand::CreateWorkflow { .. } | WorkflowCommand::Other { .. } => None,
+        }
+    }
+}
+
+/// Identifier tying a command to the replies and events it causes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CorrelationId(Uuid);
+
+impl CorrelationId {
+    /// Create a new random correlation ID
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Get the underlying UUID
+    pub fn as_uuid(&self) -> &Uuid {
+        &self.0
+    }
+}
+
+impl Default for CorrelationId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A command with its correlation ID and the actor issuing it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandEnvelope {
+    pub correlation_id: CorrelationId,
+    pub actor: Actor,
+    pub command: WorkflowCommand,
+}
+
+impl CommandEnvelope {
+    /// Wrap a command issued by an actor with a new correlation ID
+    pub fn new(actor: Actor, command: WorkflowCommand) -> Self {
+        Self::correlated(CorrelationId::new(), actor, command)
+    }
+
+    /// Wrap a command issued by an actor with an existing correlation ID
+    pub fn correlated(
+        correlation_id: CorrelationId,
+        actor: Actor,
+        command: WorkflowCommand,
+    ) -> Self {
+        Self {
+            correlation_id,
+            actor,
+            command,
+        }
+    }
+}
+
+/// What a handler did with a command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    /// The workflow the command applied to
+    pub workflow_id: Option<WorkflowId>,
+    /// The step the command created or completed
+    pub step_id: Option<StepId>,
+    /// Domain events the command emitted
+    pub events: Vec<WorkflowDomainEvent>,
+}
+
+/// Reply to a routed command
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandReply {
+    pub correlation_id: CorrelationId,
+    /// Command type of the handler that handled the command
+    pub handled_by: String,
+    pub output: CommandOutput,
+}
+
+/// Handler for one type of workflow command
+pub trait WorkflowCommandHandler: Send + Sync {
+    /// The command type this handler handles
+    fn command_type(&self) -> &str;
+
+    /// Handle a command issued by an actor against the workflows in
+    /// `repository`
+    fn handle(
+        &self,
+        command: &WorkflowCommand,
+        actor: &Actor,
+        repository: &mut dyn WorkflowRepository,
+    ) -> Result<CommandOutput, WorkflowGraphError>;
+}
+
+/// Routing statistics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutingStats {
+    /// Commands routed, including failed ones
+    pub total_routed: usize,
+    pub by_command_type: HashMap<String, usize>,
+    /// Commands handed to the fallback handler
+    pub fallback_count: usize,
+    /// Commands whose handling returned an error
+    pub failed: usize,
+    pub average_routing_time: Duration,
+}
+
+/// Routes workflow commands to registered handlers
+pub struct WorkflowCommandRouter<R: WorkflowRepository = InMemoryWorkflowRepository> {
+    repository: R,
+    handlers: HashMap<String, Box<dyn WorkflowCommandHandler>>,
+    fallback_handler: Option<Box<dyn WorkflowCommandHandler>>,
+    stats: RoutingStats,
+    total_routing_time: Duration,
+}
+
+impl<R: WorkflowRepository> WorkflowCommandRouter<R> {
+    /// Create a router without handlers
+    pub fn new(repository: R) -> Self {
+        Self {
+            repository,
+            handlers: HashMap::new(),
+            fallback_handler: None,
+            stats: RoutingStats::default(),
+            total_routing_time: Duration::ZERO,
+        }
+    }
+
+    /// Create a router with handlers for all built-in commands
+    pub fn with_default_handlers(repository: R) -> Self {
+        let mut router = Self::new(repository);
+        for handler in default_handlers() {
+            router
+                .register_handler(handler)
+                .expect("built-in command types are distinct");
+        }
+        router
+    }
+
+    /// Register a handler for its command type
+    pub fn register_handler(
+        &mut self,
+        handler: Box<dyn WorkflowCommandHandler>,
+    ) -> Result<(), WorkflowGraphError> {
+        let command_type = handler.command_type().to_string();
+        if self.handlers.contains_key(&command_type) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Handler already registered for {command_type}"
+            )));
+        }
+        self.handlers.insert(command_type, handler);
+        Ok(())
+    }
+
+    /// Remove the handler for a command type
+    pub fn remove_handler(
+        &mut self,
+        command_type: &str,
+    ) -> Result<Box<dyn WorkflowCommandHandler>, WorkflowGraphError> {
+        self.handlers
+            .remove(command_type)
+            .ok_or_else(|| WorkflowGraphError::UnknownCommand(command_type.to_string()))
+    }
+
+    /// Set the handler for commands without a registered handler
+    pub fn set_fallback_handler(&mut self, handler: Box<dyn WorkflowCommandHandler>) {
+        self.fallback_handler = Some(handler);
+    }
+
+    /// Whether a handler is registered for a command type
+    pub fn has_handler(&self, command_type: &str) -> bool {
+        self.handlers.contains_key(command_type)
+    }
+
+    /// Route a command to its handler
+    pub fn route(&mut self, envelope: CommandEnvelope) -> Result<CommandReply, WorkflowGraphError> {
+        let start = Instant::now();
+        let command_type = envelope.command.command_type().to_string();
+
+        let result = if let Some(handler) = self.handlers.get(&command_type) {
+            handler
+                .handle(&envelope.command, &envelope.actor, &mut self.repository)
+                .map(|output| (command_type.clone(), output))
+        } else if let Some(fallback) = &self.fallback_handler {
+            self.stats.fallback_count += 1;
+            fallback
+                .handle(&envelope.command, &envelope.actor, &mut self.repository)
+                .map(|output| (fallback.command_type().to_string(), output))
+        } else {
+            Err(WorkflowGraphError::UnknownCommand(command_type.clone()))
+        };
+
+        self.total_routing_time += start.elapsed();
+        self.stats.total_routed += 1;
+        *self.stats.by_command_type.entry(command_type).or_insert(0) += 1;
+        self.stats.average_routing_time = self.total_routing_time / self.stats.total_routed as u32;
+        if result.is_err() {
+            self.stats.failed += 1;
+        }
+
+        let (handled_by, output) = result?;
+        Ok(CommandReply {
+            correlation_id: envelope.correlation_id,
+            handled_by,
+            output,
+        })
+    }
+
+    /// Routing statistics so far
+    pub fn stats(&self) -> &RoutingStats {
+        &self.stats
+    }
+
+    /// The workflows commands operate on
+    pub fn repository(&self) -> &R {
+        &self.repository
+    }
+
+    /// Mutable access to the workflows commands operate on
+    pub fn repository_mut(&mut self) -> &mut R {
+        &mut self.repository
+    }
+}
+
+/// Handlers for all built-in commands
+pub fn default_handlers() -> Vec<Box<dyn WorkflowCommandHandler>> {
+    vec![
+        Box::new(CreateWorkflowHandler),
+        Box::new(AddStepHandler),
+        Box::new(StartWorkflowHandler),
+        Box::new(CompleteStepHandler),
+        Box::new(CancelWorkflowHandler),
+    ]
+}
+
+/// Handles [`WorkflowCommand::CreateWorkflow`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CreateWorkflowHandler;
+
+impl WorkflowCommandHandler for CreateWorkflowHandler {
+    fn command_type(&self) -> &str {
+        CREATE_WORKFLOW
+    }
+
+    fn handle(
+        &self,
+        command: &WorkflowCommand,
+        actor: &Actor,
+        repository: &mut dyn WorkflowRepository,
+    ) -> Result<CommandOutput, WorkflowGraphError> {
+        let WorkflowCommand::CreateWorkflow { name, description } = command else {
+            return Err(mismatched(self, command));
+        };
+        let graph = WorkflowGraph::new_as(actor, name.clone(), description.clone())?;
+        let output = CommandOutput {
+            workflow_id: Some(graph.id()),
+            step_id: None,
+            events: graph.uncommitted_events.clone(),
+        };
+        repository.save(&graph, NEW_REVISION)?;
+        Ok(output)
+    }
+}
+
+/// Handles [`WorkflowCommand::AddStep`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddStepHandler;
+
+impl WorkflowCommandHandler for AddStepHandler {
+    fn command_type(&self) -> &str {
+        ADD_STEP
+    }
+
+    fn handle(
+        &self,
+        command: &WorkflowCommand,
+        actor: &Actor,
+        repository: &mut dyn WorkflowRepository,
+    ) -> Result<CommandOutput, WorkflowGraphError> {
+        let WorkflowCommand::AddStep {
+            workflow_id,
+            name,
+            description,
+            step_type,
+            config,
+            dependencies,
+            estimated_duration_minutes,
+            assigned_to,
+        } = command
+        else {
+            return Err(mismatched(self, command));
+        };
+        update(repository, *workflow_id, |graph| {
+            graph
+                .add_step_as(
+                    actor,
+                    name.clone(),
+                    description.clone(),
+                    step_type.clone(),
+                    config.clone(),
+                    dependencies.clone(),
+                    *estimated_duration_minutes,
+                    assigned_to.clone(),
+                )
+                .map(Some)
+        })
+    }
+}
+
+/// Handles [`WorkflowCommand::StartWorkflow`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StartWorkflowHandler;
+
+impl WorkflowCommandHandler for StartWorkflowHandler {
+    fn command_type(&self) -> &str {
+        START_WORKFLOW
+    }
+
+    fn handle(
+        &self,
+        command: &WorkflowCommand,
+        actor: &Actor,
+        repository: &mut dyn WorkflowRepository,
+    ) -> Result<CommandOutput, WorkflowGraphError> {
+        let WorkflowCommand::StartWorkflow {
+            workflow_id,
+            context,
+        } = command
+        else {
+            return Err(mismatched(self, command));
+        };
+        update(repository, *workflow_id, |graph| {
+            graph.start_as(actor, context.clone()).map(|()| None)
+        })
+    }
+}
+
+/// Handles [`WorkflowCommand::CompleteStep`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompleteStepHandler;
+
+impl WorkflowCommandHandler for CompleteStepHandler {
+    fn command_type(&self) -> &str {
+        COMPLETE_STEP
+    }
+
+    fn handle(
+        &self,
+        command: &WorkflowCommand,
+        actor: &Actor,
+        repository: &mut dyn WorkflowRepository,
+    ) -> Result<CommandOutput, WorkflowGraphError> {
+        let WorkflowCommand::CompleteStep {
+            workflow_id,
+            step_id,
+            data,
+        } = command
+        else {
+            return Err(mismatched(self, command));
+        };
+        update(repository, *workflow_id, |graph| {
+            graph
+                .complete_step_as(actor, *step_id, data.clone())
+                .map(|()| Some(*step_id))
+        })
+    }
+}
+
+/// Handles [`WorkflowCommand::CancelWorkflow`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CancelWorkflowHandler;
+
+impl WorkflowCommandHandler for CancelWorkflowHandler {
+    fn command_type(&self) -> &str {
+        CANCEL_WORKFLOW
+    }
+
+    fn handle(
+        &self,
+        command: &WorkflowCommand,
+        actor: &Actor,
+        repository: &mut dyn WorkflowRepository,
+    ) -> Result<CommandOutput, WorkflowGraphError> {
+        let WorkflowCommand::CancelWorkflow {
+            workflow_id,
+            reason,
+        } = command
+        else {
+            return Err(mismatched(self, command));
+        };
+        update(repository, *workflow_id, |graph| {
+            graph.cancel_as(actor, reason.clone()).map(|()| None)
+        })
+    }
+}
+
+/// Load a graph, apply a change and save it if the change succeeded or was
+/// denied
+fn update(
+    repository: &mut dyn WorkflowRepository,
+    workflow_id: WorkflowId,
+    change: impl FnOnce(&mut WorkflowGraph) -> Result<Option<StepId>, WorkflowGraphError>,
+) -> Result<CommandOutput, WorkflowGraphError> {
+    let (mut graph, revision) = repository.load(workflow_id)?;
+    let emitted_before = graph.uncommitted_events.len();
+    let step_id = match change(&mut graph) {
+        Ok(step_id) => step_id,
+        Err(error @ WorkflowGraphError::Unauthorized { .. }) => {
+            // Keep the audit entry of the denied attempt
+            repository.save(&graph, revision)?;
+            return Err(error);
+        }
+        Err(error) => return Err(error),
+    };
+    let output = CommandOutput {
+        workflow_id: Some(workflow_id),
+        step_id,
+        events: graph.uncommitted_events[emitted_before..].to_vec(),
+    };
+    repository.save(&graph, revision)?;
+    Ok(output)
+}
+
+fn mismatched(
+    handler: &dyn WorkflowCommandHandler,
+    command: &WorkflowCommand,
+) -> WorkflowGraphError {
+    WorkflowGraphError::InvalidOperation(format!(
+        "Handler for {} cannot handle {}",
+        handler.command_type(),
+        command.command_type()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::FileWorkflowRepository;
+    use crate::{AuthorizationPolicy, Grant, Operation, StepConfigSchemaRegistry};
+    use cim_domain_workflow::value_objects::{StepStatus, WorkflowStatus};
+    use std::fs;
+
+    fn create(router: &mut WorkflowCommandRouter) -> WorkflowId {
+        let reply = router
+            .route(CommandEnvelope::new(
+                Actor::system(),
+                WorkflowCommand::CreateWorkflow {
+                    name: "Routed".to_string(),
+                    description: "Built by commands".to_string(),
+                },
+            ))
+            .unwrap();
+        reply.output.workflow_id.unwrap()
+    }
+
+    fn add_step(router: &mut WorkflowCommandRouter, workflow_id: WorkflowId) -> StepId {
+        let reply = router
+            .route(CommandEnvelope::new(
+                Actor::system(),
+                WorkflowCommand::AddStep {
+                    workflow_id,
+                    name: "Review".to_string(),
+                    description: String::new(),
+                    step_type: StepType::Manual,
+                    config: HashMap::new(),
+                    dependencies: Vec::new(),
+                    estimated_duration_minutes: Some(15),
+                    assigned_to: None,
+                },
+            ))
+            .unwrap();
+        reply.output.step_id.unwrap()
+    }
+
+    #[test]
+    fn test_commands_drive_workflow() {
+        let mut router =
+            WorkflowCommandRouter::with_default_handlers(InMemoryWorkflowRepository::new());
+        let workflow_id = create(&mut router);
+        let step_id = add_step(&mut router, workflow_id);
+
+        let correlation_id = CorrelationId::new();
+        router
+            .route(CommandEnvelope::correlated(
+                correlation_id,
+                Actor::system(),
+                WorkflowCommand::StartWorkflow {
+                    workflow_id,
+                    context: HashMap::new(),
+                },
+            ))
+            .unwrap();
+        let reply = router
+            .route(CommandEnvelope::correlated(
+                correlation_id,
+                Actor::new("reviewer"),
+                WorkflowCommand::CompleteStep {
+                    workflow_id,
+                    step_id,
+                    data: HashMap::new(),
+                },
+            ))
+            .unwrap();
+
+        assert_eq!(reply.correlation_id, correlation_id);
+        assert_eq!(reply.handled_by, COMPLETE_STEP);
+        assert!(matches!(
+            reply.output.events.as_slice(),
+            [WorkflowDomainEvent::TaskCompleted(done)] if done.completed_by == "reviewer"
+        ));
+
+        let (graph, _) = router.repository().load(workflow_id).unwrap();
+        assert_eq!(graph.status(), &WorkflowStatus::Running);
+        assert_eq!(graph.workflow.steps[&step_id].status, StepStatus::Completed);
+        assert!(graph.uncommitted_events.is_empty());
+    }
+
+    #[test]
+    fn test_failed_command_leaves_graph_unchanged() {
+        let mut router =
+            WorkflowCommandRouter::with_default_handlers(InMemoryWorkflowRepository::new());
+        let workflow_id = create(&mut router);
+
+        let result = router.route(CommandEnvelope::new(
+            Actor::system(),
+            WorkflowCommand::StartWorkflow {
+                workflow_id,
+                context: HashMap::new(),
+            },
+        ));
+        assert!(result.is_err());
+        assert_eq!(router.stats().failed, 1);
+        let (graph, _) = router.repository().load(workflow_id).unwrap();
+        assert_eq!(graph.status(), &WorkflowStatus::Draft);
+
+        let missing = router.route(CommandEnvelope::new(
+            Actor::system(),
+            WorkflowCommand::CancelWorkflow {
+                workflow_id: WorkflowId::new(),
+                reason: "gone".to_string(),
+            },
+        ));
+        assert!(matches!(
+            missing,
+            Err(WorkflowGraphError::WorkflowNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_commands_run_as_their_actor() {
+        let mut router =
+            WorkflowCommandRouter::with_default_handlers(InMemoryWorkflowRepository::new());
+        let reply = router
+            .route(CommandEnvelope::new(
+                Actor::new("alice"),
+                WorkflowCommand::CreateWorkflow {
+                    name: "Owned".to_string(),
+                    description: String::new(),
+                },
+            ))
+            .unwrap();
+        assert!(matches!(
+            reply.output.events.as_slice(),
+            [WorkflowDomainEvent::WorkflowCreated(created)]
+                if created.created_by.as_deref() == Some("alice")
+        ));
+        let workflow_id = reply.output.workflow_id.unwrap();
+        let (mut graph, revision) = router.repository().load(workflow_id).unwrap();
+        assert_eq!(graph.workflow.created_by.as_deref(), Some("alice"));
+        graph.set_authorization_policy(
+            AuthorizationPolicy::deny_all().allow(Operation::AddStep, Grant::Anyone),
+        );
+        router.repository_mut().save(&graph, revision).unwrap();
+        add_step(&mut router, workflow_id);
+
+        let start = WorkflowCommand::StartWorkflow {
+            workflow_id,
+            context: HashMap::new(),
+        };
+        let denied = router.route(CommandEnvelope::new(Actor::new("mallory"), start));
+        assert!(matches!(
+            denied,
+            Err(WorkflowGraphError::Unauthorized { ref actor, .. }) if actor == "mallory"
+        ));
+
+        // The denied attempt is persisted in the audit log, and nothing else
+        let (graph, revision) = router.repository().load(workflow_id).unwrap();
+        assert_eq!(revision, 4);
+        assert_eq!(graph.status(), &WorkflowStatus::Draft);
+        let last = graph.audit_log().last().unwrap();
+        assert_eq!(
+            (last.actor.as_str(), last.operation, last.allowed),
+            ("mallory", Operation::Start, false)
+        );
+    }
+
+    struct Ignore;
+
+    impl WorkflowCommandHandler for Ignore {
+        fn command_type(&self) -> &str {
+            "ignore"
+        }
+
+        fn handle(
+            &self,
+            _command: &WorkflowCommand,
+            _actor: &Actor,
+            _repository: &mut dyn WorkflowRepository,
+        ) -> Result<CommandOutput, WorkflowGraphError> {
+            Ok(CommandOutput::default())
+        }
+    }
+
+    #[test]
+    fn test_config_schemas_survive_a_stored_round_trip() {
+        let directory =
+            std::env::temp_dir().join(format!("workflow-commands-{}", WorkflowId::new().as_uuid()));
+        let mut router = WorkflowCommandRouter::with_default_handlers(
+            FileWorkflowRepository::open(&directory).unwrap(),
+        );
+        let reply = router
+            .route(CommandEnvelope::new(
+                Actor::system(),
+                WorkflowCommand::CreateWorkflow {
+                    name: "Validated".to_string(),
+                    description: String::new(),
+                },
+            ))
+            .unwrap();
+        let workflow_id = reply.output.workflow_id.unwrap();
+
+        let (mut graph, revision) = router.repository().load(workflow_id).unwrap();
+        let mut registry = StepConfigSchemaRegistry::new();
+        registry
+            .register(
+                &StepType::Manual,
+                serde_json::json!({
+                    "type": "object",
+                    "properties": { "form": { "type": "string" } },
+                    "additionalProperties": false
+                }),
+            )
+            .unwrap();
+        graph.set_schema_registry(registry);
+        router.repository_mut().save(&graph, revision).unwrap();
+
+        let add = |key: &str| {
+            CommandEnvelope::new(
+                Actor::system(),
+                WorkflowCommand::AddStep {
+                    workflow_id,
+                    name: "Fill Form".to_string(),
+                    description: String::new(),
+                    step_type: StepType::Manual,
+                    config: HashMap::from([(key.to_string(), serde_json::json!("expenses"))]),
+                    dependencies: Vec::new(),
+                    estimated_duration_minutes: None,
+                    assigned_to: None,
+                },
+            )
+        };
+        assert!(matches!(
+            router.route(add("frm")),
+            Err(WorkflowGraphError::InvalidConfig { .. })
+        ));
+        router.route(add("form")).unwrap();
+
+        let (graph, _) = router.repository().load(workflow_id).unwrap();
+        assert_eq!(graph.workflow.steps.len(), 1);
+        assert!(graph
+            .schema_registry()
+            .schema_for(&StepType::Manual)
+            .is_some());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_fallback_and_stats() {
+        let mut router =
+            WorkflowCommandRouter::with_default_handlers(InMemoryWorkflowRepository::new());
+        let archive = WorkflowCommand::Other {
+            command_type: "archive_workflow".to_string(),
+            payload: serde_json::json!({}),
+        };
+
+        assert!(matches!(
+            router.route(CommandEnvelope::new(Actor::system(), archive.clone())),
+            Err(WorkflowGraphError::UnknownCommand(_))
+        ));
+
+        router.set_fallback_handler(Box::new(Ignore));
+        let reply = router
+            .route(CommandEnvelope::new(Actor::system(), archive))
+            .unwrap();
+        assert_eq!(reply.handled_by, "ignore");
+        create(&mut router);
+
+        let stats = router.stats();
+        assert_eq!(stats.total_routed, 3);
+        assert_eq!(stats.fallback_count, 1);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.by_command_type["archive_workflow"], 2);
+        assert_eq!(stats.by_command_type[CREATE_WORKFLOW], 1);
+
+        assert!(router
+            .register_handler(Box::new(CreateWorkflowHandler))
+            .is_err());
+        router.remove_handler(CREATE_WORKFLOW).unwrap();
+        assert!(!router.has_handler(CREATE_WORKFLOW));
+    }
+}
@@ -0,0 +1,606 @@
+//! Structural diff between workflow definitions
+//!
+//! [`WorkflowGraph::diff`] compares two graphs step by step instead of as raw
+//! JSON. Steps are matched by [`StepId`] first and by name as a fallback, so a
+//! definition that was re-created from scratch still lines up with its
+//! predecessor.
+
+use crate::{WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepType, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+/// Reference to a step on one side of a diff
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepRef {
+    pub id: StepId,
+    pub name: String,
+}
+
+impl StepRef {
+    pub(crate) fn of(step: &WorkflowStep) -> Self {
+        Self {
+            id: step.id,
+            name: step.name.clone(),
+        }
+    }
+}
+
+/// How two steps were paired up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StepMatch {
+    /// Both sides share the same step ID
+    Id,
+    /// The IDs differ but the step names are equal
+    Name,
+}
+
+/// A single field-level change of a step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldChange {
+    Name {
+        from: String,
+        to: String,
+    },
+    Description {
+        from: String,
+        to: String,
+    },
+    StepType {
+        from: StepType,
+        to: StepType,
+    },
+    Config {
+        key: String,
+        from: Option<serde_json::Value>,
+        to: Option<serde_json::Value>,
+    },
+    EstimatedDuration {
+        from: Option<u32>,
+        to: Option<u32>,
+    },
+    AssignedTo {
+        from: Option<String>,
+        to: Option<String>,
+    },
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldChange::Name { from, to } => write!(f, "name: {from:?} -> {to:?}"),
+            FieldChange::Description { from, to } => {
+                write!(f, "description: {from:?} -> {to:?}")
+            }
+            FieldChange::StepType { from, to } => write!(f, "type: {from:?} -> {to:?}"),
+            FieldChange::Config { key, from, to } => {
+                write!(
+                    f,
+                    "config.{key}: {} -> {}",
+                    display_option(from),
+                    display_option(to)
+                )
+            }
+            FieldChange::EstimatedDuration { from, to } => write!(
+                f,
+                "estimate: {} -> {}",
+                display_option(&from.map(|m| format!("{m} min"))),
+                display_option(&to.map(|m| format!("{m} min")))
+            ),
+            FieldChange::AssignedTo { from, to } => write!(
+                f,
+                "assignee: {} -> {}",
+                display_option(from),
+                display_option(to)
+            ),
+        }
+    }
+}
+
+fn display_option<T: fmt::Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+/// A step present on both sides with different content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepChange {
+    pub before: StepRef,
+    pub after: StepRef,
+    pub matched_by: StepMatch,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A dependency edge, `step` depending on `depends_on`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependencyChange {
+    pub step: StepRef,
+    pub depends_on: StepRef,
+}
+
+/// A metadata property change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyChange {
+    pub key: String,
+    pub from: Option<serde_json::Value>,
+    pub to: Option<serde_json::Value>,
+}
+
+/// Structural differences between two workflow graphs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowDiff {
+    pub added_steps: Vec<StepRef>,
+    pub removed_steps: Vec<StepRef>,
+    pub modified_steps: Vec<StepChange>,
+    pub added_dependencies: Vec<DependencyChange>,
+    pub removed_dependencies: Vec<DependencyChange>,
+    pub added_tags: Vec<String>,
+    pub removed_tags: Vec<String>,
+    pub property_changes: Vec<PropertyChange>,
+}
+
+impl WorkflowDiff {
+    /// Whether the two graphs are structurally identical
+    pub fn is_empty(&self) -> bool {
+        self.added_steps.is_empty()
+            && self.removed_steps.is_empty()
+            && self.modified_steps.is_empty()
+            && self.added_dependencies.is_empty()
+            && self.removed_dependencies.is_empty()
+            && self.added_tags.is_empty()
+            && self.removed_tags.is_empty()
+            && self.property_changes.is_empty()
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, WorkflowGraphError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))
+    }
+}
+
+impl fmt::Display for WorkflowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for step in &self.added_steps {
+            writeln!(f, "+ step {}", step.name)?;
+        }
+        for step in &self.removed_steps {
+            writeln!(f, "- step {}", step.name)?;
+        }
+        for change in &self.modified_steps {
+            if change.before.name == change.after.name {
+                writeln!(f, "~ step {}", change.after.name)?;
+            } else {
+                writeln!(f, "~ step {} -> {}", change.before.name, change.after.name)?;
+            }
+            for field in &change.changes {
+                writeln!(f, "    {field}")?;
+            }
+        }
+        for dep in &self.added_dependencies {
+            writeln!(f, "+ {} -> {}", dep.depends_on.name, dep.step.name)?;
+        }
+        for dep in &self.removed_dependencies {
+            writeln!(f, "- {} -> {}", dep.depends_on.name, dep.step.name)?;
+        }
+        for tag in &self.added_tags {
+            writeln!(f, "+ tag {tag}")?;
+        }
+        for tag in &self.removed_tags {
+            writeln!(f, "- tag {tag}")?;
+        }
+        for property in &self.property_changes {
+            writeln!(
+                f,
+                "~ property {}: {} -> {}",
+                property.key,
+                display_option(&property.from),
+                display_option(&property.to)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl WorkflowGraph {
+    /// Compute the structural changes needed to turn `self` into `other`
+    pub fn diff(&self, other: &WorkflowGraph) -> WorkflowDiff {
+        let pairs = match_steps(self, other);
+        let mut diff = WorkflowDiff::default();
+
+        let matched_after: HashSet<StepId> = pairs.values().map(|(id, _)| *id).collect();
+        for step in sorted_steps(self) {
+            match pairs.get(&step.id) {
+                Some((after_id, matched_by)) => {
+                    let after = &other.workflow.steps[after_id];
+                    let changes = field_changes(step, after);
+                    if !changes.is_empty() {
+                        diff.modified_steps.push(StepChange {
+                            before: StepRef::of(step),
+                            after: StepRef::of(after),
+                            matched_by: *matched_by,
+                            changes,
+                        });
+                    }
+                }
+                None => diff.removed_steps.push(StepRef::of(step)),
+            }
+        }
+        for step in sorted_steps(other) {
+            if !matched_after.contains(&step.id) {
+                diff.added_steps.push(StepRef::of(step));
+            }
+        }
+
+        // Every edge without a counterpart on the other side is reported,
+        // including the dependencies of added and removed steps
+        let edges = |graph: &WorkflowGraph| -> HashSet<(StepId, StepId)> {
+            graph
+                .workflow
+                .steps
+                .values()
+                .flat_map(|step| step.dependencies.iter().map(move |dep| (step.id, *dep)))
+                .filter(|(_, dep)| graph.workflow.steps.contains_key(dep))
+                .collect()
+        };
+        let (before_edges, after_edges) = (edges(self), edges(other));
+        let forward: HashMap<StepId, StepId> = pairs
+            .iter()
+            .map(|(before, (after, _))| (*before, *after))
+            .collect();
+        let reverse: HashMap<StepId, StepId> = pairs
+            .iter()
+            .map(|(before, (after, _))| (*after, *before))
+            .collect();
+
+        for (step, dep) in &after_edges {
+            let existed =
+                reverse
+                    .get(step)
+                    .zip(reverse.get(dep))
+                    .is_some_and(|(before_step, before_dep)| {
+                        before_edges.contains(&(*before_step, *before_dep))
+                    });
+            if !existed {
+                diff.added_dependencies.push(dependency(other, *step, *dep));
+            }
+        }
+        for (step, dep) in &before_edges {
+            let kept =
+                forward
+                    .get(step)
+                    .zip(forward.get(dep))
+                    .is_some_and(|(after_step, after_dep)| {
+                        after_edges.contains(&(*after_step, *after_dep))
+                    });
+            if !kept {
+                diff.removed_dependencies
+                    .push(dependency(self, *step, *dep));
+            }
+        }
+        sort_dependencies(&mut diff.added_dependencies);
+        sort_dependencies(&mut diff.removed_dependencies);
+
+        diff.added_tags = other
+            .metadata
+            .tags
+            .iter()
+            .filter(|tag| !self.metadata.tags.contains(tag))
+            .cloned()
+            .collect();
+        diff.removed_tags = self
+            .metadata
+            .tags
+            .iter()
+            .filter(|tag| !other.metadata.tags.contains(tag))
+            .cloned()
+            .collect();
+
+        let keys: BTreeSet<&String> = self
+            .metadata
+            .properties
+            .keys()
+            .chain(other.metadata.properties.keys())
+            .collect();
+        for key in keys {
+            let from = self.metadata.properties.get(key);
+            let to = other.metadata.properties.get(key);
+            if from != to {
+                diff.property_changes.push(PropertyChange {
+                    key: key.clone(),
+                    from: from.cloned(),
+                    to: to.cloned(),
+                });
+            }
+        }
+
+        diff
+    }
+}
+
+/// Pair up steps of `before` with steps of `after`, keyed by the `before` ID
+pub(crate) fn match_steps(
+    before: &WorkflowGraph,
+    after: &WorkflowGraph,
+) -> HashMap<StepId, (StepId, StepMatch)> {
+    let mut pairs = HashMap::new();
+
+    for id in before.workflow.steps.keys() {
+        if after.workflow.steps.contains_key(id) {
+            pairs.insert(*id, (*id, StepMatch::Id));
+        }
+    }
+
+    let mut unmatched_after: Vec<&WorkflowStep> = sorted_steps(after)
+        .into_iter()
+        .filter(|step| !before.workflow.steps.contains_key(&step.id))
+        .collect();
+    for step in sorted_steps(before) {
+        if pairs.contains_key(&step.id) {
+            continue;
+        }
+        if let Some(index) = unmatched_after.iter().position(|s| s.name == step.name) {
+            let other = unmatched_after.remove(index);
+            pairs.insert(step.id, (other.id, StepMatch::Name));
+        }
+    }
+
+    pairs
+}
+
+/// Steps in a stable order: by name, then by ID
+pub(crate) fn sorted_steps(graph: &WorkflowGraph) -> Vec<&WorkflowStep> {
+    let mut steps: Vec<&WorkflowStep> = graph.workflow.steps.values().collect();
+    steps.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| a.id.as_uuid().cmp(b.id.as_uuid()))
+    });
+    steps
+}
+
+/// Field-level changes between two versions of a step
+pub(crate) fn field_changes(before: &WorkflowStep, after: &WorkflowStep) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+
+    if before.name != after.name {
+        changes.push(FieldChange::Name {
+            from: before.name.clone(),
+            to: after.name.clone(),
+        });
+    }
+    if before.description != after.description {
+        changes.push(FieldChange::Description {
+            from: before.description.clone(),
+            to: after.description.clone(),
+        });
+    }
+    if before.step_type != after.step_type {
+        changes.push(FieldChange::StepType {
+            from: before.step_type.clone(),
+            to: after.step_type.clone(),
+        });
+    }
+
+    let keys: BTreeSet<&String> = before.config.keys().chain(after.config.keys()).collect();
+    for key in keys {
+        let from = before.config.get(key);
+        let to = after.config.get(key);
+        if from != to {
+            changes.push(FieldChange::Config {
+                key: key.clone(),
+                from: from.cloned(),
+                to: to.cloned(),
+            });
+        }
+    }
+
+    if before.estimated_duration_minutes != after.estimated_duration_minutes {
+        changes.push(FieldChange::EstimatedDuration {
+            from: before.estimated_duration_minutes,
+            to: after.estimated_duration_minutes,
+        });
+    }
+    if before.assigned_to != after.assigned_to {
+        changes.push(FieldChange::AssignedTo {
+            from: before.assigned_to.clone(),
+            to: after.assigned_to.clone(),
+        });
+    }
+
+    changes
+}
+
+fn dependency(graph: &WorkflowGraph, step: StepId, depends_on: StepId) -> DependencyChange {
+    DependencyChange {
+        step: StepRef::of(&graph.workflow.steps[&step]),
+        depends_on: StepRef::of(&graph.workflow.steps[&depends_on]),
+    }
+}
+
+fn sort_dependencies(dependencies: &mut [DependencyChange]) {
+    dependencies.sort_by(|a, b| {
+        (&a.step.name, &a.depends_on.name).cmp(&(&b.step.name, &b.depends_on.name))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn base_graph() -> (WorkflowGraph, StepId, StepId) {
+        let mut graph =
+            WorkflowGraph::new("Diff Test".to_string(), "Testing diffs".to_string()).unwrap();
+        let draft = graph
+            .add_step(
+                "Create Draft".to_string(),
+                "Write the draft".to_string(),
+                StepType::Manual,
+                HashMap::from([("template".to_string(), serde_json::json!("standard_doc"))]),
+                Vec::new(),
+                Some(120),
+                Some("content-author".to_string()),
+            )
+            .unwrap();
+        let review = graph
+            .add_step(
+                "Review".to_string(),
+                "Review the draft".to_string(),
+                StepType::Manual,
+                HashMap::new(),
+                vec![draft],
+                Some(60),
+                Some("tech-reviewer".to_string()),
+            )
+            .unwrap();
+        graph.add_tag("approval".to_string());
+        (graph, draft, review)
+    }
+
+    #[test]
+    fn test_identical_graphs_have_empty_diff() {
+        let (graph, _, _) = base_graph();
+        let diff = graph.diff(&graph.clone());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "No changes\n");
+    }
+
+    #[test]
+    fn test_field_and_metadata_changes() {
+        let (before, draft, review) = base_graph();
+        let mut after = before.clone();
+
+        let step = after.workflow.steps.get_mut(&review).unwrap();
+        step.estimated_duration_minutes = Some(90);
+        step.assigned_to = Some("editor".to_string());
+        step.config
+            .insert("required_score".to_string(), serde_json::json!(8));
+        after.add_tag("document".to_string());
+        after.set_property("priority".to_string(), serde_json::json!("high"));
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.modified_steps.len(), 1);
+        let change = &diff.modified_steps[0];
+        assert_eq!(change.matched_by, StepMatch::Id);
+        assert_eq!(change.after.id, review);
+        assert_eq!(change.changes.len(), 3);
+        assert_eq!(diff.added_tags, vec!["document".to_string()]);
+        assert_eq!(diff.property_changes[0].key, "priority");
+        assert!(diff.added_dependencies.is_empty());
+        assert!(diff.modified_steps.iter().all(|c| c.before.id != draft));
+
+        let text = diff.to_string();
+        assert!(text.contains("~ step Review"));
+        assert!(text.contains("estimate: 60 min -> 90 min"));
+        assert!(text.contains("+ tag document"));
+
+        let json: serde_json::Value = serde_json::from_str(&diff.to_json().unwrap()).unwrap();
+        assert_eq!(json["modified_steps"][0]["matched_by"], "Id");
+    }
+
+    #[test]
+    fn test_matches_by_name_when_ids_differ() {
+        let (before, _, _) = base_graph();
+
+        // Recreate the same definition from scratch plus one extra step
+        let (mut after, _, review) = base_graph();
+        after
+            .add_step(
+                "Publish".to_string(),
+                "Publish it".to_string(),
+                StepType::Automated,
+                HashMap::new(),
+                vec![review],
+                Some(5),
+                None,
+            )
+            .unwrap();
+
+        let diff = before.diff(&after);
+        assert!(diff.removed_steps.is_empty());
+        assert!(diff.modified_steps.is_empty());
+        assert_eq!(diff.added_steps.len(), 1);
+        assert_eq!(diff.added_steps[0].name, "Publish");
+        assert_eq!(diff.added_dependencies.len(), 1);
+        assert_eq!(diff.added_dependencies[0].step.name, "Publish");
+        assert_eq!(diff.added_dependencies[0].depends_on.id, review);
+        assert!(diff.removed_dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_dependency_changes() {
+        let (before, draft, review) = base_graph();
+        let mut after = before.clone();
+        after
+            .workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .dependencies
+            .clear();
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.removed_dependencies.len(), 1);
+        assert_eq!(diff.removed_dependencies[0].step.id, review);
+        assert_eq!(diff.removed_dependencies[0].depends_on.id, draft);
+        assert!(diff.to_string().contains("- Create Draft -> Review"));
+
+        let reverse = after.diff(&before);
+        assert_eq!(reverse.added_dependencies.len(), 1);
+    }
+
+    #[test]
+    fn test_step_inserted_into_chain() {
+        let (before, draft, review) = base_graph();
+        let mut after = before.clone();
+        let lint = after
+            .add_step(
+                "Lint".to_string(),
+                "Check style".to_string(),
+                StepType::Automated,
+                HashMap::new(),
+                vec![draft],
+                Some(1),
+                None,
+            )
+            .unwrap();
+        after.workflow.steps.get_mut(&review).unwrap().dependencies = vec![lint];
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added_steps.len(), 1);
+        let added: Vec<(StepId, StepId)> = diff
+            .added_dependencies
+            .iter()
+            .map(|dep| (dep.step.id, dep.depends_on.id))
+            .collect();
+        assert_eq!(added.len(), 2);
+        assert!(added.contains(&(review, lint)));
+        assert!(added.contains(&(lint, draft)));
+        assert_eq!(diff.removed_dependencies.len(), 1);
+        let text = diff.to_string();
+        assert!(text.contains("+ Lint -> Review"));
+        assert!(text.contains("+ Create Draft -> Lint"));
+        assert!(text.contains("- Create Draft -> Review"));
+
+        // Taking the step out again reports both of its edges as removed
+        let reverse = after.diff(&before);
+        assert_eq!(reverse.removed_steps.len(), 1);
+        let removed: Vec<(StepId, StepId)> = reverse
+            .removed_dependencies
+            .iter()
+            .map(|dep| (dep.step.id, dep.depends_on.id))
+            .collect();
+        assert_eq!(removed.len(), 2);
+        assert!(removed.contains(&(review, lint)));
+        assert!(removed.contains(&(lint, draft)));
+        assert_eq!(reverse.added_dependencies.len(), 1);
+        assert_eq!(reverse.added_dependencies[0].depends_on.id, draft);
+    }
+}
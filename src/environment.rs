@@ -0,0 +1,202 @@
+//! Where a workflow graph gets its IDs and timestamps
+//!
+//! Constructors and mutations read the time from the environment's
+//! [`Clock`] and take new IDs from its [`IdGenerator`]. The
+//! [`WorkflowEnvironment::deterministic`] environment makes the same inputs
+//! produce byte-identical `to_json()` and `to_dot()` output.
+
+use crate::clock::{Clock, FixedClock, SystemClock};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A source of new IDs
+pub trait IdGenerator: Debug + Send + Sync {
+    fn next_uuid(&self) -> Uuid;
+}
+
+/// Random version 4 UUIDs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn next_uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// UUIDs counting up from a start value
+///
+/// Clones share the counter.
+#[derive(Debug, Clone, Default)]
+pub struct SequentialIdGenerator {
+    next: Arc<AtomicU64>,
+}
+
+impl SequentialIdGenerator {
+    /// Count up from one
+    pub fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    /// Count up from a value
+    pub fn starting_at(first: u64) -> Self {
+        Self {
+            next: Arc::new(AtomicU64::new(first)),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_uuid(&self) -> Uuid {
+        Uuid::from_u128(u128::from(self.next.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+/// The clock and ID generator a workflow graph uses
+#[derive(Debug, Clone)]
+pub struct WorkflowEnvironment {
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGenerator>,
+}
+
+impl WorkflowEnvironment {
+    /// Use a clock and an ID generator
+    pub fn new(clock: Arc<dyn Clock>, ids: Arc<dyn IdGenerator>) -> Self {
+        Self { clock, ids }
+    }
+
+    /// The system clock and random IDs
+    pub fn system() -> Self {
+        Self::new(Arc::new(SystemClock), Arc::new(RandomIdGenerator))
+    }
+
+    /// A fixed time and sequential IDs counting up from one
+    pub fn deterministic(now: DateTime<Utc>) -> Self {
+        Self::new(
+            Arc::new(FixedClock(now)),
+            Arc::new(SequentialIdGenerator::new()),
+        )
+    }
+
+    /// The current time
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// A new ID of any type that deserializes from a UUID, such as
+    /// `WorkflowId` or `StepId`
+    pub fn next_id<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_value(serde_json::Value::String(self.ids.next_uuid().to_string()))
+            .expect("domain IDs deserialize from UUIDs")
+    }
+
+    /// The clock
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// The ID generator
+    pub fn id_generator(&self) -> &Arc<dyn IdGenerator> {
+        &self.ids
+    }
+}
+
+impl Default for WorkflowEnvironment {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WorkflowGraph;
+    use cim_domain_workflow::value_objects::{StepId, StepType, WorkflowId};
+    use std::collections::HashMap;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    /// Draft -> Review -> Publish, started and partly done
+    fn build(environment: WorkflowEnvironment) -> WorkflowGraph {
+        let mut graph =
+            WorkflowGraph::new_in("Docs".to_string(), "Publishing".to_string(), environment)
+                .unwrap();
+        let mut previous = Vec::new();
+        for name in ["Draft", "Review", "Publish"] {
+            let id = graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    previous,
+                    Some(30),
+                    None,
+                )
+                .unwrap();
+            previous = vec![id];
+        }
+        graph.start(HashMap::new()).unwrap();
+        graph
+    }
+
+    #[test]
+    fn test_sequential_ids() {
+        let ids = SequentialIdGenerator::starting_at(41);
+        let shared = ids.clone();
+        assert_eq!(ids.next_uuid(), Uuid::from_u128(41));
+        assert_eq!(shared.next_uuid(), Uuid::from_u128(42));
+
+        let environment = WorkflowEnvironment::deterministic(utc("2025-01-01T00:00:00Z"));
+        let workflow_id: WorkflowId = environment.next_id();
+        let step_id: StepId = environment.next_id();
+        assert_eq!(*workflow_id.as_uuid(), Uuid::from_u128(1));
+        assert_eq!(*step_id.as_uuid(), Uuid::from_u128(2));
+    }
+
+    #[test]
+    fn test_graph_takes_ids_and_times_from_environment() {
+        let now = utc("2025-01-01T00:00:00Z");
+        let graph = build(WorkflowEnvironment::deterministic(now));
+
+        assert_eq!(*graph.id().as_uuid(), Uuid::from_u128(1));
+        assert_eq!(graph.workflow.created_at, now);
+        // The workflow takes ID 1 and each dependency edge takes the ID after its step
+        let mut step_ids: Vec<u128> = graph
+            .workflow
+            .steps
+            .keys()
+            .map(|id| id.as_uuid().as_u128())
+            .collect();
+        step_ids.sort();
+        assert_eq!(step_ids, [2, 3, 5]);
+        assert!(graph
+            .uncommitted_events
+            .iter()
+            .all(|event| serde_json::to_string(event)
+                .unwrap()
+                .contains("2025-01-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_deterministic_exports_are_byte_identical() {
+        let now = utc("2025-01-01T00:00:00Z");
+        let first = build(WorkflowEnvironment::deterministic(now));
+        let second = build(WorkflowEnvironment::deterministic(now));
+
+        assert_eq!(first.to_json().unwrap(), second.to_json().unwrap());
+        assert_eq!(first.to_dot(), second.to_dot());
+        assert_eq!(first.metadata.content_hash, second.metadata.content_hash);
+
+        let later = build(WorkflowEnvironment::deterministic(utc(
+            "2025-06-01T00:00:00Z",
+        )));
+        assert_ne!(first.to_json().unwrap(), later.to_json().unwrap());
+    }
+}
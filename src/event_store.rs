@@ -0,0 +1,578 @@
+//! CID-chained event log for workflow domain events
+//!
+//! Every persisted [`WorkflowDomainEvent`] is wrapped in an [`EventEnvelope`]
+//! that records its position in the log, the workflow it belongs to and the
+//! CID of the previous envelope. The envelope's own CID covers all of that,
+//! so changing, removing or reordering any event breaks the chain from that
+//! point on, and [`verify_chain`] reports exactly where.
+//!
+//! The log holds the domain's events only, so it is the source of truth for
+//! the workflow aggregate but not for the graph around it. Tags, metadata
+//! properties such as `template_id`, the definition version, the schema
+//! registry, the authorization policy and the audit log are not evented and
+//! are lost on [`EventStore::replay`]; keep them in a
+//! [`WorkflowRepository`](crate::WorkflowRepository) alongside the log.
+//!
+//! Changes with no domain event are not in the log either: replaying it
+//! brings back dependencies removed by
+//! [`WorkflowGraph::transitive_reduction`] and assignees replaced by SLA
+//! escalations. The domain has no events for removing steps or replacing a
+//! definition, so a log cannot record [`WorkflowGraph::migrate_to`]. Graphs saved to or
+//! rebuilt from a log are marked as event sourced, and migrating them is
+//! rejected rather than leaving a log that replays the old definition.
+
+use crate::canonical::{canonical_json, Cid};
+use crate::{created_workflow, WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Utc};
+use cim_domain_workflow::value_objects::WorkflowId;
+use cim_domain_workflow::WorkflowDomainEvent;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// A persisted domain event and its place in the chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    /// Position in the log, starting at 0
+    pub sequence: u64,
+    pub workflow_id: WorkflowId,
+    pub recorded_at: DateTime<Utc>,
+    pub event: WorkflowDomainEvent,
+    /// CID of the envelope before this one, `None` for the first
+    pub previous_cid: Option<Cid>,
+    /// CID of this envelope's contents, including `previous_cid`
+    pub cid: Cid,
+}
+
+impl EventEnvelope {
+    /// Wrap an event recorded at a moment, chaining it to `previous`
+    pub fn new(
+        workflow_id: WorkflowId,
+        event: WorkflowDomainEvent,
+        previous: Option<&EventEnvelope>,
+        recorded_at: DateTime<Utc>,
+    ) -> Self {
+        let mut envelope = Self {
+            sequence: previous.map_or(0, |p| p.sequence + 1),
+            workflow_id,
+            recorded_at,
+            event,
+            previous_cid: previous.map(|p| p.cid),
+            cid: Cid::for_bytes(&[]),
+        };
+        envelope.cid = envelope.compute_cid();
+        envelope
+    }
+
+    /// Recompute the CID from the envelope's contents
+    pub fn compute_cid(&self) -> Cid {
+        let content = serde_json::json!({
+            "sequence": self.sequence,
+            "workflow_id": self.workflow_id,
+            "recorded_at": self.recorded_at,
+            "event": self.event,
+            "previous_cid": self.previous_cid,
+        });
+        Cid::for_bytes(canonical_json(&content).as_bytes())
+    }
+}
+
+/// Where and how an event chain is broken
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainBreak {
+    /// Sequence number at which the chain stops verifying
+    pub sequence: u64,
+    pub kind: ChainBreakKind,
+}
+
+/// The ways an event chain can be broken
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainBreakKind {
+    /// The envelope's contents no longer match its CID
+    Tampered { recorded: Cid, computed: Cid },
+    /// One or more events are missing before this position
+    Missing { found_sequence: u64 },
+    /// The envelope does not point at the envelope before it
+    BrokenLink {
+        expected: Option<Cid>,
+        found: Option<Cid>,
+    },
+}
+
+impl fmt::Display for ChainBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ChainBreakKind::Tampered { recorded, computed } => write!(
+                f,
+                "event {} was modified (recorded {recorded}, computed {computed})",
+                self.sequence
+            ),
+            ChainBreakKind::Missing { found_sequence } => write!(
+                f,
+                "event {} is missing (found event {found_sequence} in its place)",
+                self.sequence
+            ),
+            ChainBreakKind::BrokenLink { expected, found } => write!(
+                f,
+                "event {} links to {} instead of {}",
+                self.sequence,
+                found.map_or("nothing".to_string(), |c| c.to_string()),
+                expected.map_or("nothing".to_string(), |c| c.to_string())
+            ),
+        }
+    }
+}
+
+/// Result of a successful chain verification
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainSummary {
+    /// Number of verified events
+    pub length: u64,
+    /// CID of the last event, `None` for an empty log
+    pub head: Option<Cid>,
+}
+
+/// Verify an ordered event log, reporting the first break
+pub fn verify_chain(envelopes: &[EventEnvelope]) -> Result<ChainSummary, WorkflowGraphError> {
+    let head = verify_links(envelopes, 0, None)?;
+    Ok(ChainSummary {
+        length: envelopes.len() as u64,
+        head,
+    })
+}
+
+/// Verify the part of a log after a trusted event, given that event's
+/// sequence and CID
+///
+/// Only the events after `sequence` are hashed, so checking the tail behind a
+/// snapshot does not cost a pass over the whole log.
+pub fn verify_chain_after(
+    envelopes: &[EventEnvelope],
+    sequence: u64,
+    cid: Cid,
+) -> Result<ChainSummary, WorkflowGraphError> {
+    let start = sequence + 1;
+    let tail = envelopes.get(start as usize..).unwrap_or_default();
+    let head = verify_links(tail, start, Some(cid))?;
+    Ok(ChainSummary {
+        length: envelopes.len() as u64,
+        head: head.or(Some(cid)),
+    })
+}
+
+/// Check sequences, links and CIDs of envelopes starting at `start` and
+/// chained to `head`, returning the CID of the last one
+fn verify_links(
+    envelopes: &[EventEnvelope],
+    start: u64,
+    mut head: Option<Cid>,
+) -> Result<Option<Cid>, WorkflowGraphError> {
+    for (offset, envelope) in envelopes.iter().enumerate() {
+        let sequence = start + offset as u64;
+        let broken = |kind| WorkflowGraphError::ChainBroken(ChainBreak { sequence, kind });
+
+        if envelope.sequence != sequence {
+            return Err(broken(ChainBreakKind::Missing {
+                found_sequence: envelope.sequence,
+            }));
+        }
+        if envelope.previous_cid != head {
+            return Err(broken(ChainBreakKind::BrokenLink {
+                expected: head,
+                found: envelope.previous_cid,
+            }));
+        }
+        let computed = envelope.compute_cid();
+        if computed != envelope.cid {
+            return Err(broken(ChainBreakKind::Tampered {
+                recorded: envelope.cid,
+                computed,
+            }));
+        }
+        head = Some(envelope.cid);
+    }
+    Ok(head)
+}
+
+/// Append-only storage for chained workflow events
+pub trait EventStore {
+    /// Append an event to the log
+    fn append(
+        &mut self,
+        workflow_id: WorkflowId,
+        event: WorkflowDomainEvent,
+        recorded_at: DateTime<Utc>,
+    ) -> Result<EventEnvelope, WorkflowGraphError>;
+
+    /// All events in log order
+    fn events(&self) -> Result<Vec<EventEnvelope>, WorkflowGraphError>;
+
+    /// Append several events for one workflow
+    fn append_all(
+        &mut self,
+        workflow_id: WorkflowId,
+        events: Vec<WorkflowDomainEvent>,
+        recorded_at: DateTime<Utc>,
+    ) -> Result<Vec<EventEnvelope>, WorkflowGraphError> {
+        events
+            .into_iter()
+            .map(|event| self.append(workflow_id, event, recorded_at))
+            .collect()
+    }
+
+    /// Persist a graph's uncommitted events, recorded at its environment's
+    /// time, and mark the graph as event sourced
+    fn save(
+        &mut self,
+        graph: &mut WorkflowGraph,
+    ) -> Result<Vec<EventEnvelope>, WorkflowGraphError> {
+        let workflow_id = graph.id();
+        let recorded_at = graph.environment().now();
+        graph.metadata.event_sourced = true;
+        let events = graph.take_uncommitted_events();
+        self.append_all(workflow_id, events, recorded_at)
+    }
+
+    /// Events of one workflow in log order
+    fn events_for(
+        &self,
+        workflow_id: WorkflowId,
+    ) -> Result<Vec<EventEnvelope>, WorkflowGraphError> {
+        Ok(self
+            .events()?
+            .into_iter()
+            .filter(|envelope| envelope.workflow_id == workflow_id)
+            .collect())
+    }
+
+    /// Verify the whole log
+    fn verify_chain(&self) -> Result<ChainSummary, WorkflowGraphError> {
+        verify_chain(&self.events()?)
+    }
+
+    /// Rebuild a workflow from its events after verifying the log
+    ///
+    /// Only the workflow aggregate is rebuilt; graph metadata that is not
+    /// evented starts out empty.
+    fn replay(&self, workflow_id: WorkflowId) -> Result<WorkflowGraph, WorkflowGraphError> {
+        let events = self.events()?;
+        verify_chain(&events)?;
+        WorkflowGraph::from_events(
+            events
+                .iter()
+                .filter(|envelope| envelope.workflow_id == workflow_id)
+                .map(|envelope| &envelope.event),
+        )
+    }
+}
+
+/// Event store held in memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryEventStore {
+    envelopes: Vec<EventEnvelope>,
+}
+
+impl InMemoryEventStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventStore for InMemoryEventStore {
+    fn append(
+        &mut self,
+        workflow_id: WorkflowId,
+        event: WorkflowDomainEvent,
+        recorded_at: DateTime<Utc>,
+    ) -> Result<EventEnvelope, WorkflowGraphError> {
+        let envelope = EventEnvelope::new(workflow_id, event, self.envelopes.last(), recorded_at);
+        self.envelopes.push(envelope.clone());
+        Ok(envelope)
+    }
+
+    fn events(&self) -> Result<Vec<EventEnvelope>, WorkflowGraphError> {
+        Ok(self.envelopes.clone())
+    }
+}
+
+/// Event store backed by an append-only file of JSON lines
+///
+/// Events are read back from the file, so changes made to it outside the
+/// store are caught by [`EventStore::verify_chain`].
+#[derive(Debug)]
+pub struct FileEventStore {
+    path: PathBuf,
+    head: Option<EventEnvelope>,
+}
+
+impl FileEventStore {
+    /// Open a log file, creating it if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, WorkflowGraphError> {
+        let path = path.as_ref().to_path_buf();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let mut store = Self { path, head: None };
+        store.head = store.events()?.pop();
+        Ok(store)
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl EventStore for FileEventStore {
+    fn append(
+        &mut self,
+        workflow_id: WorkflowId,
+        event: WorkflowDomainEvent,
+        recorded_at: DateTime<Utc>,
+    ) -> Result<EventEnvelope, WorkflowGraphError> {
+        let envelope = EventEnvelope::new(workflow_id, event, self.head.as_ref(), recorded_at);
+        let line = serde_json::to_string(&envelope)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        writeln!(file, "{line}").map_err(io_error)?;
+        file.sync_data().map_err(io_error)?;
+
+        self.head = Some(envelope.clone());
+        Ok(envelope)
+    }
+
+    fn events(&self) -> Result<Vec<EventEnvelope>, WorkflowGraphError> {
+        let file = File::open(&self.path).map_err(io_error)?;
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+            .map(|(number, line)| {
+                let line = line.map_err(io_error)?;
+                serde_json::from_str(&line).map_err(|e| {
+                    WorkflowGraphError::SerializationError(format!(
+                        "{} line {}: {e}",
+                        self.path.display(),
+                        number + 1
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+fn io_error(error: std::io::Error) -> WorkflowGraphError {
+    WorkflowGraphError::InvalidOperation(format!("Event log I/O failed: {error}"))
+}
+
+impl WorkflowGraph {
+    /// Rebuild a workflow graph from its domain events, oldest first
+    ///
+    /// The first event must be the workflow's creation. The graph is marked
+    /// as event sourced.
+    pub fn from_events<'a>(
+        events: impl IntoIterator<Item = &'a WorkflowDomainEvent>,
+    ) -> Result<Self, WorkflowGraphError> {
+        let mut events = events.into_iter();
+        let Some(WorkflowDomainEvent::WorkflowCreated(created)) = events.next() else {
+            return Err(WorkflowGraphError::InvalidOperation(
+                "Event history must start with WorkflowCreated".to_string(),
+            ));
+        };
+
+        let workflow = created_workflow(created)?;
+        let mut graph = Self::from_workflow(workflow);
+        graph.metadata.event_sourced = true;
+        graph.apply_events(events)?;
+        Ok(graph)
+    }
+
+    /// Apply already persisted domain events to this graph, oldest first
+    pub fn apply_events<'a>(
+        &mut self,
+        events: impl IntoIterator<Item = &'a WorkflowDomainEvent>,
+    ) -> Result<(), WorkflowGraphError> {
+        for event in events {
+            self.workflow
+                .apply_event(event)
+                .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        }
+        self.refresh_context_graph();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SnapshotStore, WorkflowEnvironment};
+    use cim_domain_workflow::value_objects::{StepStatus, StepType};
+    use std::collections::HashMap;
+
+    fn record(store: &mut impl EventStore) -> WorkflowGraph {
+        let mut graph = WorkflowGraph::new("Chained".to_string(), "Event log".to_string()).unwrap();
+        let draft = graph
+            .add_step(
+                "Create Draft".to_string(),
+                "Write".to_string(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                Some(60),
+                None,
+            )
+            .unwrap();
+        graph
+            .add_step(
+                "Review".to_string(),
+                "Review".to_string(),
+                StepType::Approval,
+                HashMap::new(),
+                vec![draft],
+                Some(30),
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        store.save(&mut graph).unwrap();
+        graph
+    }
+
+    #[test]
+    fn test_append_chains_events() {
+        let mut store = InMemoryEventStore::new();
+        let graph = record(&mut store);
+        assert!(graph.uncommitted_events.is_empty());
+
+        let events = store.events().unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(events[0].previous_cid.is_none());
+        assert_eq!(events[1].previous_cid, Some(events[0].cid));
+
+        let summary = store.verify_chain().unwrap();
+        assert_eq!(summary.length, 4);
+        assert_eq!(summary.head, Some(events[3].cid));
+    }
+
+    #[test]
+    fn test_deterministic_environment_gives_identical_logs() {
+        let now: DateTime<Utc> = "2025-03-03T09:00:00Z".parse().unwrap();
+        let run = || {
+            let mut graph = WorkflowGraph::new_in(
+                "Chained".to_string(),
+                "Event log".to_string(),
+                WorkflowEnvironment::deterministic(now),
+            )
+            .unwrap();
+            let draft = graph
+                .add_step(
+                    "Create Draft".to_string(),
+                    "Write".to_string(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    Vec::new(),
+                    Some(60),
+                    None,
+                )
+                .unwrap();
+            graph.start(HashMap::new()).unwrap();
+            let mut store = InMemoryEventStore::new();
+            store.save(&mut graph).unwrap();
+            (graph, draft, store)
+        };
+
+        let (graph, draft, store) = run();
+        let (_, _, again) = run();
+        assert_eq!(store.events().unwrap(), again.events().unwrap());
+        assert!(store.events().unwrap().iter().all(|e| e.recorded_at == now));
+        assert_eq!(graph.workflow.steps[&draft].id, draft);
+        assert_eq!(
+            WorkflowGraph::from_events(store.events().unwrap().iter().map(|e| &e.event))
+                .unwrap()
+                .to_json()
+                .unwrap(),
+            graph.to_json().unwrap()
+        );
+
+        let mut snapshots =
+            SnapshotStore::new(0).with_environment(WorkflowEnvironment::deterministic(now));
+        let snapshot = snapshots.create_snapshot(&store, graph.id()).unwrap();
+        assert_eq!(snapshot.taken_at, now);
+    }
+
+    #[test]
+    fn test_verify_chain_pinpoints_tampering_and_gaps() {
+        let mut store = InMemoryEventStore::new();
+        record(&mut store);
+
+        let mut tampered = store.clone();
+        if let WorkflowDomainEvent::StepAdded(ref mut added) = tampered.envelopes[2].event {
+            added.name = "Rubber Stamp".to_string();
+        }
+        match tampered.verify_chain() {
+            Err(WorkflowGraphError::ChainBroken(ChainBreak {
+                sequence: 2,
+                kind: ChainBreakKind::Tampered { .. },
+            })) => {}
+            other => panic!("unexpected verification result: {other:?}"),
+        }
+
+        let mut gap = store.clone();
+        gap.envelopes.remove(1);
+        match gap.verify_chain() {
+            Err(WorkflowGraphError::ChainBroken(ChainBreak {
+                sequence: 1,
+                kind: ChainBreakKind::Missing { found_sequence: 2 },
+            })) => {}
+            other => panic!("unexpected verification result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_replay_by_workflow_id() {
+        let mut store = InMemoryEventStore::new();
+        let original = record(&mut store);
+        let other = record(&mut store);
+
+        let replayed = store.replay(original.id()).unwrap();
+        assert_eq!(replayed.id(), original.id());
+        assert_ne!(replayed.id(), other.id());
+        assert_eq!(replayed.workflow.steps.len(), 2);
+        assert_eq!(replayed.status(), original.status());
+        assert_eq!(replayed.cid(), original.cid());
+        assert_eq!(
+            replayed.find_steps_by_status(StepStatus::Pending).len(),
+            original.find_steps_by_status(StepStatus::Pending).len()
+        );
+    }
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "workflow-events-{}.jsonl",
+            WorkflowId::new().as_uuid()
+        ));
+        let graph = {
+            let mut store = FileEventStore::open(&path).unwrap();
+            record(&mut store)
+        };
+
+        let mut reopened = FileEventStore::open(&path).unwrap();
+        assert_eq!(reopened.verify_chain().unwrap().length, 4);
+        record(&mut reopened);
+        assert_eq!(reopened.verify_chain().unwrap().length, 8);
+        assert_eq!(reopened.replay(graph.id()).unwrap().cid(), graph.cid());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
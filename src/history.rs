@@ -0,0 +1,249 @@
+//! Time-travel queries over a workflow's event history
+//!
+//! These functions rebuild a workflow as it was at an earlier point of its
+//! recorded history. They work on envelopes already read from an
+//! [`EventStore`](crate::EventStore); verifying the chain is the store's job.
+
+use crate::event_store::EventEnvelope;
+use crate::{WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Utc};
+use cim_domain_workflow::value_objects::WorkflowId;
+use cim_domain_workflow::WorkflowDomainEvent;
+use serde::{Deserialize, Serialize};
+
+/// A point in a workflow's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// After every event recorded at or before this time
+    Timestamp(DateTime<Utc>),
+    /// After the event with this log sequence number
+    Sequence(u64),
+}
+
+impl AsOf {
+    fn includes(&self, envelope: &EventEnvelope) -> bool {
+        match self {
+            AsOf::Timestamp(at) => envelope.recorded_at <= *at,
+            AsOf::Sequence(sequence) => envelope.sequence <= *sequence,
+        }
+    }
+}
+
+/// The workflow's projection after one event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub sequence: u64,
+    pub recorded_at: DateTime<Utc>,
+    /// Name of the event that produced this frame, e.g. `TaskCompleted`
+    pub event: String,
+    /// Graphviz rendering of the context graph
+    pub dot: String,
+    /// JSON rendering of the context graph
+    pub json: String,
+}
+
+impl WorkflowGraph {
+    /// Rebuild the workflow as it was at a point of its history
+    ///
+    /// `events` are in log order and may belong to several workflows; only
+    /// those of `workflow_id` are used.
+    pub fn as_of(
+        events: &[EventEnvelope],
+        workflow_id: WorkflowId,
+        at: AsOf,
+    ) -> Result<Self, WorkflowGraphError> {
+        let history = history_of(events, workflow_id);
+        WorkflowGraph::from_events(
+            history
+                .into_iter()
+                .take_while(|envelope| at.includes(envelope))
+                .map(|envelope| &envelope.event),
+        )
+    }
+
+    /// Render the workflow after each of its events, for animating progress
+    pub fn frames(
+        events: &[EventEnvelope],
+        workflow_id: WorkflowId,
+    ) -> Result<Vec<Frame>, WorkflowGraphError> {
+        let history = history_of(events, workflow_id);
+        let Some((first, rest)) = history.split_first() else {
+            return Ok(Vec::new());
+        };
+
+        let mut graph = WorkflowGraph::from_events([&first.event])?;
+        let mut frames = vec![Frame::of(&graph, first)?];
+        for envelope in rest {
+            graph.apply_events([&envelope.event])?;
+            frames.push(Frame::of(&graph, envelope)?);
+        }
+        Ok(frames)
+    }
+}
+
+impl Frame {
+    fn of(graph: &WorkflowGraph, envelope: &EventEnvelope) -> Result<Self, WorkflowGraphError> {
+        Ok(Self {
+            sequence: envelope.sequence,
+            recorded_at: envelope.recorded_at,
+            event: event_name(&envelope.event),
+            dot: graph.to_dot(),
+            json: graph.to_json()?,
+        })
+    }
+}
+
+/// Events of one workflow, in log order
+fn history_of(events: &[EventEnvelope], workflow_id: WorkflowId) -> Vec<&EventEnvelope> {
+    events
+        .iter()
+        .filter(|envelope| envelope.workflow_id == workflow_id)
+        .collect()
+}
+
+/// The event's variant name, as used for its serialized tag
+pub(crate) fn event_name(event: &WorkflowDomainEvent) -> String {
+    match serde_json::to_value(event) {
+        Ok(serde_json::Value::Object(map)) => map.keys().next().cloned().unwrap_or_default(),
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::{EventStore, InMemoryEventStore};
+    use chrono::Duration;
+    use cim_domain_workflow::value_objects::{StepStatus, StepType, WorkflowStatus};
+    use std::collections::HashMap;
+
+    /// Records a two-step approval, one minute per event
+    fn history() -> (Vec<EventEnvelope>, DateTime<Utc>, WorkflowId) {
+        let mut store = InMemoryEventStore::new();
+        let mut graph = WorkflowGraph::new("Expense".to_string(), "Approval".to_string()).unwrap();
+        let submit = graph
+            .add_step(
+                "Submit".to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                None,
+                None,
+            )
+            .unwrap();
+        let approve = graph
+            .add_step(
+                "Manager Approval".to_string(),
+                String::new(),
+                StepType::Approval,
+                HashMap::new(),
+                vec![submit],
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        store.save(&mut graph).unwrap();
+        for step in [submit, approve] {
+            let events = graph
+                .workflow
+                .complete_task(step, "manager".to_string(), HashMap::new())
+                .unwrap();
+            store
+                .append_all(graph.id(), events, graph.environment().now())
+                .unwrap();
+        }
+
+        let start = Utc::now();
+        let mut events = store.events().unwrap();
+        for envelope in &mut events {
+            envelope.recorded_at = start + Duration::minutes(envelope.sequence as i64);
+        }
+        (events, start, graph.id())
+    }
+
+    #[test]
+    fn test_as_of_sequence() {
+        let (events, _, id) = history();
+
+        let draft = WorkflowGraph::as_of(&events, id, AsOf::Sequence(1)).unwrap();
+        assert_eq!(draft.workflow.steps.len(), 1);
+        assert_eq!(draft.status(), &WorkflowStatus::Draft);
+
+        let running = WorkflowGraph::as_of(&events, id, AsOf::Sequence(4)).unwrap();
+        assert_eq!(running.status(), &WorkflowStatus::Running);
+        assert_eq!(running.find_steps_by_status(StepStatus::Completed).len(), 1);
+        assert_eq!(running.get_step_nodes().len(), 2);
+    }
+
+    #[test]
+    fn test_as_of_timestamp() {
+        let (events, start, id) = history();
+
+        let approved = WorkflowGraph::as_of(
+            &events,
+            id,
+            AsOf::Timestamp(start + Duration::seconds(5 * 60 + 30)),
+        )
+        .unwrap();
+        assert_eq!(
+            approved.find_steps_by_status(StepStatus::Completed).len(),
+            2
+        );
+
+        let before =
+            WorkflowGraph::as_of(&events, id, AsOf::Timestamp(start - Duration::minutes(1)));
+        assert!(before.is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let (events, _, id) = history();
+        let frames = WorkflowGraph::frames(&events, id).unwrap();
+
+        assert_eq!(frames.len(), events.len());
+        assert_eq!(frames[0].event, "WorkflowCreated");
+        assert_eq!(frames[5].event, "TaskCompleted");
+        assert!(frames[0].dot.starts_with("digraph"));
+        assert!(!frames[1].dot.contains("Manager Approval"));
+        assert!(frames[2].dot.contains("Manager Approval"));
+        assert!(WorkflowGraph::from_json(&frames[5].json).is_ok());
+    }
+
+    #[test]
+    fn test_history_of_one_workflow_in_shared_log() {
+        let mut store = InMemoryEventStore::new();
+        let mut first = WorkflowGraph::new("First".to_string(), String::new()).unwrap();
+        store.save(&mut first).unwrap();
+        let mut second = WorkflowGraph::new("Second".to_string(), String::new()).unwrap();
+        store.save(&mut second).unwrap();
+        first
+            .add_step(
+                "Late".to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                None,
+                None,
+            )
+            .unwrap();
+        store.save(&mut first).unwrap();
+        let events = store.events().unwrap();
+
+        let head = AsOf::Sequence(events.len() as u64);
+        let rebuilt = WorkflowGraph::as_of(&events, second.id(), head).unwrap();
+        assert_eq!(rebuilt.workflow.name, "Second");
+        assert!(rebuilt.workflow.steps.is_empty());
+        assert_eq!(
+            WorkflowGraph::frames(&events, second.id()).unwrap().len(),
+            1
+        );
+        assert_eq!(WorkflowGraph::frames(&events, first.id()).unwrap().len(), 2);
+        assert!(WorkflowGraph::frames(&events, WorkflowId::new())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Upstream, downstream and delay analysis of steps
+//!
+//! Durations are the steps' estimated minutes; steps without an estimate
+//! take no time. Completed and skipped steps have no remaining work.
+//! Results are lists of step IDs that can be passed to a [`Highlight`] to
+//! mark the sub-graph in DOT output, and from there in SVG via Graphviz.
+
+use crate::{topology, ContextGraphNodeValue, WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepStatus, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How delaying one step moves the rest of the workflow
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DelayImpact {
+    pub step_id: StepId,
+    pub delay_minutes: u32,
+    /// Remaining minutes until the end before the delay
+    pub remaining_before: u64,
+    /// Remaining minutes until the end with the delay
+    pub remaining_after: u64,
+    /// Steps that finish later, in topological order
+    pub affected: Vec<StepId>,
+}
+
+impl DelayImpact {
+    /// Minutes the end of the workflow moves
+    pub fn end_shift_minutes(&self) -> u64 {
+        self.remaining_after - self.remaining_before
+    }
+}
+
+/// A step holding up other unfinished work
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockingStep {
+    pub step_id: StepId,
+    /// Unfinished steps that transitively depend on this one
+    pub blocked_steps: usize,
+    /// Remaining estimated minutes of those steps
+    pub blocked_minutes: u64,
+}
+
+impl WorkflowGraph {
+    /// Steps the given step transitively depends on, in topological order
+    pub fn ancestors(&self, step_id: StepId) -> Vec<StepId> {
+        let ancestors = topology::ancestors(self, step_id);
+        topology::topological_order(self)
+            .into_iter()
+            .filter(|id| ancestors.contains(id))
+            .collect()
+    }
+
+    /// Steps that transitively depend on the given step, in topological order
+    pub fn descendants(&self, step_id: StepId) -> Vec<StepId> {
+        let descendants = topology::descendants(self, step_id);
+        topology::topological_order(self)
+            .into_iter()
+            .filter(|id| descendants.contains(id))
+            .collect()
+    }
+
+    /// How far the end of the workflow moves if a step takes `minutes` longer
+    pub fn impact_of_delay(
+        &self,
+        step_id: StepId,
+        minutes: u32,
+    ) -> Result<DelayImpact, WorkflowGraphError> {
+        if !self.workflow.steps.contains_key(&step_id) {
+            return Err(WorkflowGraphError::StepNotFound(
+                step_id.as_uuid().to_string(),
+            ));
+        }
+
+        let before = topology::finish_times(self, remaining_minutes);
+        let after = topology::finish_times(self, |step| {
+            let delay = if step.id == step_id {
+                minutes.into()
+            } else {
+                0
+            };
+            remaining_minutes(step) + delay
+        });
+        let end = |finish: &HashMap<StepId, u64>| finish.values().max().copied().unwrap_or(0);
+
+        Ok(DelayImpact {
+            step_id,
+            delay_minutes: minutes,
+            remaining_before: end(&before),
+            remaining_after: end(&after),
+            affected: topology::topological_order(self)
+                .into_iter()
+                .filter(|id| after[id] > before[id])
+                .collect(),
+        })
+    }
+
+    /// Unfinished steps with unfinished work depending on them, most blocking
+    /// first
+    pub fn blocking_steps(&self) -> Vec<BlockingStep> {
+        let steps = &self.workflow.steps;
+        let mut blocking: Vec<BlockingStep> = topology::topological_order(self)
+            .into_iter()
+            .filter(|id| !is_finished(&steps[id]))
+            .map(|id| {
+                let blocked: Vec<&WorkflowStep> = topology::descendants(self, id)
+                    .iter()
+                    .map(|descendant| &steps[descendant])
+                    .filter(|step| !is_finished(step))
+                    .collect();
+                BlockingStep {
+                    step_id: id,
+                    blocked_steps: blocked.len(),
+                    blocked_minutes: blocked.iter().map(|step| remaining_minutes(step)).sum(),
+                }
+            })
+            .filter(|step| step.blocked_steps > 0)
+            .collect();
+
+        // Stable sort keeps topological order among equals
+        blocking.sort_by(|a, b| {
+            b.blocked_steps
+                .cmp(&a.blocked_steps)
+                .then(b.blocked_minutes.cmp(&a.blocked_minutes))
+        });
+        blocking
+    }
+
+    /// Export as DOT with highlighted steps
+    pub fn to_dot_highlighted(&self, highlight: &Highlight) -> String {
+        let dot = self.to_dot();
+        let body = dot.trim_end().strip_suffix('}').unwrap_or(&dot);
+
+        let mut statements = String::new();
+        for node in self.get_step_nodes() {
+            let ContextGraphNodeValue::Step { step_id, .. } = &node.value else {
+                continue;
+            };
+            if let Some(color) = highlight.colors.get(step_id) {
+                statements.push_str(&format!(
+                    "  \"{}\" [style=filled, fillcolor=\"{}\"];\n",
+                    node.id.replace('"', "\\\""),
+                    color.replace('"', "\\\"")
+                ));
+            }
+        }
+        format!("{body}{statements}}}\n")
+    }
+}
+
+/// Colors for steps in DOT output
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Highlight {
+    colors: HashMap<StepId, String>,
+}
+
+impl Highlight {
+    /// Highlight nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fill the steps with a Graphviz color; later calls win for a step
+    pub fn steps(mut self, steps: impl IntoIterator<Item = StepId>, color: &str) -> Self {
+        for step_id in steps {
+            self.colors.insert(step_id, color.to_string());
+        }
+        self
+    }
+}
+
+/// Estimated minutes of work left in a step
+pub(crate) fn remaining_minutes(step: &WorkflowStep) -> u64 {
+    if is_finished(step) {
+        0
+    } else {
+        step.estimated_duration_minutes.unwrap_or(0).into()
+    }
+}
+
+/// Whether a step has no work left
+pub(crate) fn is_finished(step: &WorkflowStep) -> bool {
+    matches!(step.status, StepStatus::Completed | StepStatus::Skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cim_domain_workflow::value_objects::StepType;
+
+    fn step(
+        graph: &mut WorkflowGraph,
+        name: &str,
+        dependencies: Vec<StepId>,
+        minutes: u32,
+    ) -> StepId {
+        graph
+            .add_step(
+                name.to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                dependencies,
+                Some(minutes),
+                None,
+            )
+            .unwrap()
+    }
+
+    /// design(30) -> build(60) -> ship(10), with docs(20) also feeding ship
+    fn release() -> (WorkflowGraph, [StepId; 4]) {
+        let mut graph = WorkflowGraph::new("Release".to_string(), "Ship it".to_string()).unwrap();
+        let design = step(&mut graph, "Design", vec![], 30);
+        let build = step(&mut graph, "Build", vec![design], 60);
+        let docs = step(&mut graph, "Docs", vec![design], 20);
+        let ship = step(&mut graph, "Ship", vec![build, docs], 10);
+        (graph, [design, build, docs, ship])
+    }
+
+    #[test]
+    fn test_ancestors_and_descendants() {
+        let (graph, [design, build, docs, ship]) = release();
+
+        assert_eq!(graph.ancestors(ship), vec![design, build, docs]);
+        assert_eq!(graph.descendants(design), vec![build, docs, ship]);
+        assert!(graph.ancestors(design).is_empty());
+    }
+
+    #[test]
+    fn test_impact_of_delay() {
+        let (graph, [design, build, docs, ship]) = release();
+
+        // Docs has 40 minutes of slack beside the build
+        let docs_delay = graph.impact_of_delay(docs, 30).unwrap();
+        assert_eq!(docs_delay.remaining_before, 100);
+        assert_eq!(docs_delay.end_shift_minutes(), 0);
+        assert_eq!(docs_delay.affected, vec![docs]);
+
+        let docs_delay = graph.impact_of_delay(docs, 50).unwrap();
+        assert_eq!(docs_delay.end_shift_minutes(), 10);
+        assert_eq!(docs_delay.affected, vec![docs, ship]);
+
+        let design_delay = graph.impact_of_delay(design, 15).unwrap();
+        assert_eq!(design_delay.end_shift_minutes(), 15);
+        assert_eq!(design_delay.affected, vec![design, build, docs, ship]);
+
+        assert!(matches!(
+            graph.impact_of_delay(StepId::new(), 5),
+            Err(WorkflowGraphError::StepNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_blocking_steps_and_highlight() {
+        let (graph, [design, build, docs, _]) = release();
+
+        let blocking = graph.blocking_steps();
+        let order: Vec<StepId> = blocking.iter().map(|step| step.step_id).collect();
+        assert_eq!(order, vec![design, build, docs]);
+        assert_eq!(blocking[0].blocked_steps, 3);
+        assert_eq!(blocking[0].blocked_minutes, 90);
+
+        let dot =
+            graph.to_dot_highlighted(&Highlight::new().steps(graph.descendants(build), "tomato"));
+        assert_eq!(dot.matches("fillcolor=\"tomato\"").count(), 1);
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn test_analysis_over_a_dependency_cycle() {
+        let (mut graph, [design, build, docs, ship]) = release();
+        graph
+            .workflow
+            .steps
+            .get_mut(&design)
+            .unwrap()
+            .dependencies
+            .push(ship);
+
+        // Cycle members are ordered by name and never their own ancestors
+        assert_eq!(graph.ancestors(ship), vec![build, design, docs]);
+        assert_eq!(graph.descendants(ship), vec![build, design, docs]);
+
+        let delay = graph.impact_of_delay(build, 10).unwrap();
+        assert_eq!(delay.remaining_before, 70);
+        assert_eq!(delay.end_shift_minutes(), 10);
+        assert_eq!(delay.affected, vec![build, ship]);
+        assert!(graph.impact_of_delay(build, 0).unwrap().affected.is_empty());
+
+        let order: Vec<StepId> = graph.blocking_steps().iter().map(|s| s.step_id).collect();
+        assert_eq!(order, vec![ship, docs, design, build]);
+    }
+}
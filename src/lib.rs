@@ -5,13 +5,90 @@
 
 use cim_domain_workflow::{
     aggregate::Workflow,
+    events::{StepAdded, WorkflowCreated},
     projections::WorkflowContextGraph,
     value_objects::{StepId, StepStatus, StepType, WorkflowId, WorkflowStatus},
+    WorkflowDomainEvent,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Debug;
 
+pub mod approval;
+pub mod authorization;
+pub mod calendar;
+pub mod canonical;
+pub mod clock;
+pub mod commands;
+pub mod diff;
+pub mod environment;
+pub mod event_store;
+pub mod history;
+pub mod impact;
+pub mod merge;
+pub mod progress;
+pub mod query;
+pub mod reduction;
+pub mod repository;
+pub mod schedule;
+pub mod schema;
+pub mod simulation;
+pub mod sla;
+pub mod snapshot;
+pub mod template;
+pub mod transport;
+pub mod versioning;
+pub mod waves;
+pub mod worklist;
+
+mod topology;
+
+pub use approval::{
+    ApprovalEvent, ApprovalOutcome, ApprovalPolicy, ApprovalResolved, Approvals, Decision, Quorum,
+    RejectionAction, Vote, VoteCast, APPROVAL_POLICY_KEY,
+};
+pub use authorization::{Actor, AuditEntry, AuthorizationPolicy, Grant, Operation, SYSTEM_ACTOR};
+pub use calendar::{BusinessCalendar, Eta, StepDue};
+pub use canonical::{CanonicalDefinition, CanonicalStep, Cid, DefinitionStore};
+pub use clock::{Clock, FixedClock, ManualClock, SystemClock};
+pub use commands::{
+    CommandEnvelope, CommandOutput, CommandReply, CorrelationId, RoutingStats, WorkflowCommand,
+    WorkflowCommandHandler, WorkflowCommandRouter,
+};
+pub use diff::{
+    DependencyChange, FieldChange, PropertyChange, StepChange, StepMatch, StepRef, WorkflowDiff,
+};
+pub use environment::{IdGenerator, RandomIdGenerator, SequentialIdGenerator, WorkflowEnvironment};
+pub use event_store::{
+    verify_chain, verify_chain_after, ChainBreak, ChainBreakKind, ChainSummary, EventEnvelope,
+    EventStore, FileEventStore, InMemoryEventStore,
+};
+pub use history::{AsOf, Frame};
+pub use impact::{BlockingStep, DelayImpact, Highlight};
+pub use merge::{merge, MergeConflict, MergeResult, MergeSide};
+pub use progress::{OverdueStep, WorkflowProgress};
+pub use query::StepQuery;
+pub use reduction::{ReductionReport, RedundantDependency};
+pub use repository::{FileWorkflowRepository, InMemoryWorkflowRepository, WorkflowRepository};
+pub use schedule::{ResourceCapacities, Schedule, ScheduleMethod, ScheduledStep, EXACT_STEP_LIMIT};
+pub use schema::{ConfigViolation, StepConfigSchemaRegistry};
+pub use simulation::{DurationEstimate, MonteCarlo, SimulationReport, DURATION_ESTIMATE_KEY};
+pub use sla::{
+    Deadline, Escalation, EscalationAction, SlaEvent, SlaMonitor, StepEscalated, StepSla,
+    StepSlaBreached,
+};
+pub use snapshot::{Snapshot, SnapshotState, SnapshotStore};
+pub use template::{TemplateParameter, TemplateStep, WorkflowTemplate};
+pub use transport::{
+    EventMessage, InMemoryTransport, SubscriptionId, TransportMessage, WorkflowTransport,
+};
+pub use versioning::{AddedStepRule, Migration, MigrationReport, MigrationRules, RemovedStepRule};
+pub use waves::{ExecutionWaves, TieBreak};
+pub use worklist::{
+    Priority, WorkItem, WorkItemClaimed, WorkItemCompleted, WorkItemDelegated, WorkItemOffered,
+    WorkItemUnclaimed, Worklist, WorklistEvent, PRIORITY_PROPERTY,
+};
+
 pub use cim_domain_workflow::projections::{
     ContextGraphEdge, ContextGraphEdgeValue, ContextGraphNode, ContextGraphNodeValue,
     WorkflowContextGraph as ContextGraph, WorkflowGraphStatistics,
@@ -26,6 +103,16 @@ pub struct WorkflowGraph {
     pub context_graph: WorkflowContextGraph,
     /// Graph metadata
     pub metadata: WorkflowGraphMetadata,
+    /// Schemas that step configuration must satisfy
+    pub schema_registry: StepConfigSchemaRegistry,
+    /// Domain events emitted since they were last taken for persistence
+    pub uncommitted_events: Vec<WorkflowDomainEvent>,
+    /// Clock and ID generator used by constructors and mutations
+    pub environment: WorkflowEnvironment,
+    /// Who may perform each mutation
+    authorization_policy: AuthorizationPolicy,
+    /// Attempted mutations, allowed or not, oldest first
+    audit_log: Vec<AuditEntry>,
 }
 
 /// Metadata for workflow graphs
@@ -35,6 +122,19 @@ pub struct WorkflowGraphMetadata {
     pub description: String,
     pub tags: Vec<String>,
     pub properties: HashMap<String, serde_json::Value>,
+    /// Definition version, incremented when a changed definition is published
+    #[serde(default = "initial_definition_version")]
+    pub version: u32,
+    /// Content identifier (CID) of the canonical definition
+    #[serde(default)]
+    pub content_hash: String,
+    /// Whether the workflow's history is kept in an event log
+    #[serde(default)]
+    pub event_sourced: bool,
+}
+
+fn initial_definition_version() -> u32 {
+    1
 }
 
 impl Default for WorkflowGraphMetadata {
@@ -44,6 +144,9 @@ impl Default for WorkflowGraphMetadata {
             description: String::new(),
             tags: Vec::new(),
             properties: HashMap::new(),
+            version: initial_definition_version(),
+            content_hash: String::new(),
+            event_sourced: false,
         }
     }
 }
@@ -51,41 +154,103 @@ impl Default for WorkflowGraphMetadata {
 impl WorkflowGraph {
     /// Create a new workflow graph
     pub fn new(name: String, description: String) -> Result<Self, WorkflowGraphError> {
-        let metadata = HashMap::new();
-        let (workflow, _events) = Workflow::new(name.clone(), description.clone(), metadata, None)
-            .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        Self::new_in(name, description, WorkflowEnvironment::system())
+    }
+
+    /// Create a new workflow graph that takes IDs and timestamps from an
+    /// environment
+    pub fn new_in(
+        name: String,
+        description: String,
+        environment: WorkflowEnvironment,
+    ) -> Result<Self, WorkflowGraphError> {
+        Self::create(name, description, environment, None)
+    }
+
+    /// Create a new workflow graph, recording the actor that created it
+    pub fn new_as(
+        actor: &Actor,
+        name: String,
+        description: String,
+    ) -> Result<Self, WorkflowGraphError> {
+        Self::new_in_as(actor, name, description, WorkflowEnvironment::system())
+    }
+
+    /// Create a new workflow graph in an environment, recording the actor
+    /// that created it
+    pub fn new_in_as(
+        actor: &Actor,
+        name: String,
+        description: String,
+        environment: WorkflowEnvironment,
+    ) -> Result<Self, WorkflowGraphError> {
+        Self::create(name, description, environment, Some(actor.id.clone()))
+    }
+
+    fn create(
+        name: String,
+        description: String,
+        environment: WorkflowEnvironment,
+        created_by: Option<String>,
+    ) -> Result<Self, WorkflowGraphError> {
+        let created = WorkflowCreated {
+            workflow_id: environment.next_id(),
+            name: name.clone(),
+            description: description.clone(),
+            metadata: HashMap::new(),
+            created_by,
+            created_at: environment.now(),
+        };
+        let workflow = created_workflow(&created)?;
+        let events = vec![WorkflowDomainEvent::WorkflowCreated(created)];
 
         let context_graph = WorkflowContextGraph::from_workflow(&workflow);
 
-        Ok(Self {
+        let mut graph = Self {
             workflow,
             context_graph,
             metadata: WorkflowGraphMetadata {
                 name,
                 description,
-                tags: Vec::new(),
-                properties: HashMap::new(),
+                ..Default::default()
             },
-        })
+            schema_registry: StepConfigSchemaRegistry::new(),
+            uncommitted_events: events,
+            environment,
+            authorization_policy: AuthorizationPolicy::default(),
+            audit_log: Vec::new(),
+        };
+        graph.refresh_context_graph();
+
+        Ok(graph)
     }
 
     /// Create a workflow graph from an existing workflow
     pub fn from_workflow(workflow: Workflow) -> Self {
         let context_graph = WorkflowContextGraph::from_workflow(&workflow);
 
-        Self {
+        let mut graph = Self {
             metadata: WorkflowGraphMetadata {
                 name: workflow.name.clone(),
                 description: workflow.description.clone(),
-                tags: Vec::new(),
                 properties: workflow.metadata.clone(),
+                ..Default::default()
             },
             workflow,
             context_graph,
-        }
+            schema_registry: StepConfigSchemaRegistry::new(),
+            uncommitted_events: Vec::new(),
+            environment: WorkflowEnvironment::system(),
+            authorization_policy: AuthorizationPolicy::default(),
+            audit_log: Vec::new(),
+        };
+        graph.refresh_context_graph();
+
+        graph
     }
 
-    /// Add a step to the workflow
+    /// Add a step to the workflow as the system actor
+    #[allow(clippy::too_many_arguments)]
     pub fn add_step(
         &mut self,
         name: String,
@@ -96,49 +261,99 @@ impl WorkflowGraph {
         estimated_duration_minutes: Option<u32>,
         assigned_to: Option<String>,
     ) -> Result<StepId, WorkflowGraphError> {
-        let events = self
-            .workflow
-            .add_step(
-                name,
-                description,
-                step_type,
-                config,
-                dependencies,
-                estimated_duration_minutes,
-                assigned_to,
-                Some("system".to_string()),
-            )
-            .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        self.add_step_as(
+            &Actor::system(),
+            name,
+            description,
+            step_type,
+            config,
+            dependencies,
+            estimated_duration_minutes,
+            assigned_to,
+        )
+    }
+
+    /// Add a step to the workflow as an actor
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_step_as(
+        &mut self,
+        actor: &Actor,
+        name: String,
+        description: String,
+        step_type: StepType,
+        config: HashMap<String, serde_json::Value>,
+        dependencies: Vec<StepId>,
+        estimated_duration_minutes: Option<u32>,
+        assigned_to: Option<String>,
+    ) -> Result<StepId, WorkflowGraphError> {
+        self.authorize(actor, Operation::AddStep, None)?;
+        self.schema_registry.validate(&name, &step_type, &config)?;
 
-        // Extract the step ID from the events
-        if let Some(cim_domain_workflow::WorkflowDomainEvent::StepAdded(ref event)) = events.first()
+        if self.workflow.status != WorkflowStatus::Draft {
+            return Err(WorkflowGraphError::InvalidOperation(
+                "Steps can only be added to a draft workflow".to_string(),
+            ));
+        }
+        if let Some(missing) = dependencies
+            .iter()
+            .find(|dependency| !self.workflow.steps.contains_key(dependency))
         {
-            let step_id = event.step_id;
+            return Err(WorkflowGraphError::InvalidDependency(format!(
+                "Dependency {} does not exist",
+                missing.as_uuid()
+            )));
+        }
 
-            // Refresh the context graph
-            self.refresh_context_graph();
+        let step_id = self.environment.next_id();
+        let event = WorkflowDomainEvent::StepAdded(StepAdded {
+            workflow_id: self.workflow.id,
+            step_id,
+            name,
+            description,
+            step_type,
+            config,
+            dependencies,
+            estimated_duration_minutes,
+            assigned_to,
+            added_by: Some(actor.id.clone()),
+            added_at: self.environment.now(),
+        });
+        self.workflow
+            .apply_event(&event)
+            .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        self.uncommitted_events.push(event);
 
-            Ok(step_id)
-        } else {
-            Err(WorkflowGraphError::InvalidOperation(
-                "Failed to create step".to_string(),
-            ))
-        }
+        // Refresh the context graph
+        self.refresh_context_graph();
+
+        Ok(step_id)
     }
 
-    /// Start the workflow
+    /// Start the workflow as the system actor
     pub fn start(
         &mut self,
         context: HashMap<String, serde_json::Value>,
     ) -> Result<(), WorkflowGraphError> {
+        self.start_as(&Actor::system(), context)
+    }
+
+    /// Start the workflow as an actor
+    pub fn start_as(
+        &mut self,
+        actor: &Actor,
+        context: HashMap<String, serde_json::Value>,
+    ) -> Result<(), WorkflowGraphError> {
+        self.authorize(actor, Operation::Start, None)?;
+
         let mut workflow_context = cim_domain_workflow::value_objects::WorkflowContext::new();
         workflow_context.variables = context;
-        workflow_context.set_actor("system".to_string());
+        workflow_context.set_actor(actor.id.clone());
 
-        let _events = self
+        let events = self
             .workflow
-            .start(workflow_context, Some("system".to_string()))
+            .start(workflow_context, Some(actor.id.clone()))
             .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        self.record(events);
 
         // Refresh the context graph
         self.refresh_context_graph();
@@ -146,12 +361,107 @@ impl WorkflowGraph {
         Ok(())
     }
 
-    /// Complete the workflow
+    /// Complete the workflow as the system actor
     pub fn complete(&mut self) -> Result<(), WorkflowGraphError> {
-        let _events = self
+        self.complete_as(&Actor::system())
+    }
+
+    /// Complete the workflow as an actor
+    pub fn complete_as(&mut self, actor: &Actor) -> Result<(), WorkflowGraphError> {
+        self.authorize(actor, Operation::Complete, None)?;
+
+        let events = self
             .workflow
             .complete()
             .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        self.record(events);
+
+        // Refresh the context graph
+        self.refresh_context_graph();
+
+        Ok(())
+    }
+
+    /// Complete a step of the running workflow as the system actor,
+    /// recording `completed_by` as who did the work
+    ///
+    /// `completed_by` is not checked against the authorization policy; use
+    /// [`Self::complete_step_as`] to act as someone.
+    pub fn complete_step(
+        &mut self,
+        step_id: StepId,
+        completed_by: String,
+        data: HashMap<String, serde_json::Value>,
+    ) -> Result<(), WorkflowGraphError> {
+        self.authorize_step_completion(&Actor::system(), step_id)?;
+        self.complete_step_unchecked(step_id, completed_by, data)
+    }
+
+    /// Complete a step of the running workflow as an actor
+    pub fn complete_step_as(
+        &mut self,
+        actor: &Actor,
+        step_id: StepId,
+        data: HashMap<String, serde_json::Value>,
+    ) -> Result<(), WorkflowGraphError> {
+        self.authorize_step_completion(actor, step_id)?;
+        self.complete_step_unchecked(step_id, actor.id.clone(), data)
+    }
+
+    fn authorize_step_completion(
+        &mut self,
+        actor: &Actor,
+        step_id: StepId,
+    ) -> Result<(), WorkflowGraphError> {
+        if !self.workflow.steps.contains_key(&step_id) {
+            return Err(WorkflowGraphError::StepNotFound(
+                step_id.as_uuid().to_string(),
+            ));
+        }
+        self.authorize(actor, Operation::CompleteStep, Some(step_id))
+    }
+
+    /// Complete a step without consulting the authorization policy
+    pub(crate) fn complete_step_unchecked(
+        &mut self,
+        step_id: StepId,
+        completed_by: String,
+        data: HashMap<String, serde_json::Value>,
+    ) -> Result<(), WorkflowGraphError> {
+        let events = self
+            .workflow
+            .complete_task(step_id, completed_by, data)
+            .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        self.record(events);
+
+        // Refresh the context graph
+        self.refresh_context_graph();
+
+        Ok(())
+    }
+
+    /// Cancel the workflow as the system actor
+    pub fn cancel(&mut self, reason: String) -> Result<(), WorkflowGraphError> {
+        self.cancel_as(&Actor::system(), reason)
+    }
+
+    /// Cancel the workflow as an actor
+    pub fn cancel_as(&mut self, actor: &Actor, reason: String) -> Result<(), WorkflowGraphError> {
+        self.authorize(actor, Operation::Cancel, None)?;
+        self.cancel_unchecked(actor, reason)
+    }
+
+    /// Cancel the workflow without consulting the authorization policy
+    pub(crate) fn cancel_unchecked(
+        &mut self,
+        actor: &Actor,
+        reason: String,
+    ) -> Result<(), WorkflowGraphError> {
+        let events = self
+            .workflow
+            .cancel(reason, Some(actor.id.clone()))
+            .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+        self.record(events);
 
         // Refresh the context graph
         self.refresh_context_graph();
@@ -258,9 +568,94 @@ impl WorkflowGraph {
         self.metadata.properties.get(key)
     }
 
+    /// Set the schema registry used to validate step configuration
+    pub fn set_schema_registry(&mut self, registry: StepConfigSchemaRegistry) {
+        self.schema_registry = registry;
+    }
+
+    /// Get the schema registry used to validate step configuration
+    pub fn schema_registry(&self) -> &StepConfigSchemaRegistry {
+        &self.schema_registry
+    }
+
+    /// Set the clock and ID generator used by later mutations
+    pub fn set_environment(&mut self, environment: WorkflowEnvironment) {
+        self.environment = environment;
+    }
+
+    /// Get the clock and ID generator used by mutations
+    pub fn environment(&self) -> &WorkflowEnvironment {
+        &self.environment
+    }
+
+    /// Take the domain events emitted since the last call
+    pub fn take_uncommitted_events(&mut self) -> Vec<WorkflowDomainEvent> {
+        std::mem::take(&mut self.uncommitted_events)
+    }
+
+    /// Stamp newly emitted events with the environment's time and keep
+    /// them for persistence
+    fn record(&mut self, mut events: Vec<WorkflowDomainEvent>) {
+        let now = self.environment.now();
+        for event in &mut events {
+            match event {
+                WorkflowDomainEvent::WorkflowStarted(started) => started.started_at = now,
+                WorkflowDomainEvent::TaskCompleted(completed) => {
+                    completed.completed_at = now;
+                    if let Some(step) = self.workflow.steps.get_mut(&completed.step_id) {
+                        step.completed_at = Some(now);
+                    }
+                }
+                WorkflowDomainEvent::WorkflowCompleted(completed) => completed.completed_at = now,
+                WorkflowDomainEvent::WorkflowCancelled(cancelled) => cancelled.cancelled_at = now,
+                _ => {}
+            }
+        }
+        self.uncommitted_events.extend(events);
+    }
+
     /// Refresh the context graph representation
+    ///
+    /// Step nodes are listed in topological order and dependency edges by
+    /// target then source, and an edge keeps its ID across refreshes, so the
+    /// exports only change when the workflow does.
     fn refresh_context_graph(&mut self) {
-        self.context_graph = WorkflowContextGraph::from_workflow(&self.workflow);
+        let previous: HashMap<(String, String), String> = self
+            .context_graph
+            .edges
+            .drain(..)
+            .map(|edge| ((edge.source, edge.target), edge.id))
+            .collect();
+        let mut context_graph = WorkflowContextGraph::from_workflow(&self.workflow);
+
+        let position: HashMap<String, usize> = topology::topological_order(self)
+            .into_iter()
+            .enumerate()
+            .map(|(index, id)| (id.as_uuid().to_string(), index))
+            .collect();
+        let rank = |id: &str| match id {
+            "start" => (0, 0),
+            "end" => (2, 0),
+            _ => (1, position.get(id).copied().unwrap_or(usize::MAX)),
+        };
+        context_graph.nodes.sort_by_key(|node| rank(&node.id));
+        context_graph
+            .edges
+            .sort_by_key(|edge| (rank(&edge.target), rank(&edge.source)));
+        for edge in &mut context_graph.edges {
+            edge.id = match previous.get(&(edge.source.clone(), edge.target.clone())) {
+                Some(id) => id.clone(),
+                None => self.environment.id_generator().next_uuid().to_string(),
+            };
+        }
+
+        self.context_graph = context_graph;
+        self.refresh_content_hash();
+    }
+
+    /// Recompute the definition content hash
+    fn refresh_content_hash(&mut self) {
+        self.metadata.content_hash = versioning::content_hash(self);
     }
 
     /// Validate the workflow graph
@@ -288,6 +683,12 @@ impl WorkflowGraph {
             }
         }
 
+        // Check step configuration against the registered schemas
+        for step in self.workflow.steps.values() {
+            self.schema_registry
+                .validate(&step.name, &step.step_type, &step.config)?;
+        }
+
         Ok(())
     }
 
@@ -327,6 +728,63 @@ pub enum WorkflowGraphError {
 
     #[error("Step not found: {0}")]
     StepNotFound(String),
+
+    #[error("Workflow not found: {0}")]
+    WorkflowNotFound(String),
+
+    #[error("Concurrent modification of workflow {workflow_id}: expected revision {expected}, found {actual}")]
+    ConcurrencyConflict {
+        workflow_id: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("No handler for command: {0}")]
+    UnknownCommand(String),
+
+    #[error("Invalid template parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("Migration rejected: {0}")]
+    MigrationRejected(String),
+
+    #[error("Event chain broken: {0}")]
+    ChainBroken(ChainBreak),
+
+    #[error("Snapshot rejected: {0}")]
+    SnapshotRejected(String),
+
+    #[error("Unauthorized: {actor} may not {operation}")]
+    Unauthorized { actor: String, operation: String },
+
+    #[error("Invalid config for step {step}: {}", format_violations(.violations))]
+    InvalidConfig {
+        step: String,
+        violations: Vec<ConfigViolation>,
+    },
+}
+
+/// A workflow in the state its creation event describes
+pub(crate) fn created_workflow(created: &WorkflowCreated) -> Result<Workflow, WorkflowGraphError> {
+    let (mut workflow, _events) = Workflow::new(
+        created.name.clone(),
+        created.description.clone(),
+        created.metadata.clone(),
+        created.created_by.clone(),
+    )
+    .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+    workflow
+        .apply_event(&WorkflowDomainEvent::WorkflowCreated(created.clone()))
+        .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+    Ok(workflow)
+}
+
+fn format_violations(violations: &[ConfigViolation]) -> String {
+    violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[cfg(test)]
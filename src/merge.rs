@@ -0,0 +1,705 @@
+//! Three-way merge of concurrent workflow definition edits
+//!
+//! [`merge`] combines the changes made in two branches (`ours` and `theirs`)
+//! relative to their common ancestor (`base`). Steps are paired up the same way
+//! as in [`WorkflowGraph::diff`]. Independent changes are combined
+//! automatically; competing changes are reported as [`MergeConflict`]s rather
+//! than resolved by picking a side.
+
+use crate::diff::{field_changes, match_steps, sorted_steps, StepMatch, StepRef};
+use crate::WorkflowGraph;
+use cim_domain_workflow::value_objects::{StepId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Which branch of a merge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeSide {
+    Ours,
+    Theirs,
+}
+
+/// A change that could not be merged automatically
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MergeConflict {
+    /// Both branches changed the same step field to different values
+    ///
+    /// The merged graph keeps the base value.
+    StepField {
+        step: StepRef,
+        field: String,
+        base: serde_json::Value,
+        ours: serde_json::Value,
+        theirs: serde_json::Value,
+    },
+    /// One branch removed a step that the other branch modified
+    ///
+    /// The merged graph keeps the modified step.
+    ModifiedAndRemoved {
+        step: StepRef,
+        removed_by: MergeSide,
+    },
+    /// Both branches added a step with the same name but different content
+    ///
+    /// The merged graph keeps our step.
+    BothAdded { ours: StepRef, theirs: StepRef },
+    /// Both branches changed the same metadata property to different values
+    ///
+    /// The merged graph keeps the base value.
+    Property {
+        key: String,
+        base: Option<serde_json::Value>,
+        ours: Option<serde_json::Value>,
+        theirs: Option<serde_json::Value>,
+    },
+    /// One branch made a step depend on a step that the other branch removed
+    ///
+    /// The merged graph leaves the dependency out.
+    DependsOnRemoved {
+        step: StepRef,
+        dependency: StepRef,
+        removed_by: MergeSide,
+    },
+    /// The combined dependencies form a cycle through these steps
+    Cycle { steps: Vec<StepRef> },
+}
+
+/// Outcome of a three-way merge
+#[derive(Debug, Clone)]
+pub struct MergeResult {
+    /// The merged definition, based on `ours`
+    pub merged: WorkflowGraph,
+    /// Changes that need to be resolved by hand
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeResult {
+    /// Whether every change merged without conflict
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merge the changes of `ours` and `theirs` relative to `base`
+pub fn merge(base: &WorkflowGraph, ours: &WorkflowGraph, theirs: &WorkflowGraph) -> MergeResult {
+    let ours_pairs = match_steps(base, ours);
+    let theirs_pairs = match_steps(base, theirs);
+    let mut conflicts = Vec::new();
+
+    // Every surviving step is keyed by the ID it will have in the merged graph
+    let mut steps: Vec<WorkflowStep> = Vec::new();
+    let mut base_ids: HashMap<StepId, StepId> = HashMap::new();
+    let mut ours_ids: HashMap<StepId, StepId> = HashMap::new();
+    let mut theirs_ids: HashMap<StepId, StepId> = HashMap::new();
+
+    for step in sorted_steps(base) {
+        let our_step = ours_pairs
+            .get(&step.id)
+            .map(|(id, _)| &ours.workflow.steps[id]);
+        let their_step = theirs_pairs
+            .get(&step.id)
+            .map(|(id, _)| &theirs.workflow.steps[id]);
+
+        let merged = match (our_step, their_step) {
+            (Some(o), Some(t)) => Some(merge_step(step, o, t, &mut conflicts)),
+            (Some(o), None) => keep_if_modified(step, o, MergeSide::Theirs, &mut conflicts),
+            (None, Some(t)) => keep_if_modified(step, t, MergeSide::Ours, &mut conflicts),
+            (None, None) => None,
+        };
+
+        if let Some(merged) = merged {
+            base_ids.insert(step.id, merged.id);
+            if let Some(o) = our_step {
+                ours_ids.insert(o.id, merged.id);
+            }
+            if let Some(t) = their_step {
+                theirs_ids.insert(t.id, merged.id);
+            }
+            steps.push(merged);
+        }
+    }
+
+    // Steps added on either branch
+    let ours_matched: HashSet<StepId> = ours_pairs.values().map(|(id, _)| *id).collect();
+    let theirs_matched: HashSet<StepId> = theirs_pairs.values().map(|(id, _)| *id).collect();
+    let ours_added: Vec<&WorkflowStep> = sorted_steps(ours)
+        .into_iter()
+        .filter(|s| !ours_matched.contains(&s.id))
+        .collect();
+
+    for step in &ours_added {
+        ours_ids.insert(step.id, step.id);
+        steps.push((*step).clone());
+    }
+    for step in sorted_steps(theirs) {
+        if theirs_matched.contains(&step.id) {
+            continue;
+        }
+        match ours_added.iter().find(|o| o.name == step.name) {
+            Some(o) => {
+                if !field_changes(o, step).is_empty() {
+                    conflicts.push(MergeConflict::BothAdded {
+                        ours: StepRef::of(o),
+                        theirs: StepRef::of(step),
+                    });
+                }
+                theirs_ids.insert(step.id, o.id);
+            }
+            None => {
+                theirs_ids.insert(step.id, step.id);
+                steps.push(step.clone());
+            }
+        }
+    }
+
+    // New dependencies on steps the other side removed cannot be kept
+    dangling_dependencies(
+        base,
+        theirs,
+        &theirs_pairs,
+        &theirs_ids,
+        MergeSide::Ours,
+        &mut conflicts,
+    );
+    dangling_dependencies(
+        base,
+        ours,
+        &ours_pairs,
+        &ours_ids,
+        MergeSide::Theirs,
+        &mut conflicts,
+    );
+
+    // Combine dependency edges: keep what both sides kept, plus what either added
+    let base_edges = edges(base, &base_ids);
+    let ours_edges = edges(ours, &ours_ids);
+    let theirs_edges = edges(theirs, &theirs_ids);
+    let merged_edges: HashSet<(StepId, StepId)> = ours_edges
+        .iter()
+        .chain(theirs_edges.iter())
+        .filter(|edge| {
+            (ours_edges.contains(edge) && theirs_edges.contains(edge)) || !base_edges.contains(edge)
+        })
+        .copied()
+        .collect();
+
+    for step in &mut steps {
+        let mut dependencies: Vec<StepId> = merged_edges
+            .iter()
+            .filter(|(from, _)| *from == step.id)
+            .map(|(_, dep)| *dep)
+            .collect();
+        dependencies.sort_by(|a, b| a.as_uuid().cmp(b.as_uuid()));
+        step.dependencies = dependencies;
+    }
+
+    let mut merged = ours.clone();
+    merged.workflow.steps = steps.into_iter().map(|step| (step.id, step)).collect();
+    merged.metadata.tags = merge_tags(
+        &base.metadata.tags,
+        &ours.metadata.tags,
+        &theirs.metadata.tags,
+    );
+
+    let keys: BTreeSet<&String> = base
+        .metadata
+        .properties
+        .keys()
+        .chain(ours.metadata.properties.keys())
+        .chain(theirs.metadata.properties.keys())
+        .collect();
+    for key in keys {
+        let base_value = base.metadata.properties.get(key);
+        let ours_value = ours.metadata.properties.get(key);
+        let theirs_value = theirs.metadata.properties.get(key);
+        let value = match merge3(base_value, ours_value, theirs_value) {
+            Some(value) => value,
+            None => {
+                conflicts.push(MergeConflict::Property {
+                    key: key.clone(),
+                    base: base_value.cloned(),
+                    ours: ours_value.cloned(),
+                    theirs: theirs_value.cloned(),
+                });
+                base_value
+            }
+        };
+        match value {
+            Some(value) => merged
+                .metadata
+                .properties
+                .insert(key.clone(), value.clone()),
+            None => merged.metadata.properties.remove(key),
+        };
+    }
+
+    if let Some(cycle) = find_cycle(&merged) {
+        conflicts.push(MergeConflict::Cycle {
+            steps: cycle
+                .iter()
+                .map(|id| StepRef::of(&merged.workflow.steps[id]))
+                .collect(),
+        });
+    }
+
+    merged.refresh_context_graph();
+
+    MergeResult { merged, conflicts }
+}
+
+/// Merge a step present on all three sides, field by field
+fn merge_step(
+    base: &WorkflowStep,
+    ours: &WorkflowStep,
+    theirs: &WorkflowStep,
+    conflicts: &mut Vec<MergeConflict>,
+) -> WorkflowStep {
+    let mut merged = ours.clone();
+    let step = StepRef::of(ours);
+
+    merged.name = merge_field(
+        &step,
+        "name",
+        &base.name,
+        &ours.name,
+        &theirs.name,
+        conflicts,
+    );
+    merged.description = merge_field(
+        &step,
+        "description",
+        &base.description,
+        &ours.description,
+        &theirs.description,
+        conflicts,
+    );
+    merged.step_type = merge_field(
+        &step,
+        "step_type",
+        &base.step_type,
+        &ours.step_type,
+        &theirs.step_type,
+        conflicts,
+    );
+    merged.estimated_duration_minutes = merge_field(
+        &step,
+        "estimated_duration_minutes",
+        &base.estimated_duration_minutes,
+        &ours.estimated_duration_minutes,
+        &theirs.estimated_duration_minutes,
+        conflicts,
+    );
+    merged.assigned_to = merge_field(
+        &step,
+        "assigned_to",
+        &base.assigned_to,
+        &ours.assigned_to,
+        &theirs.assigned_to,
+        conflicts,
+    );
+
+    let keys: BTreeSet<&String> = base
+        .config
+        .keys()
+        .chain(ours.config.keys())
+        .chain(theirs.config.keys())
+        .collect();
+    merged.config.clear();
+    for key in keys {
+        let value = merge_field(
+            &step,
+            &format!("config.{key}"),
+            &base.config.get(key),
+            &ours.config.get(key),
+            &theirs.config.get(key),
+            conflicts,
+        );
+        if let Some(value) = value {
+            merged.config.insert(key.clone(), value.clone());
+        }
+    }
+
+    merged
+}
+
+/// Three-way merge of one step field, recording a conflict and keeping the base value
+fn merge_field<T: PartialEq + Clone + Serialize>(
+    step: &StepRef,
+    field: &str,
+    base: &T,
+    ours: &T,
+    theirs: &T,
+    conflicts: &mut Vec<MergeConflict>,
+) -> T {
+    match merge3(Some(base), Some(ours), Some(theirs)) {
+        Some(Some(value)) => value.clone(),
+        _ => {
+            conflicts.push(MergeConflict::StepField {
+                step: step.clone(),
+                field: field.to_string(),
+                base: json(base),
+                ours: json(ours),
+                theirs: json(theirs),
+            });
+            base.clone()
+        }
+    }
+}
+
+/// Keep a step removed on one side only if the other side changed it
+fn keep_if_modified(
+    base: &WorkflowStep,
+    kept: &WorkflowStep,
+    removed_by: MergeSide,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<WorkflowStep> {
+    if field_changes(base, kept).is_empty() {
+        return None;
+    }
+    conflicts.push(MergeConflict::ModifiedAndRemoved {
+        step: StepRef::of(kept),
+        removed_by,
+    });
+    Some(kept.clone())
+}
+
+/// Three-way merge of a single value; `None` on conflict
+fn merge3<'a, T: PartialEq>(
+    base: Option<&'a T>,
+    ours: Option<&'a T>,
+    theirs: Option<&'a T>,
+) -> Option<Option<&'a T>> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+fn merge_tags(base: &[String], ours: &[String], theirs: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = ours
+        .iter()
+        .filter(|tag| theirs.contains(tag) || !base.contains(tag))
+        .cloned()
+        .collect();
+    for tag in theirs {
+        if !base.contains(tag) && !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    tags
+}
+
+/// Report dependencies a branch added on base steps that did not survive the
+/// merge
+///
+/// A dependency the base already had is dropped quietly, as removing its step
+/// removes it too.
+fn dangling_dependencies(
+    base: &WorkflowGraph,
+    graph: &WorkflowGraph,
+    pairs: &HashMap<StepId, (StepId, StepMatch)>,
+    ids: &HashMap<StepId, StepId>,
+    removed_by: MergeSide,
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    let base_ids: HashMap<StepId, StepId> = pairs
+        .iter()
+        .map(|(base_id, (id, _))| (*id, *base_id))
+        .collect();
+    for step in sorted_steps(graph) {
+        if !ids.contains_key(&step.id) {
+            continue;
+        }
+        let base_step = base_ids.get(&step.id).map(|id| &base.workflow.steps[id]);
+        let mut dangling: Vec<&WorkflowStep> = step
+            .dependencies
+            .iter()
+            .filter(|dep| !ids.contains_key(dep))
+            .filter(|dep| {
+                !base_step.is_some_and(|base_step| {
+                    base_ids
+                        .get(dep)
+                        .is_some_and(|base_dep| base_step.dependencies.contains(base_dep))
+                })
+            })
+            .filter_map(|dep| graph.workflow.steps.get(dep))
+            .collect();
+        dangling.sort_by(|a, b| a.name.cmp(&b.name));
+        for dependency in dangling {
+            conflicts.push(MergeConflict::DependsOnRemoved {
+                step: StepRef::of(step),
+                dependency: StepRef::of(dependency),
+                removed_by,
+            });
+        }
+    }
+}
+
+/// Dependency edges of a graph, translated to merged step IDs
+fn edges(graph: &WorkflowGraph, ids: &HashMap<StepId, StepId>) -> HashSet<(StepId, StepId)> {
+    graph
+        .workflow
+        .steps
+        .values()
+        .flat_map(|step| step.dependencies.iter().map(move |dep| (step.id, *dep)))
+        .filter_map(|(step, dep)| Some((*ids.get(&step)?, *ids.get(&dep)?)))
+        .collect()
+}
+
+/// Find a dependency cycle, returned as the steps along it
+fn find_cycle(graph: &WorkflowGraph) -> Option<Vec<StepId>> {
+    fn visit(
+        graph: &WorkflowGraph,
+        id: StepId,
+        path: &mut Vec<StepId>,
+        done: &mut HashSet<StepId>,
+    ) -> Option<Vec<StepId>> {
+        if let Some(start) = path.iter().position(|p| *p == id) {
+            return Some(path[start..].to_vec());
+        }
+        if done.contains(&id) {
+            return None;
+        }
+        path.push(id);
+        if let Some(step) = graph.workflow.steps.get(&id) {
+            for dep in &step.dependencies {
+                if let Some(cycle) = visit(graph, *dep, path, done) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        done.insert(id);
+        None
+    }
+
+    let mut done = HashSet::new();
+    sorted_steps(graph)
+        .into_iter()
+        .find_map(|step| visit(graph, step.id, &mut Vec::new(), &mut done))
+}
+
+fn json<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or(serde_json::Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cim_domain_workflow::value_objects::StepType;
+    use std::collections::HashMap;
+
+    fn base_graph() -> (WorkflowGraph, StepId, StepId) {
+        let mut graph =
+            WorkflowGraph::new("Merge Test".to_string(), "Testing merges".to_string()).unwrap();
+        let draft = graph
+            .add_step(
+                "Create Draft".to_string(),
+                "Write the draft".to_string(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                Some(120),
+                Some("content-author".to_string()),
+            )
+            .unwrap();
+        let review = graph
+            .add_step(
+                "Review".to_string(),
+                "Review the draft".to_string(),
+                StepType::Manual,
+                HashMap::new(),
+                vec![draft],
+                Some(60),
+                None,
+            )
+            .unwrap();
+        (graph, draft, review)
+    }
+
+    fn add_publish(graph: &mut WorkflowGraph, after: StepId) -> StepId {
+        let id = StepId::new();
+        graph.workflow.steps.insert(
+            id,
+            WorkflowStep {
+                id,
+                name: "Publish".to_string(),
+                dependencies: vec![after],
+                ..graph.workflow.steps[&after].clone()
+            },
+        );
+        id
+    }
+
+    #[test]
+    fn test_independent_changes_merge_cleanly() {
+        let (base, draft, review) = base_graph();
+
+        let mut ours = base.clone();
+        ours.workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .estimated_duration_minutes = Some(90);
+        ours.add_tag("ours".to_string());
+
+        let mut theirs = base.clone();
+        theirs.workflow.steps.get_mut(&review).unwrap().assigned_to = Some("editor".to_string());
+        let publish = add_publish(&mut theirs, review);
+
+        let result = merge(&base, &ours, &theirs);
+        assert!(result.is_clean(), "{:?}", result.conflicts);
+
+        let merged = &result.merged;
+        let review_step = &merged.workflow.steps[&review];
+        assert_eq!(review_step.estimated_duration_minutes, Some(90));
+        assert_eq!(review_step.assigned_to.as_deref(), Some("editor"));
+        assert_eq!(review_step.dependencies, vec![draft]);
+        assert_eq!(merged.workflow.steps[&publish].dependencies, vec![review]);
+        assert_eq!(merged.metadata.tags, vec!["ours".to_string()]);
+        assert!(merged.validate().is_ok());
+    }
+
+    #[test]
+    fn test_conflicting_edits_are_reported() {
+        let (base, _, review) = base_graph();
+
+        let mut ours = base.clone();
+        ours.workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .estimated_duration_minutes = Some(90);
+        ours.set_property("priority".to_string(), serde_json::json!("high"));
+
+        let mut theirs = base.clone();
+        theirs
+            .workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .estimated_duration_minutes = Some(30);
+        theirs.set_property("priority".to_string(), serde_json::json!("low"));
+
+        let result = merge(&base, &ours, &theirs);
+        assert_eq!(result.conflicts.len(), 2);
+        assert!(result.conflicts.iter().any(|c| matches!(
+            c,
+            MergeConflict::StepField { field, .. } if field == "estimated_duration_minutes"
+        )));
+        assert!(result
+            .conflicts
+            .iter()
+            .any(|c| matches!(c, MergeConflict::Property { key, .. } if key == "priority")));
+
+        // Conflicting fields keep the base value
+        assert_eq!(
+            result.merged.workflow.steps[&review].estimated_duration_minutes,
+            Some(60)
+        );
+        assert!(result.merged.get_property("priority").is_none());
+    }
+
+    #[test]
+    fn test_modified_and_removed() {
+        let (base, _, review) = base_graph();
+
+        let mut ours = base.clone();
+        ours.workflow.steps.remove(&review);
+
+        let mut theirs = base.clone();
+        theirs.workflow.steps.get_mut(&review).unwrap().assigned_to = Some("editor".to_string());
+
+        let result = merge(&base, &ours, &theirs);
+        assert_eq!(
+            result.conflicts,
+            vec![MergeConflict::ModifiedAndRemoved {
+                step: StepRef {
+                    id: review,
+                    name: "Review".to_string(),
+                },
+                removed_by: MergeSide::Ours,
+            }]
+        );
+        assert!(result.merged.workflow.steps.contains_key(&review));
+
+        // An unmodified step removed on one side is simply removed
+        let result = merge(&base, &ours, &base);
+        assert!(result.is_clean());
+        assert!(!result.merged.workflow.steps.contains_key(&review));
+    }
+
+    #[test]
+    fn test_merge_introducing_cycle_is_a_conflict() {
+        let (base, draft, review) = base_graph();
+
+        let mut ours = base.clone();
+        let publish = add_publish(&mut ours, review);
+
+        let mut theirs = base.clone();
+        let their_publish = add_publish(&mut theirs, review);
+        theirs
+            .workflow
+            .steps
+            .get_mut(&draft)
+            .unwrap()
+            .dependencies
+            .push(their_publish);
+
+        let result = merge(&base, &ours, &theirs);
+        let cycle = result
+            .conflicts
+            .iter()
+            .find_map(|c| match c {
+                MergeConflict::Cycle { steps } => Some(steps),
+                _ => None,
+            })
+            .expect("cycle conflict");
+        let ids: HashSet<StepId> = cycle.iter().map(|s| s.id).collect();
+        assert_eq!(ids, HashSet::from([draft, review, publish]));
+    }
+
+    #[test]
+    fn test_dependency_on_removed_step_is_a_conflict() {
+        let (base, _, review) = base_graph();
+
+        let mut ours = base.clone();
+        ours.workflow.steps.remove(&review);
+
+        let mut theirs = base.clone();
+        let publish = add_publish(&mut theirs, review);
+
+        let result = merge(&base, &ours, &theirs);
+        assert_eq!(
+            result.conflicts,
+            vec![MergeConflict::DependsOnRemoved {
+                step: StepRef {
+                    id: publish,
+                    name: "Publish".to_string(),
+                },
+                dependency: StepRef {
+                    id: review,
+                    name: "Review".to_string(),
+                },
+                removed_by: MergeSide::Ours,
+            }]
+        );
+        assert!(!result.merged.workflow.steps.contains_key(&review));
+        assert!(result.merged.workflow.steps[&publish]
+            .dependencies
+            .is_empty());
+
+        // The same edit on the other branch names the other side
+        let result = merge(&base, &theirs, &ours);
+        assert!(matches!(
+            result.conflicts.as_slice(),
+            [MergeConflict::DependsOnRemoved {
+                removed_by: MergeSide::Theirs,
+                ..
+            }]
+        ));
+    }
+}
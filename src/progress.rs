@@ -0,0 +1,293 @@
+//! Progress of a running workflow
+//!
+//! Completed and skipped steps count as done. A step that has started is
+//! expected to end its estimated duration after its start; until then only
+//! the rest of its estimate remains, and after that it is overdue.
+
+use crate::impact::{is_finished, remaining_minutes};
+use crate::{topology, WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Duration, Utc};
+use cim_domain_workflow::value_objects::{StepId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+
+/// How far along a workflow is at a moment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowProgress {
+    pub as_of: DateTime<Utc>,
+    pub total_steps: usize,
+    /// Completed or skipped steps
+    pub completed_steps: usize,
+    /// Started steps that are not done
+    pub running_steps: usize,
+    /// Estimated minutes of the done steps
+    pub completed_minutes: u64,
+    /// Estimated minutes of the steps not done
+    pub remaining_minutes: u64,
+    /// Share of estimated minutes done, or of steps when nothing is estimated
+    pub percent_complete: f64,
+    /// When the remaining critical path ends, given when steps started
+    pub eta: DateTime<Utc>,
+    /// Started steps running past their estimate, in topological order
+    pub overdue: Vec<OverdueStep>,
+}
+
+/// A step running longer than estimated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OverdueStep {
+    pub step_id: StepId,
+    pub started_at: DateTime<Utc>,
+    pub expected_by: DateTime<Utc>,
+    pub overdue_minutes: u64,
+}
+
+impl WorkflowGraph {
+    /// Progress at the current time of the graph's environment
+    pub fn progress(&self) -> WorkflowProgress {
+        self.progress_at(self.environment().now())
+    }
+
+    /// Progress at a moment
+    pub fn progress_at(&self, now: DateTime<Utc>) -> WorkflowProgress {
+        let steps = &self.workflow.steps;
+        let estimate =
+            |step: &WorkflowStep| u64::from(step.estimated_duration_minutes.unwrap_or(0));
+        let total_minutes: u64 = steps.values().map(estimate).sum();
+        let completed_steps = steps.values().filter(|step| is_finished(step)).count();
+        let completed_minutes: u64 = steps
+            .values()
+            .filter(|step| is_finished(step))
+            .map(estimate)
+            .sum();
+
+        let finish = topology::finish_times(self, |step| match expected_by(step) {
+            Some(expected) => minutes_until(now, expected),
+            None => remaining_minutes(step),
+        });
+        let end = finish.values().max().copied().unwrap_or(0);
+
+        let overdue = topology::topological_order(self)
+            .into_iter()
+            .filter_map(|id| {
+                let step = &steps[&id];
+                let expected = expected_by(step)?;
+                (expected < now).then(|| OverdueStep {
+                    step_id: id,
+                    started_at: step.started_at.expect("started steps have a start"),
+                    expected_by: expected,
+                    overdue_minutes: (now - expected).num_minutes() as u64,
+                })
+            })
+            .collect();
+
+        let percent_complete = if total_minutes > 0 {
+            completed_minutes as f64 * 100.0 / total_minutes as f64
+        } else if !steps.is_empty() {
+            completed_steps as f64 * 100.0 / steps.len() as f64
+        } else {
+            0.0
+        };
+
+        WorkflowProgress {
+            as_of: now,
+            total_steps: steps.len(),
+            completed_steps,
+            running_steps: steps
+                .values()
+                .filter(|step| !is_finished(step) && step.started_at.is_some())
+                .count(),
+            completed_minutes,
+            remaining_minutes: total_minutes - completed_minutes,
+            percent_complete,
+            eta: now + Duration::minutes(end as i64),
+            overdue,
+        }
+    }
+
+    /// Export as JSON with the progress at a moment under
+    /// `metadata.progress`
+    pub fn to_json_with_progress(&self, now: DateTime<Utc>) -> Result<String, WorkflowGraphError> {
+        let mut projection: serde_json::Value = serde_json::from_str(&self.to_json()?)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+        let progress = serde_json::to_value(self.progress_at(now))
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+
+        let metadata = &mut projection["metadata"];
+        if !metadata.is_object() {
+            *metadata = serde_json::json!({});
+        }
+        metadata["progress"] = progress;
+        serde_json::to_string_pretty(&projection)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))
+    }
+}
+
+/// When a started step with an estimate should end
+fn expected_by(step: &WorkflowStep) -> Option<DateTime<Utc>> {
+    if is_finished(step) {
+        return None;
+    }
+    let minutes = step.estimated_duration_minutes?;
+    Some(step.started_at? + Duration::minutes(minutes.into()))
+}
+
+/// Whole minutes from `now` until `at`, rounded up, or zero if past
+fn minutes_until(now: DateTime<Utc>, at: DateTime<Utc>) -> u64 {
+    let seconds = (at - now).num_seconds().max(0) as u64;
+    seconds.div_ceil(60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WorkflowEnvironment;
+    use cim_domain_workflow::value_objects::{StepStatus, StepType};
+    use std::collections::HashMap;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    /// draft(60) -> review(30) -> publish(30); translate(120) beside review
+    fn running() -> (WorkflowGraph, [StepId; 4]) {
+        let mut graph = WorkflowGraph::new("Docs".to_string(), "Progress".to_string()).unwrap();
+        let mut add = |name: &str, dependencies: Vec<StepId>, minutes: u32| {
+            graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    dependencies,
+                    Some(minutes),
+                    None,
+                )
+                .unwrap()
+        };
+        let draft = add("Draft", vec![], 60);
+        let review = add("Review", vec![draft], 30);
+        let translate = add("Translate", vec![draft], 120);
+        let publish = add("Publish", vec![review], 30);
+        graph.start(HashMap::new()).unwrap();
+
+        let step = graph.workflow.steps.get_mut(&draft).unwrap();
+        step.status = StepStatus::Completed;
+        step.started_at = Some(utc("2025-03-03T09:00:00Z"));
+        step.completed_at = Some(utc("2025-03-03T10:00:00Z"));
+        for (id, started) in [
+            (review, "2025-03-03T10:00:00Z"),
+            (translate, "2025-03-03T10:00:00Z"),
+        ] {
+            let step = graph.workflow.steps.get_mut(&id).unwrap();
+            step.status = StepStatus::InProgress;
+            step.started_at = Some(utc(started));
+        }
+        (graph, [draft, review, translate, publish])
+    }
+
+    #[test]
+    fn test_progress_counts_and_eta() {
+        let (graph, _) = running();
+        let now = utc("2025-03-03T10:20:00Z");
+
+        let progress = graph.progress_at(now);
+        assert_eq!(progress.total_steps, 4);
+        assert_eq!(progress.completed_steps, 1);
+        assert_eq!(progress.running_steps, 2);
+        assert_eq!(progress.completed_minutes, 60);
+        assert_eq!(progress.remaining_minutes, 180);
+        assert_eq!(progress.percent_complete, 25.0);
+        // Translate started at 10:00 and takes two hours
+        assert_eq!(progress.eta, utc("2025-03-03T12:00:00Z"));
+        assert!(progress.overdue.is_empty());
+    }
+
+    #[test]
+    fn test_overdue_steps() {
+        let (graph, [_, review, _, _]) = running();
+        let now = utc("2025-03-03T10:45:00Z");
+
+        let progress = graph.progress_at(now);
+        assert_eq!(progress.overdue.len(), 1);
+        assert_eq!(progress.overdue[0].step_id, review);
+        assert_eq!(progress.overdue[0].expected_by, utc("2025-03-03T10:30:00Z"));
+        assert_eq!(progress.overdue[0].overdue_minutes, 15);
+        // Review is late, so publishing can at best finish 30 minutes from now
+        assert_eq!(progress.eta, utc("2025-03-03T12:00:00Z"));
+        assert_eq!(
+            graph.progress_at(utc("2025-03-03T11:50:00Z")).eta,
+            utc("2025-03-03T12:20:00Z")
+        );
+    }
+
+    #[test]
+    fn test_progress_in_json_projection() {
+        let (mut graph, _) = running();
+        let now = utc("2025-03-03T10:20:00Z");
+        graph.set_environment(WorkflowEnvironment::deterministic(now));
+        assert_eq!(graph.progress(), graph.progress_at(now));
+
+        let json = graph
+            .to_json_with_progress(utc("2025-03-03T10:20:00Z"))
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["metadata"]["progress"]["completed_steps"], 1);
+        assert_eq!(value["metadata"]["progress"]["percent_complete"], 25.0);
+    }
+
+    #[test]
+    fn test_progress_over_a_dependency_cycle() {
+        let (mut graph, [_, review, _, publish]) = running();
+        graph
+            .workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .dependencies
+            .push(publish);
+
+        let progress = graph.progress_at(utc("2025-03-03T10:20:00Z"));
+        assert_eq!(progress.eta, utc("2025-03-03T12:00:00Z"));
+        let late = graph.progress_at(utc("2025-03-03T10:45:00Z"));
+        assert_eq!(late.overdue.len(), 1);
+        assert_eq!(late.overdue[0].step_id, review);
+    }
+
+    #[test]
+    fn test_progress_without_estimates() {
+        let now = utc("2025-03-03T10:00:00Z");
+        let empty = WorkflowGraph::new("Empty".to_string(), String::new()).unwrap();
+        let progress = empty.progress_at(now);
+        assert_eq!(progress.percent_complete, 0.0);
+        assert_eq!(progress.eta, now);
+
+        let mut graph = WorkflowGraph::new("Unestimated".to_string(), String::new()).unwrap();
+        let mut ids = Vec::new();
+        for name in ["Call", "Write"] {
+            let id = graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    Vec::new(),
+                    None,
+                    None,
+                )
+                .unwrap();
+            ids.push(id);
+        }
+        let call = graph.workflow.steps.get_mut(&ids[0]).unwrap();
+        call.status = StepStatus::Completed;
+        // A start in the future, e.g. from clock skew, is neither running late
+        // nor counted as elapsed
+        let write = graph.workflow.steps.get_mut(&ids[1]).unwrap();
+        write.status = StepStatus::InProgress;
+        write.started_at = Some(now + Duration::minutes(5));
+        write.estimated_duration_minutes = Some(0);
+
+        let progress = graph.progress_at(now);
+        assert_eq!(progress.percent_complete, 50.0);
+        assert_eq!(progress.eta, now + Duration::minutes(5));
+        assert!(progress.overdue.is_empty());
+    }
+}
@@ -0,0 +1,407 @@
+//! Composable queries over a workflow's steps
+//!
+//! A [`StepQuery`] is a predicate tree. Leaves test a step's fields or its
+//! position in the dependency graph, and [`StepQuery::and`],
+//! [`StepQuery::or`] and `!` combine them:
+//!
+//! ```ignore
+//! let query = StepQuery::assigned_to("alice")
+//!     .and(StepQuery::duration_between(Some(30), None))
+//!     .and(!StepQuery::descendants_of(review));
+//! let steps = graph.query(&query);
+//! ```
+
+use crate::{topology, WorkflowGraph};
+use cim_domain_workflow::value_objects::{StepId, StepStatus, StepType, WorkflowStep};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// A predicate over the steps of a workflow
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepQuery {
+    /// Every step
+    All,
+    /// Steps assigned to this assignee
+    AssignedTo(String),
+    /// Steps without an assignee
+    Unassigned,
+    /// Steps in this status
+    Status(StepStatus),
+    /// Steps of this type
+    Type(StepType),
+    /// Steps with this config key, whatever its value
+    HasConfig(String),
+    /// Steps whose config key holds this value, or an array containing it
+    ConfigEquals(String, Value),
+    /// Steps with an estimated duration within the inclusive bounds
+    DurationBetween { min: Option<u32>, max: Option<u32> },
+    /// Steps whose name matches a glob pattern using `*` and `?`
+    NameMatches(String),
+    /// Steps the given step transitively depends on
+    AncestorsOf(StepId),
+    /// Steps that transitively depend on the given step
+    DescendantsOf(StepId),
+    /// Steps no other step depends on
+    NoDependents,
+    /// Steps without dependencies
+    NoDependencies,
+    /// Steps matching every query
+    And(Vec<StepQuery>),
+    /// Steps matching any query
+    Or(Vec<StepQuery>),
+    /// Steps not matching the query
+    Not(Box<StepQuery>),
+}
+
+impl StepQuery {
+    /// Steps assigned to this assignee
+    pub fn assigned_to(assignee: impl Into<String>) -> Self {
+        StepQuery::AssignedTo(assignee.into())
+    }
+
+    /// Steps with this config key
+    pub fn has_config(key: impl Into<String>) -> Self {
+        StepQuery::HasConfig(key.into())
+    }
+
+    /// Steps whose config key holds this value
+    pub fn config_equals(key: impl Into<String>, value: impl Into<Value>) -> Self {
+        StepQuery::ConfigEquals(key.into(), value.into())
+    }
+
+    /// Steps with an estimated duration within the inclusive bounds
+    pub fn duration_between(min: Option<u32>, max: Option<u32>) -> Self {
+        StepQuery::DurationBetween { min, max }
+    }
+
+    /// Steps whose name matches a glob pattern
+    pub fn name_matches(pattern: impl Into<String>) -> Self {
+        StepQuery::NameMatches(pattern.into())
+    }
+
+    /// Steps the given step transitively depends on
+    pub fn ancestors_of(step_id: StepId) -> Self {
+        StepQuery::AncestorsOf(step_id)
+    }
+
+    /// Steps that transitively depend on the given step
+    pub fn descendants_of(step_id: StepId) -> Self {
+        StepQuery::DescendantsOf(step_id)
+    }
+
+    /// Steps matching both queries
+    pub fn and(self, other: StepQuery) -> Self {
+        match self {
+            StepQuery::And(mut queries) => {
+                queries.push(other);
+                StepQuery::And(queries)
+            }
+            query => StepQuery::And(vec![query, other]),
+        }
+    }
+
+    /// Steps matching either query
+    pub fn or(self, other: StepQuery) -> Self {
+        match self {
+            StepQuery::Or(mut queries) => {
+                queries.push(other);
+                StepQuery::Or(queries)
+            }
+            query => StepQuery::Or(vec![query, other]),
+        }
+    }
+
+    /// IDs of the matching steps in topological order
+    pub fn run(&self, graph: &WorkflowGraph) -> Vec<StepId> {
+        let matched = self.evaluate(graph);
+        topology::topological_order(graph)
+            .into_iter()
+            .filter(|id| matched.contains(id))
+            .collect()
+    }
+
+    fn evaluate(&self, graph: &WorkflowGraph) -> HashSet<StepId> {
+        let steps = &graph.workflow.steps;
+        let select = |predicate: &dyn Fn(&WorkflowStep) -> bool| {
+            steps
+                .values()
+                .filter(|step| predicate(step))
+                .map(|step| step.id)
+                .collect()
+        };
+
+        match self {
+            StepQuery::All => steps.keys().copied().collect(),
+            StepQuery::AssignedTo(assignee) => {
+                select(&|step| step.assigned_to.as_deref() == Some(assignee.as_str()))
+            }
+            StepQuery::Unassigned => select(&|step| step.assigned_to.is_none()),
+            StepQuery::Status(status) => select(&|step| step.status == *status),
+            StepQuery::Type(step_type) => select(&|step| step.step_type == *step_type),
+            StepQuery::HasConfig(key) => select(&|step| step.config.contains_key(key)),
+            StepQuery::ConfigEquals(key, value) => select(&|step| match step.config.get(key) {
+                Some(Value::Array(items)) if !value.is_array() => items.contains(value),
+                Some(found) => found == value,
+                None => false,
+            }),
+            StepQuery::DurationBetween { min, max } => select(&|step| {
+                step.estimated_duration_minutes.is_some_and(|minutes| {
+                    min.is_none_or(|min| minutes >= min) && max.is_none_or(|max| minutes <= max)
+                })
+            }),
+            StepQuery::NameMatches(pattern) => select(&|step| glob_matches(pattern, &step.name)),
+            StepQuery::AncestorsOf(step_id) => topology::ancestors(graph, *step_id),
+            StepQuery::DescendantsOf(step_id) => topology::descendants(graph, *step_id),
+            StepQuery::NoDependents => topology::dependents(graph)
+                .into_iter()
+                .filter(|(_, dependents)| dependents.is_empty())
+                .map(|(id, _)| id)
+                .collect(),
+            StepQuery::NoDependencies => select(&|step| step.dependencies.is_empty()),
+            StepQuery::And(queries) => {
+                let mut queries = queries.iter();
+                let Some(first) = queries.next() else {
+                    return steps.keys().copied().collect();
+                };
+                let mut matched = first.evaluate(graph);
+                for query in queries {
+                    let next = query.evaluate(graph);
+                    matched.retain(|id| next.contains(id));
+                }
+                matched
+            }
+            StepQuery::Or(queries) => queries
+                .iter()
+                .flat_map(|query| query.evaluate(graph))
+                .collect(),
+            StepQuery::Not(query) => {
+                let excluded = query.evaluate(graph);
+                steps
+                    .keys()
+                    .filter(|id| !excluded.contains(id))
+                    .copied()
+                    .collect()
+            }
+        }
+    }
+}
+
+impl std::ops::Not for StepQuery {
+    type Output = StepQuery;
+
+    fn not(self) -> StepQuery {
+        StepQuery::Not(Box::new(self))
+    }
+}
+
+impl WorkflowGraph {
+    /// IDs of the steps matching a query, in topological order
+    pub fn query(&self, query: &StepQuery) -> Vec<StepId> {
+        query.run(self)
+    }
+}
+
+/// Match text against a pattern where `*` is any run of characters and `?`
+/// is one character
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn step(
+        graph: &mut WorkflowGraph,
+        name: &str,
+        dependencies: Vec<StepId>,
+        minutes: u32,
+        assignee: Option<&str>,
+        config: HashMap<String, Value>,
+    ) -> StepId {
+        graph
+            .add_step(
+                name.to_string(),
+                String::new(),
+                StepType::Manual,
+                config,
+                dependencies,
+                Some(minutes),
+                assignee.map(str::to_string),
+            )
+            .unwrap()
+    }
+
+    /// draft -> review -> publish, with translate also feeding publish
+    fn pipeline() -> (WorkflowGraph, [StepId; 4]) {
+        let mut graph = WorkflowGraph::new("Docs".to_string(), "Publishing".to_string()).unwrap();
+        let tagged = HashMap::from([("labels".to_string(), serde_json::json!(["legal", "docs"]))]);
+        let draft = step(
+            &mut graph,
+            "Draft",
+            vec![],
+            60,
+            Some("alice"),
+            HashMap::new(),
+        );
+        let review = step(&mut graph, "Review", vec![draft], 30, Some("bob"), tagged);
+        let translate = step(
+            &mut graph,
+            "Translate",
+            vec![draft],
+            120,
+            Some("alice"),
+            HashMap::new(),
+        );
+        let publish = step(
+            &mut graph,
+            "Publish",
+            vec![review, translate],
+            5,
+            None,
+            HashMap::new(),
+        );
+        (graph, [draft, review, translate, publish])
+    }
+
+    #[test]
+    fn test_field_predicates() {
+        let (graph, [draft, review, translate, publish]) = pipeline();
+
+        assert_eq!(
+            graph.query(&StepQuery::assigned_to("alice")),
+            vec![draft, translate]
+        );
+        assert_eq!(graph.query(&StepQuery::Unassigned), vec![publish]);
+        assert_eq!(
+            graph.query(&StepQuery::duration_between(Some(30), Some(60))),
+            vec![draft, review]
+        );
+        assert_eq!(
+            graph.query(&StepQuery::config_equals("labels", "legal")),
+            vec![review]
+        );
+        assert_eq!(graph.query(&StepQuery::name_matches("*e?i*")), vec![review]);
+        assert_eq!(graph.query(&StepQuery::name_matches("P*")), vec![publish]);
+    }
+
+    #[test]
+    fn test_graph_predicates_in_topological_order() {
+        let (graph, [draft, review, translate, publish]) = pipeline();
+
+        assert_eq!(
+            graph.query(&StepQuery::All),
+            vec![draft, review, translate, publish]
+        );
+        assert_eq!(
+            graph.query(&StepQuery::ancestors_of(publish)),
+            vec![draft, review, translate]
+        );
+        assert_eq!(
+            graph.query(&StepQuery::descendants_of(review)),
+            vec![publish]
+        );
+        assert_eq!(graph.query(&StepQuery::NoDependents), vec![publish]);
+        assert_eq!(graph.query(&StepQuery::NoDependencies), vec![draft]);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let (graph, [draft, review, translate, publish]) = pipeline();
+
+        let query = StepQuery::assigned_to("alice").and(!StepQuery::NoDependencies);
+        assert_eq!(graph.query(&query), vec![translate]);
+
+        let query = StepQuery::assigned_to("bob").or(StepQuery::NoDependents);
+        assert_eq!(graph.query(&query), vec![review, publish]);
+
+        let query = !StepQuery::descendants_of(draft);
+        assert_eq!(graph.query(&query), vec![draft]);
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*c", "abbbc"));
+        assert!(glob_matches("a?c", "abc"));
+        assert!(!glob_matches("a?c", "ac"));
+        assert!(!glob_matches("a*d", "abc"));
+    }
+
+    #[test]
+    fn test_glob_with_several_stars() {
+        assert!(glob_matches("**", ""));
+        assert!(glob_matches("*a*a*", "banana"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(glob_matches("*?", "é"));
+        assert!(glob_matches("*x*", "x"));
+        assert!(!glob_matches("*?", ""));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(!glob_matches("*a*a*a*a*", "banana"));
+    }
+
+    #[test]
+    fn test_queries_over_a_dependency_cycle() {
+        let (mut graph, [draft, review, translate, publish]) = pipeline();
+        graph
+            .workflow
+            .steps
+            .get_mut(&draft)
+            .unwrap()
+            .dependencies
+            .push(publish);
+
+        assert_eq!(
+            graph.query(&StepQuery::All),
+            vec![draft, publish, review, translate]
+        );
+        assert_eq!(
+            graph.query(&StepQuery::ancestors_of(review)),
+            vec![draft, publish, translate]
+        );
+        assert_eq!(
+            graph.query(&StepQuery::descendants_of(review)),
+            vec![draft, publish, translate]
+        );
+        assert!(graph.query(&StepQuery::NoDependencies).is_empty());
+        assert!(graph.query(&StepQuery::NoDependents).is_empty());
+        assert!(graph
+            .query(&StepQuery::ancestors_of(StepId::new()))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_empty_combinators() {
+        let (graph, steps) = pipeline();
+
+        assert_eq!(graph.query(&StepQuery::And(Vec::new())).len(), steps.len());
+        assert!(graph.query(&StepQuery::Or(Vec::new())).is_empty());
+        assert!(graph.query(&!StepQuery::All).is_empty());
+    }
+}
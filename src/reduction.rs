@@ -0,0 +1,236 @@
+//! Detection and removal of redundant dependencies
+//!
+//! A dependency is redundant when another path already orders the two
+//! steps, e.g. `C` depending on `A` when it also depends on `B` and `B`
+//! depends on `A`. Removing every redundant dependency gives the transitive
+//! reduction, which runs the steps in the same order with fewer edges.
+
+use crate::diff::StepRef;
+use crate::{topology, WorkflowGraph};
+use cim_domain_workflow::value_objects::{StepId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// A dependency already implied by other dependencies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedundantDependency {
+    pub step: StepRef,
+    pub depends_on: StepRef,
+    /// Steps on one path from `depends_on` to `step` that implies the
+    /// dependency, in execution order
+    pub via: Vec<StepRef>,
+}
+
+/// Dependencies removed by [`WorkflowGraph::transitive_reduction`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReductionReport {
+    pub removed: Vec<RedundantDependency>,
+}
+
+impl ReductionReport {
+    /// Whether nothing was removed
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+    }
+}
+
+impl WorkflowGraph {
+    /// Dependencies implied by other paths, without changing the graph
+    ///
+    /// Results are ordered by the dependent step's topological position.
+    /// Each dependency is checked with the ones reported before it already
+    /// removed, so in a cycle, where two dependencies can imply each other,
+    /// only one of them is reported.
+    pub fn redundant_dependencies(&self) -> Vec<RedundantDependency> {
+        let steps = &self.workflow.steps;
+        let mut remaining = steps.clone();
+        let mut redundant = Vec::new();
+
+        for id in topology::topological_order(self) {
+            let step = &steps[&id];
+            for dependency in &step.dependencies {
+                let path = remaining[&id]
+                    .dependencies
+                    .iter()
+                    .filter(|other| *other != dependency)
+                    .find_map(|other| dependency_path(&remaining, *other, *dependency));
+                if let Some(mut path) = path {
+                    if let Some(step) = remaining.get_mut(&id) {
+                        step.dependencies.retain(|id| id != dependency);
+                    }
+                    // The path runs from a direct dependency back to the
+                    // implied one; report it in execution order without it
+                    path.pop();
+                    path.reverse();
+                    redundant.push(RedundantDependency {
+                        step: StepRef::of(step),
+                        depends_on: StepRef::of(&steps[dependency]),
+                        via: path.iter().map(|id| StepRef::of(&steps[id])).collect(),
+                    });
+                }
+            }
+        }
+        redundant
+    }
+
+    /// Remove every redundant dependency, returning what was removed
+    ///
+    /// Use [`redundant_dependencies`](Self::redundant_dependencies) to see
+    /// the same report without applying it. The domain has no event for
+    /// removing a dependency, so nothing is recorded: replaying the
+    /// workflow's event log brings the removed dependencies back.
+    pub fn transitive_reduction(&mut self) -> ReductionReport {
+        let removed = self.redundant_dependencies();
+        if removed.is_empty() {
+            return ReductionReport::default();
+        }
+
+        for edge in &removed {
+            if let Some(step) = self.workflow.steps.get_mut(&edge.step.id) {
+                step.dependencies.retain(|id| *id != edge.depends_on.id);
+            }
+        }
+        self.refresh_context_graph();
+        ReductionReport { removed }
+    }
+}
+
+/// Shortest chain of dependencies leading from `from` to `to`, inclusive
+fn dependency_path(
+    steps: &HashMap<StepId, WorkflowStep>,
+    from: StepId,
+    to: StepId,
+) -> Option<Vec<StepId>> {
+    let mut parents: HashMap<StepId, StepId> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(id) = queue.pop_front() {
+        if id == to {
+            let mut path = vec![to];
+            while let Some(parent) = parents.get(path.last().expect("path is not empty")) {
+                path.push(*parent);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for dependency in steps.get(&id).map_or(&[][..], |s| &s.dependencies) {
+            if *dependency != from && !parents.contains_key(dependency) {
+                parents.insert(*dependency, id);
+                queue.push_back(*dependency);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventStore, InMemoryEventStore};
+    use cim_domain_workflow::value_objects::StepType;
+
+    fn step(graph: &mut WorkflowGraph, name: &str, dependencies: Vec<StepId>) -> StepId {
+        graph
+            .add_step(
+                name.to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                dependencies,
+                Some(10),
+                None,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lint_reports_implied_dependencies() {
+        let mut graph = WorkflowGraph::new("Review".to_string(), "Lint".to_string()).unwrap();
+        let a = step(&mut graph, "A", vec![]);
+        let b = step(&mut graph, "B", vec![a]);
+        let c = step(&mut graph, "C", vec![b]);
+        let d = step(&mut graph, "D", vec![a, c]);
+
+        let redundant = graph.redundant_dependencies();
+        assert_eq!(redundant.len(), 1);
+        assert_eq!(redundant[0].step.id, d);
+        assert_eq!(redundant[0].depends_on.id, a);
+        let via: Vec<StepId> = redundant[0].via.iter().map(|s| s.id).collect();
+        assert_eq!(via, vec![b, c]);
+
+        // The lint leaves the graph alone
+        assert_eq!(graph.statistics().dependency_edges, 4);
+    }
+
+    #[test]
+    fn test_transitive_reduction_in_place() {
+        let mut graph = WorkflowGraph::new("Review".to_string(), "Reduce".to_string()).unwrap();
+        let a = step(&mut graph, "A", vec![]);
+        let b = step(&mut graph, "B", vec![a]);
+        let c = step(&mut graph, "C", vec![a, b]);
+        let d = step(&mut graph, "D", vec![a, b, c]);
+        let hash = graph.content_hash().to_string();
+
+        let report = graph.transitive_reduction();
+        assert_eq!(report.removed.len(), 3);
+        assert_eq!(graph.workflow.steps[&c].dependencies, vec![b]);
+        assert_eq!(graph.workflow.steps[&d].dependencies, vec![c]);
+        assert_eq!(graph.statistics().dependency_edges, 3);
+        assert_ne!(graph.content_hash(), hash);
+        assert_eq!(graph.ancestors(d), vec![a, b, c]);
+
+        assert!(graph.transitive_reduction().is_empty());
+    }
+
+    #[test]
+    fn test_parallel_paths_are_kept() {
+        let mut graph = WorkflowGraph::new("Review".to_string(), "Diamond".to_string()).unwrap();
+        let a = step(&mut graph, "A", vec![]);
+        let b = step(&mut graph, "B", vec![a]);
+        let c = step(&mut graph, "C", vec![a]);
+        step(&mut graph, "D", vec![b, c]);
+
+        assert!(graph.redundant_dependencies().is_empty());
+    }
+
+    #[test]
+    fn test_cycle_keeps_one_of_two_mutually_implied_dependencies() {
+        let mut graph = WorkflowGraph::new("Review".to_string(), "Cycle".to_string()).unwrap();
+        let a = step(&mut graph, "A", vec![]);
+        let b = step(&mut graph, "B", vec![a]);
+        let c = step(&mut graph, "C", vec![b]);
+        let d = step(&mut graph, "D", vec![a, b]);
+        graph
+            .workflow
+            .steps
+            .get_mut(&a)
+            .unwrap()
+            .dependencies
+            .push(c);
+        let ancestors = graph.ancestors(d);
+
+        // A and B each reach the other through the cycle
+        let report = graph.transitive_reduction();
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(graph.workflow.steps[&d].dependencies, vec![b]);
+        assert_eq!(graph.ancestors(d), ancestors);
+        assert!(graph.redundant_dependencies().is_empty());
+    }
+
+    #[test]
+    fn test_reduction_is_not_evented() {
+        let mut graph = WorkflowGraph::new("Review".to_string(), "Replay".to_string()).unwrap();
+        let a = step(&mut graph, "A", vec![]);
+        let b = step(&mut graph, "B", vec![a]);
+        let c = step(&mut graph, "C", vec![a, b]);
+        let mut store = InMemoryEventStore::new();
+        store.save(&mut graph).unwrap();
+
+        assert_eq!(graph.transitive_reduction().removed.len(), 1);
+        assert!(store.save(&mut graph).unwrap().is_empty());
+
+        let replayed = store.replay(graph.id()).unwrap();
+        assert_eq!(replayed.workflow.steps[&c].dependencies, vec![a, b]);
+        assert_eq!(graph.workflow.steps[&c].dependencies, vec![b]);
+    }
+}
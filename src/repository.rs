@@ -0,0 +1,516 @@
+//! Storage for workflow graph instances
+//!
+//! A [`WorkflowRepository`] keeps each workflow together with a revision
+//! number. Saving requires the revision the caller loaded; if another save
+//! happened in between, the save fails with
+//! [`WorkflowGraphError::ConcurrencyConflict`] instead of overwriting it.
+//!
+//! Every backend persists the same record: the workflow's state, graph
+//! metadata, step config schema registry, authorization policy and audit
+//! log. Uncommitted events are not persisted, so a loaded graph is the same
+//! whichever backend stored it.
+
+use crate::authorization::{AuditEntry, AuthorizationPolicy};
+use crate::schema::StepConfigSchemaRegistry;
+use crate::snapshot::SnapshotState;
+use crate::{WorkflowGraph, WorkflowGraphError, WorkflowGraphMetadata};
+use cim_domain_workflow::value_objects::{WorkflowId, WorkflowStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteWorkflowRepository;
+
+/// Revision of a workflow that has never been saved
+pub const NEW_REVISION: u64 = 0;
+
+/// How long a file repository save waits for another save of the same
+/// workflow to release its lock
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Storage for workflow graphs with optimistic concurrency
+pub trait WorkflowRepository {
+    /// Load a workflow and its current revision
+    fn load(&self, workflow_id: WorkflowId) -> Result<(WorkflowGraph, u64), WorkflowGraphError>;
+
+    /// Save a workflow loaded at `expected_revision`, returning the new
+    /// revision
+    ///
+    /// Use [`NEW_REVISION`] for workflows that have not been saved before.
+    fn save(
+        &mut self,
+        graph: &WorkflowGraph,
+        expected_revision: u64,
+    ) -> Result<u64, WorkflowGraphError>;
+
+    /// Delete a workflow
+    fn delete(&mut self, workflow_id: WorkflowId) -> Result<(), WorkflowGraphError>;
+
+    /// IDs of all stored workflows
+    fn list(&self) -> Result<Vec<WorkflowId>, WorkflowGraphError>;
+
+    /// IDs of workflows carrying a tag
+    fn find_by_tag(&self, tag: &str) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        self.find(&|graph| graph.metadata.tags.iter().any(|t| t == tag))
+    }
+
+    /// IDs of workflows in a status
+    fn find_by_status(
+        &self,
+        status: &WorkflowStatus,
+    ) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        self.find(&|graph| graph.status() == status)
+    }
+
+    /// IDs of workflows matching a predicate
+    fn find(
+        &self,
+        predicate: &dyn Fn(&WorkflowGraph) -> bool,
+    ) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        let mut found = Vec::new();
+        for workflow_id in self.list()? {
+            let (graph, _) = self.load(workflow_id)?;
+            if predicate(&graph) {
+                found.push(workflow_id);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Persisted form of a workflow graph
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WorkflowRecord {
+    revision: u64,
+    state: SnapshotState,
+    metadata: WorkflowGraphMetadata,
+    #[serde(default)]
+    schema_registry: StepConfigSchemaRegistry,
+    #[serde(default)]
+    authorization_policy: AuthorizationPolicy,
+    #[serde(default)]
+    audit_log: Vec<AuditEntry>,
+}
+
+impl WorkflowRecord {
+    fn new(graph: &WorkflowGraph, revision: u64) -> Self {
+        Self {
+            revision,
+            state: SnapshotState::capture(graph),
+            metadata: graph.metadata.clone(),
+            schema_registry: graph.schema_registry.clone(),
+            authorization_policy: graph.authorization_policy.clone(),
+            audit_log: graph.audit_log.clone(),
+        }
+    }
+
+    fn to_graph(&self) -> Result<WorkflowGraph, WorkflowGraphError> {
+        let mut graph = self.state.to_graph()?;
+        graph.metadata = self.metadata.clone();
+        graph.schema_registry = self.schema_registry.clone();
+        graph.authorization_policy = self.authorization_policy.clone();
+        graph.audit_log = self.audit_log.clone();
+        Ok(graph)
+    }
+}
+
+fn check_revision(
+    workflow_id: WorkflowId,
+    expected: u64,
+    actual: u64,
+) -> Result<(), WorkflowGraphError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(WorkflowGraphError::ConcurrencyConflict {
+            workflow_id: workflow_id.as_uuid().to_string(),
+            expected,
+            actual,
+        })
+    }
+}
+
+fn not_found(workflow_id: WorkflowId) -> WorkflowGraphError {
+    WorkflowGraphError::WorkflowNotFound(workflow_id.as_uuid().to_string())
+}
+
+/// Workflow graphs held in memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryWorkflowRepository {
+    records: HashMap<WorkflowId, WorkflowRecord>,
+}
+
+impl InMemoryWorkflowRepository {
+    /// Create an empty repository
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored workflows
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Whether the repository is empty
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl WorkflowRepository for InMemoryWorkflowRepository {
+    fn load(&self, workflow_id: WorkflowId) -> Result<(WorkflowGraph, u64), WorkflowGraphError> {
+        let record = self
+            .records
+            .get(&workflow_id)
+            .ok_or_else(|| not_found(workflow_id))?;
+        Ok((record.to_graph()?, record.revision))
+    }
+
+    fn save(
+        &mut self,
+        graph: &WorkflowGraph,
+        expected_revision: u64,
+    ) -> Result<u64, WorkflowGraphError> {
+        let current = self
+            .records
+            .get(&graph.id())
+            .map_or(NEW_REVISION, |record| record.revision);
+        check_revision(graph.id(), expected_revision, current)?;
+
+        let record = WorkflowRecord::new(graph, current + 1);
+        let revision = record.revision;
+        self.records.insert(graph.id(), record);
+        Ok(revision)
+    }
+
+    fn delete(&mut self, workflow_id: WorkflowId) -> Result<(), WorkflowGraphError> {
+        self.records
+            .remove(&workflow_id)
+            .map(|_| ())
+            .ok_or_else(|| not_found(workflow_id))
+    }
+
+    fn list(&self) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        Ok(self.records.keys().copied().collect())
+    }
+}
+
+/// Workflow graphs stored as one JSON file each in a directory
+///
+/// A save or delete holds an advisory lock on a file beside the workflow's
+/// file while it checks the revision and writes, so concurrent saves through
+/// separate repositories, even in separate processes, cannot both succeed
+/// from the same revision.
+#[derive(Debug, Clone)]
+pub struct FileWorkflowRepository {
+    directory: PathBuf,
+}
+
+impl FileWorkflowRepository {
+    /// Use a directory, creating it if needed
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, WorkflowGraphError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).map_err(io_error)?;
+        Ok(Self { directory })
+    }
+
+    /// Directory holding the workflow files
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn path_of(&self, workflow_id: WorkflowId) -> PathBuf {
+        self.directory
+            .join(format!("{}.json", workflow_id.as_uuid()))
+    }
+
+    fn read(&self, workflow_id: WorkflowId) -> Result<Option<WorkflowRecord>, WorkflowGraphError> {
+        let path = self.path_of(workflow_id);
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(e)),
+        };
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| WorkflowGraphError::SerializationError(format!("{}: {e}", path.display())))
+    }
+}
+
+impl WorkflowRepository for FileWorkflowRepository {
+    fn load(&self, workflow_id: WorkflowId) -> Result<(WorkflowGraph, u64), WorkflowGraphError> {
+        let record = self
+            .read(workflow_id)?
+            .ok_or_else(|| not_found(workflow_id))?;
+        Ok((record.to_graph()?, record.revision))
+    }
+
+    fn save(
+        &mut self,
+        graph: &WorkflowGraph,
+        expected_revision: u64,
+    ) -> Result<u64, WorkflowGraphError> {
+        let path = self.path_of(graph.id());
+        let _lock = LockFile::acquire(path.with_extension("json.lock"))?;
+        let current = self
+            .read(graph.id())?
+            .map_or(NEW_REVISION, |record| record.revision);
+        check_revision(graph.id(), expected_revision, current)?;
+
+        let record = WorkflowRecord::new(graph, current + 1);
+        let json = serde_json::to_string_pretty(&record)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+
+        // Write beside the target and rename, so readers never see a partial file
+        let staged = path.with_extension("json.tmp");
+        fs::write(&staged, json).map_err(io_error)?;
+        fs::rename(&staged, &path).map_err(io_error)?;
+        Ok(record.revision)
+    }
+
+    fn delete(&mut self, workflow_id: WorkflowId) -> Result<(), WorkflowGraphError> {
+        let path = self.path_of(workflow_id);
+        let _lock = LockFile::acquire(path.with_extension("json.lock"))?;
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(not_found(workflow_id)),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.directory).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let stem = path.file_stem().and_then(|stem| stem.to_str());
+            if let Some(workflow_id) = stem.and_then(parse_workflow_id) {
+                ids.push(workflow_id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+/// Advisory lock on a file beside a workflow's file, held for the duration
+/// of a save or delete
+///
+/// The operating system releases the lock when the file is closed, including
+/// when the process dies, so a crash never leaves a workflow locked. The lock
+/// file itself stays in place; removing it could let two processes lock
+/// different files of the same name.
+struct LockFile {
+    _file: fs::File,
+}
+
+impl LockFile {
+    /// Lock the file, waiting up to [`LOCK_TIMEOUT`] while another save
+    /// holds it
+    fn acquire(path: PathBuf) -> Result<Self, WorkflowGraphError> {
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(fs::TryLockError::WouldBlock) => {
+                    if started.elapsed() >= LOCK_TIMEOUT {
+                        return Err(WorkflowGraphError::InvalidOperation(format!(
+                            "Workflow repository lock {} is still held",
+                            path.display()
+                        )));
+                    }
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(io_error(e)),
+            }
+        }
+    }
+}
+
+/// Parse the string form of a workflow ID
+pub(crate) fn parse_workflow_id(text: &str) -> Option<WorkflowId> {
+    serde_json::from_value(serde_json::Value::String(text.to_string())).ok()
+}
+
+fn io_error(error: std::io::Error) -> WorkflowGraphError {
+    WorkflowGraphError::InvalidOperation(format!("Workflow repository I/O failed: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Grant, Operation};
+    use cim_domain_workflow::value_objects::StepType;
+
+    pub(super) fn tagged_graph(tag: &str) -> WorkflowGraph {
+        let mut graph = WorkflowGraph::new("Stored".to_string(), "Persisted".to_string()).unwrap();
+        graph
+            .add_step(
+                "Only".to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                Some(5),
+                None,
+            )
+            .unwrap();
+        graph.add_tag(tag.to_string());
+        graph
+    }
+
+    /// Shared behaviour every backend must provide
+    pub(super) fn exercise(repository: &mut impl WorkflowRepository) {
+        let mut graph = tagged_graph("finance");
+        let mut other = tagged_graph("hr");
+        other.set_authorization_policy(
+            AuthorizationPolicy::deny_all().allow(Operation::Start, Grant::Role("hr".to_string())),
+        );
+        assert!(other.start(HashMap::new()).is_err());
+
+        assert_eq!(repository.save(&graph, NEW_REVISION).unwrap(), 1);
+        assert_eq!(repository.save(&other, NEW_REVISION).unwrap(), 1);
+
+        let (loaded, revision) = repository.load(graph.id()).unwrap();
+        assert_eq!(revision, 1);
+        assert_eq!(loaded.cid(), graph.cid());
+        assert_eq!(loaded.metadata.tags, vec!["finance".to_string()]);
+        assert!(loaded.uncommitted_events.is_empty());
+        assert!(!graph.uncommitted_events.is_empty());
+
+        // A restricted workflow stays restricted once reloaded
+        let (mut restricted, _) = repository.load(other.id()).unwrap();
+        assert_eq!(
+            restricted.authorization_policy(),
+            other.authorization_policy()
+        );
+        assert_eq!(restricted.audit_log(), other.audit_log());
+        assert!(matches!(
+            restricted.start(HashMap::new()),
+            Err(WorkflowGraphError::Unauthorized { .. })
+        ));
+        assert_eq!(restricted.audit_log().len(), other.audit_log().len() + 1);
+
+        graph.start(HashMap::new()).unwrap();
+        assert_eq!(repository.save(&graph, revision).unwrap(), 2);
+        match repository.save(&loaded, revision) {
+            Err(WorkflowGraphError::ConcurrencyConflict {
+                expected: 1,
+                actual: 2,
+                ..
+            }) => {}
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+
+        assert_eq!(repository.find_by_tag("finance").unwrap(), vec![graph.id()]);
+        assert_eq!(
+            repository.find_by_status(&WorkflowStatus::Running).unwrap(),
+            vec![graph.id()]
+        );
+        assert_eq!(repository.list().unwrap().len(), 2);
+
+        repository.delete(other.id()).unwrap();
+        assert!(matches!(
+            repository.load(other.id()),
+            Err(WorkflowGraphError::WorkflowNotFound(_))
+        ));
+        assert!(repository.delete(other.id()).is_err());
+    }
+
+    #[test]
+    fn test_in_memory_repository() {
+        exercise(&mut InMemoryWorkflowRepository::new());
+    }
+
+    #[test]
+    fn test_file_repository() {
+        let directory = std::env::temp_dir().join(format!(
+            "workflow-repository-{}",
+            WorkflowId::new().as_uuid()
+        ));
+        let mut repository = FileWorkflowRepository::open(&directory).unwrap();
+        exercise(&mut repository);
+
+        let reopened = FileWorkflowRepository::open(&directory).unwrap();
+        assert_eq!(reopened.list().unwrap().len(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_file_saves_conflict() {
+        let directory = std::env::temp_dir().join(format!(
+            "workflow-repository-{}",
+            WorkflowId::new().as_uuid()
+        ));
+        let mut repository = FileWorkflowRepository::open(&directory).unwrap();
+
+        for _ in 0..20 {
+            let graph = tagged_graph("race");
+            repository.save(&graph, NEW_REVISION).unwrap();
+            let barrier = std::sync::Barrier::new(2);
+            let results: Vec<Result<u64, WorkflowGraphError>> = std::thread::scope(|scope| {
+                let writers: Vec<_> = (0..2)
+                    .map(|_| {
+                        let mut writer = FileWorkflowRepository::open(&directory).unwrap();
+                        let (graph, barrier) = (&graph, &barrier);
+                        scope.spawn(move || {
+                            barrier.wait();
+                            writer.save(graph, 1)
+                        })
+                    })
+                    .collect();
+                writers.into_iter().map(|w| w.join().unwrap()).collect()
+            });
+
+            let saved = results.iter().filter(|r| matches!(r, Ok(2))).count();
+            let conflicts = results
+                .iter()
+                .filter(|r| {
+                    matches!(
+                        r,
+                        Err(WorkflowGraphError::ConcurrencyConflict {
+                            expected: 1,
+                            actual: 2,
+                            ..
+                        })
+                    )
+                })
+                .count();
+            assert_eq!((saved, conflicts), (1, 1));
+            assert_eq!(repository.load(graph.id()).unwrap().1, 2);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_lock_left_by_a_crashed_save_does_not_block() {
+        let directory = std::env::temp_dir().join(format!(
+            "workflow-repository-{}",
+            WorkflowId::new().as_uuid()
+        ));
+        let mut repository = FileWorkflowRepository::open(&directory).unwrap();
+        let graph = tagged_graph("crash");
+        let lock = repository.path_of(graph.id()).with_extension("json.lock");
+        fs::write(&lock, "left behind").unwrap();
+
+        let started = Instant::now();
+        assert_eq!(repository.save(&graph, NEW_REVISION).unwrap(), 1);
+        repository.delete(graph.id()).unwrap();
+        assert!(started.elapsed() < LOCK_TIMEOUT);
+        assert!(repository.list().unwrap().is_empty());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
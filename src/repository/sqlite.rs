@@ -0,0 +1,187 @@
+//! Embedded SQLite workflow repository
+
+use super::{
+    check_revision, not_found, parse_workflow_id, WorkflowRecord, WorkflowRepository, NEW_REVISION,
+};
+use crate::{WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{WorkflowId, WorkflowStatus};
+use rusqlite::{params, Connection, OptionalExtension, Params, TransactionBehavior};
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS workflows (
+        id TEXT PRIMARY KEY,
+        revision INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS workflow_tags (
+        workflow_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (workflow_id, tag)
+    );
+    CREATE INDEX IF NOT EXISTS workflow_tags_by_tag ON workflow_tags (tag);
+";
+
+/// Workflow graphs stored in an SQLite database
+///
+/// Revision checks and writes happen in one immediate transaction, so
+/// concurrent writers through separate connections cannot overwrite each
+/// other.
+pub struct SqliteWorkflowRepository {
+    connection: Connection,
+}
+
+impl SqliteWorkflowRepository {
+    /// Open or create a database file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, WorkflowGraphError> {
+        Self::with_connection(Connection::open(path).map_err(sqlite_error)?)
+    }
+
+    /// Create a database that lives in memory
+    pub fn open_in_memory() -> Result<Self, WorkflowGraphError> {
+        Self::with_connection(Connection::open_in_memory().map_err(sqlite_error)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, WorkflowGraphError> {
+        connection.execute_batch(SCHEMA).map_err(sqlite_error)?;
+        Ok(Self { connection })
+    }
+
+    fn ids(&self, sql: &str, params: impl Params) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        let mut statement = self.connection.prepare(sql).map_err(sqlite_error)?;
+        let rows = statement
+            .query_map(params, |row| row.get::<_, String>(0))
+            .map_err(sqlite_error)?;
+
+        let mut ids = Vec::new();
+        for id in rows {
+            ids.extend(parse_workflow_id(&id.map_err(sqlite_error)?));
+        }
+        Ok(ids)
+    }
+}
+
+impl WorkflowRepository for SqliteWorkflowRepository {
+    fn load(&self, workflow_id: WorkflowId) -> Result<(WorkflowGraph, u64), WorkflowGraphError> {
+        let json: String = self
+            .connection
+            .query_row(
+                "SELECT record FROM workflows WHERE id = ?1",
+                [workflow_id.as_uuid().to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sqlite_error)?
+            .ok_or_else(|| not_found(workflow_id))?;
+        let record: WorkflowRecord = serde_json::from_str(&json)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+        Ok((record.to_graph()?, record.revision))
+    }
+
+    fn save(
+        &mut self,
+        graph: &WorkflowGraph,
+        expected_revision: u64,
+    ) -> Result<u64, WorkflowGraphError> {
+        let id = graph.id().as_uuid().to_string();
+        let transaction = self
+            .connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(sqlite_error)?;
+
+        let current: u64 = transaction
+            .query_row(
+                "SELECT revision FROM workflows WHERE id = ?1",
+                [&id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sqlite_error)?
+            .unwrap_or(NEW_REVISION);
+        check_revision(graph.id(), expected_revision, current)?;
+
+        let record = WorkflowRecord::new(graph, current + 1);
+        let json = serde_json::to_string(&record)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO workflows (id, revision, status, record)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![id, record.revision, status_key(graph.status())?, json],
+            )
+            .map_err(sqlite_error)?;
+        transaction
+            .execute("DELETE FROM workflow_tags WHERE workflow_id = ?1", [&id])
+            .map_err(sqlite_error)?;
+        for tag in &graph.metadata.tags {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO workflow_tags (workflow_id, tag) VALUES (?1, ?2)",
+                    params![id, tag],
+                )
+                .map_err(sqlite_error)?;
+        }
+
+        transaction.commit().map_err(sqlite_error)?;
+        Ok(record.revision)
+    }
+
+    fn delete(&mut self, workflow_id: WorkflowId) -> Result<(), WorkflowGraphError> {
+        let id = workflow_id.as_uuid().to_string();
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        let deleted = transaction
+            .execute("DELETE FROM workflows WHERE id = ?1", [&id])
+            .map_err(sqlite_error)?;
+        transaction
+            .execute("DELETE FROM workflow_tags WHERE workflow_id = ?1", [&id])
+            .map_err(sqlite_error)?;
+        transaction.commit().map_err(sqlite_error)?;
+
+        if deleted == 0 {
+            return Err(not_found(workflow_id));
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        self.ids("SELECT id FROM workflows", [])
+    }
+
+    fn find_by_tag(&self, tag: &str) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        self.ids(
+            "SELECT workflow_id FROM workflow_tags WHERE tag = ?1",
+            [tag],
+        )
+    }
+
+    fn find_by_status(
+        &self,
+        status: &WorkflowStatus,
+    ) -> Result<Vec<WorkflowId>, WorkflowGraphError> {
+        self.ids(
+            "SELECT id FROM workflows WHERE status = ?1",
+            [status_key(status)?],
+        )
+    }
+}
+
+/// Stored form of a workflow status
+fn status_key(status: &WorkflowStatus) -> Result<String, WorkflowGraphError> {
+    serde_json::to_string(status).map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))
+}
+
+fn sqlite_error(error: rusqlite::Error) -> WorkflowGraphError {
+    WorkflowGraphError::InvalidOperation(format!("Workflow repository SQLite failed: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_repository() {
+        let mut repository = SqliteWorkflowRepository::open_in_memory().unwrap();
+        super::super::tests::exercise(&mut repository);
+    }
+}
@@ -0,0 +1,509 @@
+//! Schedules that respect both dependencies and people's capacity
+//!
+//! Each step occupies one unit of its `assigned_to` assignee or role for
+//! its remaining estimated duration. [`ResourceCapacities`] limits how many
+//! steps of an assignee can run at once; unassigned steps and assignees
+//! without a limit run in parallel freely.
+//!
+//! Steps are placed one at a time at the earliest moment their
+//! dependencies are done and their assignee has a free unit. The list
+//! scheduling heuristic picks the step heading the longest remaining chain
+//! first. For graphs of at most [`EXACT_STEP_LIMIT`] steps the exact solver
+//! tries every placement order and keeps the shortest schedule.
+
+use crate::impact::remaining_minutes;
+use crate::{topology, WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Largest graph the exact solver is used for by [`ScheduleMethod::Auto`]
+pub const EXACT_STEP_LIMIT: usize = 8;
+
+/// How many steps of each assignee or role can run at the same time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceCapacities {
+    capacities: BTreeMap<String, usize>,
+    default_capacity: Option<usize>,
+}
+
+impl ResourceCapacities {
+    /// No limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit an assignee or role
+    pub fn with(mut self, assignee: impl Into<String>, capacity: usize) -> Self {
+        self.capacities.insert(assignee.into(), capacity);
+        self
+    }
+
+    /// Limit assignees that have no capacity of their own
+    pub fn with_default(mut self, capacity: usize) -> Self {
+        self.default_capacity = Some(capacity);
+        self
+    }
+
+    /// Capacity of an assignee, `None` if unlimited
+    pub fn capacity_of(&self, assignee: &str) -> Option<usize> {
+        self.capacities
+            .get(assignee)
+            .copied()
+            .or(self.default_capacity)
+    }
+}
+
+/// Which algorithm builds the schedule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScheduleMethod {
+    /// Exact for small graphs, list scheduling otherwise
+    #[default]
+    Auto,
+    /// Longest-remaining-chain-first list scheduling
+    ListScheduling,
+    /// Search every placement order for the shortest schedule
+    Exact,
+}
+
+/// One step's place in a schedule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledStep {
+    pub step_id: StepId,
+    pub assignee: Option<String>,
+    /// Minutes from the start of the schedule
+    pub start_minutes: u64,
+    pub end_minutes: u64,
+}
+
+/// A concrete plan for running the remaining work
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Steps ordered by start
+    pub steps: Vec<ScheduledStep>,
+    /// Minutes until the last step ends
+    pub makespan: u64,
+    /// Share of each limited assignee's capacity in use over the makespan
+    pub utilization: BTreeMap<String, f64>,
+    /// Whether the makespan is known to be the shortest possible
+    pub optimal: bool,
+}
+
+impl Schedule {
+    /// The scheduled step with this ID
+    pub fn get(&self, step_id: StepId) -> Option<&ScheduledStep> {
+        self.steps.iter().find(|step| step.step_id == step_id)
+    }
+}
+
+impl WorkflowGraph {
+    /// Schedule the remaining work within the capacities
+    pub fn schedule(
+        &self,
+        capacities: &ResourceCapacities,
+    ) -> Result<Schedule, WorkflowGraphError> {
+        self.schedule_with(capacities, ScheduleMethod::Auto)
+    }
+
+    /// Schedule the remaining work with a chosen algorithm
+    pub fn schedule_with(
+        &self,
+        capacities: &ResourceCapacities,
+        method: ScheduleMethod,
+    ) -> Result<Schedule, WorkflowGraphError> {
+        let planner = Planner::new(self, capacities)?;
+        let exact = match method {
+            ScheduleMethod::Auto => self.workflow.steps.len() <= EXACT_STEP_LIMIT,
+            ScheduleMethod::ListScheduling => false,
+            ScheduleMethod::Exact => true,
+        };
+
+        let mut best = planner.list_schedule()?;
+        if exact {
+            planner.search(Partial::default(), &mut best);
+        }
+        Ok(planner.finish(best, exact))
+    }
+}
+
+struct Planner<'a> {
+    steps: &'a HashMap<StepId, WorkflowStep>,
+    capacities: &'a ResourceCapacities,
+    order: Vec<StepId>,
+    /// Remaining minutes of the longest chain each step starts
+    chain: HashMap<StepId, u64>,
+}
+
+/// Steps placed so far
+#[derive(Debug, Clone, Default)]
+struct Partial {
+    placed: HashMap<StepId, (u64, u64)>,
+    busy: HashMap<String, Vec<(u64, u64)>>,
+    makespan: u64,
+}
+
+impl<'a> Planner<'a> {
+    fn new(
+        graph: &'a WorkflowGraph,
+        capacities: &'a ResourceCapacities,
+    ) -> Result<Self, WorkflowGraphError> {
+        let steps = &graph.workflow.steps;
+        for step in steps.values() {
+            if let Some(assignee) = &step.assigned_to {
+                if capacities.capacity_of(assignee) == Some(0) && remaining_minutes(step) > 0 {
+                    return Err(WorkflowGraphError::InvalidOperation(format!(
+                        "Step {} needs {assignee}, who has no capacity",
+                        step.name
+                    )));
+                }
+            }
+        }
+
+        let order = topology::topological_order(graph);
+        let dependents = topology::dependents(graph);
+        let mut chain = HashMap::with_capacity(steps.len());
+        for id in order.iter().rev() {
+            let after = dependents[id]
+                .iter()
+                .filter_map(|dependent| chain.get(dependent))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            chain.insert(*id, remaining_minutes(&steps[id]) + after);
+        }
+
+        Ok(Self {
+            steps,
+            capacities,
+            order,
+            chain,
+        })
+    }
+
+    /// Unplaced steps whose dependencies are all placed
+    fn eligible(&self, partial: &Partial) -> Vec<StepId> {
+        self.order
+            .iter()
+            .filter(|id| !partial.placed.contains_key(id))
+            .filter(|id| {
+                self.steps[id].dependencies.iter().all(|dependency| {
+                    partial.placed.contains_key(dependency) || !self.steps.contains_key(dependency)
+                })
+            })
+            .copied()
+            .collect()
+    }
+
+    fn list_schedule(&self) -> Result<Partial, WorkflowGraphError> {
+        let mut partial = Partial::default();
+        while partial.placed.len() < self.steps.len() {
+            // Eligible steps come in topological order, so max_by_key keeps
+            // the last of equals; reverse to prefer the first
+            let next = self
+                .eligible(&partial)
+                .into_iter()
+                .rev()
+                .max_by_key(|id| self.chain[id])
+                .ok_or_else(|| {
+                    WorkflowGraphError::CircularDependency(
+                        "Steps in a dependency cycle cannot be scheduled".to_string(),
+                    )
+                })?;
+            self.place(&mut partial, next);
+        }
+        Ok(partial)
+    }
+
+    /// Branch and bound over every placement order
+    fn search(&self, partial: Partial, best: &mut Partial) {
+        let eligible = self.eligible(&partial);
+        if eligible.is_empty() {
+            if partial.placed.len() == self.steps.len() && partial.makespan < best.makespan {
+                *best = partial;
+            }
+            return;
+        }
+
+        for id in eligible {
+            let mut next = partial.clone();
+            self.place(&mut next, id);
+            if self.lower_bound(&next) < best.makespan {
+                self.search(next, best);
+            }
+        }
+    }
+
+    /// Makespan no completion of the partial schedule can beat
+    fn lower_bound(&self, partial: &Partial) -> u64 {
+        self.eligible(partial)
+            .iter()
+            .map(|id| self.ready_at(partial, *id) + self.chain[id])
+            .max()
+            .unwrap_or(0)
+            .max(partial.makespan)
+    }
+
+    fn ready_at(&self, partial: &Partial, id: StepId) -> u64 {
+        self.steps[&id]
+            .dependencies
+            .iter()
+            .filter_map(|dependency| partial.placed.get(dependency))
+            .map(|(_, end)| *end)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Place a step at the earliest moment its assignee has a free unit
+    fn place(&self, partial: &mut Partial, id: StepId) {
+        let step = &self.steps[&id];
+        let duration = remaining_minutes(step);
+        let ready = self.ready_at(partial, id);
+
+        let limited = step
+            .assigned_to
+            .as_ref()
+            .and_then(|assignee| Some((assignee, self.capacities.capacity_of(assignee)?)))
+            .filter(|_| duration > 0);
+        let start = match limited {
+            Some((assignee, capacity)) => {
+                let busy = partial.busy.get(assignee).map_or(&[][..], Vec::as_slice);
+                earliest_free(busy, capacity, ready, duration)
+            }
+            None => ready,
+        };
+
+        let end = start + duration;
+        if let Some((assignee, _)) = limited {
+            partial
+                .busy
+                .entry(assignee.clone())
+                .or_default()
+                .push((start, end));
+        }
+        partial.placed.insert(id, (start, end));
+        partial.makespan = partial.makespan.max(end);
+    }
+
+    fn finish(&self, partial: Partial, optimal: bool) -> Schedule {
+        let mut steps: Vec<ScheduledStep> = self
+            .order
+            .iter()
+            .map(|id| {
+                let (start, end) = partial.placed[id];
+                ScheduledStep {
+                    step_id: *id,
+                    assignee: self.steps[id].assigned_to.clone(),
+                    start_minutes: start,
+                    end_minutes: end,
+                }
+            })
+            .collect();
+        steps.sort_by_key(|step| step.start_minutes);
+
+        let utilization = partial
+            .busy
+            .iter()
+            .filter_map(|(assignee, busy)| {
+                let capacity = self.capacities.capacity_of(assignee)? as u64;
+                let used: u64 = busy.iter().map(|(start, end)| end - start).sum();
+                let available = capacity * partial.makespan;
+                let share = if available == 0 {
+                    0.0
+                } else {
+                    used as f64 / available as f64
+                };
+                Some((assignee.clone(), share))
+            })
+            .collect();
+
+        Schedule {
+            steps,
+            makespan: partial.makespan,
+            utilization,
+            optimal,
+        }
+    }
+}
+
+/// Earliest start at or after `ready` where fewer than `capacity` busy
+/// intervals overlap the whole duration
+fn earliest_free(busy: &[(u64, u64)], capacity: usize, ready: u64, duration: u64) -> u64 {
+    let mut candidates: Vec<u64> = busy
+        .iter()
+        .map(|(_, end)| *end)
+        .filter(|end| *end > ready)
+        .collect();
+    candidates.push(ready);
+    candidates.sort_unstable();
+
+    let in_use = |at: u64| busy.iter().filter(|(s, e)| *s <= at && at < *e).count();
+    candidates
+        .into_iter()
+        .find(|start| {
+            let end = start + duration;
+            // Usage only rises where an interval begins
+            std::iter::once(*start)
+                .chain(
+                    busy.iter()
+                        .map(|(s, _)| *s)
+                        .filter(|s| start < s && *s < end),
+                )
+                .all(|at| in_use(at) < capacity)
+        })
+        .expect("the last busy interval's end is always free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cim_domain_workflow::value_objects::{StepStatus, StepType};
+
+    fn step(
+        graph: &mut WorkflowGraph,
+        name: &str,
+        dependencies: Vec<StepId>,
+        minutes: u32,
+        assignee: &str,
+    ) -> StepId {
+        graph
+            .add_step(
+                name.to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                dependencies,
+                Some(minutes),
+                Some(assignee.to_string()),
+            )
+            .unwrap()
+    }
+
+    fn assert_respects(graph: &WorkflowGraph, schedule: &Schedule, editors: usize) {
+        for scheduled in &schedule.steps {
+            for dependency in &graph.workflow.steps[&scheduled.step_id].dependencies {
+                assert!(schedule.get(*dependency).unwrap().end_minutes <= scheduled.start_minutes);
+            }
+            let overlapping = schedule
+                .steps
+                .iter()
+                .filter(|other| other.assignee.as_deref() == Some("editor"))
+                .filter(|other| {
+                    other.start_minutes <= scheduled.start_minutes
+                        && scheduled.start_minutes < other.end_minutes
+                })
+                .count();
+            assert!(overlapping <= editors);
+        }
+    }
+
+    #[test]
+    fn test_capacity_serializes_work() {
+        let mut graph = WorkflowGraph::new("Edit".to_string(), "Chapters".to_string()).unwrap();
+        let write = step(&mut graph, "Write", vec![], 30, "author");
+        for chapter in ["One", "Two", "Three"] {
+            step(&mut graph, chapter, vec![write], 20, "editor");
+        }
+
+        let unlimited = graph.schedule(&ResourceCapacities::new()).unwrap();
+        assert_eq!(unlimited.makespan, 50);
+
+        let capacities = ResourceCapacities::new().with("editor", 1);
+        let schedule = graph.schedule(&capacities).unwrap();
+        assert_eq!(schedule.makespan, 90);
+        assert_eq!(schedule.utilization["editor"], 60.0 / 90.0);
+        assert_eq!(schedule.get(write).unwrap().start_minutes, 0);
+        assert_respects(&graph, &schedule, 1);
+
+        let schedule = graph
+            .schedule(&ResourceCapacities::new().with("editor", 2))
+            .unwrap();
+        assert_eq!(schedule.makespan, 70);
+        assert_respects(&graph, &schedule, 2);
+    }
+
+    #[test]
+    fn test_exact_beats_list_scheduling() {
+        // Layout heads the longest chain, but starting the short Proof first
+        // lets its long follow-up overlap the editor's other work
+        let mut graph = WorkflowGraph::new("Edit".to_string(), "Tricky".to_string()).unwrap();
+        let layout = step(&mut graph, "Layout", vec![], 30, "editor");
+        step(&mut graph, "Print", vec![layout], 5, "author");
+        let proof = step(&mut graph, "Proof", vec![], 5, "editor");
+        step(&mut graph, "Revise", vec![proof], 29, "author");
+        let capacities = ResourceCapacities::new().with("editor", 1);
+
+        let list = graph
+            .schedule_with(&capacities, ScheduleMethod::ListScheduling)
+            .unwrap();
+        let exact = graph
+            .schedule_with(&capacities, ScheduleMethod::Exact)
+            .unwrap();
+        assert!(!list.optimal);
+        assert!(exact.optimal);
+        assert_eq!(list.makespan, 64);
+        assert_eq!(exact.makespan, 40);
+        assert_respects(&graph, &exact, 1);
+    }
+
+    #[test]
+    fn test_zero_capacity_is_rejected() {
+        let mut graph = WorkflowGraph::new("Edit".to_string(), "Nobody".to_string()).unwrap();
+        step(&mut graph, "Review", vec![], 10, "editor");
+
+        let capacities = ResourceCapacities::new().with_default(0);
+        assert!(matches!(
+            graph.schedule(&capacities),
+            Err(WorkflowGraphError::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_cycles_are_rejected_by_both_solvers() {
+        let mut graph = WorkflowGraph::new("Edit".to_string(), "Loop".to_string()).unwrap();
+        let draft = step(&mut graph, "Draft", vec![], 10, "author");
+        let edit = step(&mut graph, "Edit", vec![draft], 10, "editor");
+        graph
+            .workflow
+            .steps
+            .get_mut(&draft)
+            .unwrap()
+            .dependencies
+            .push(edit);
+
+        for method in [ScheduleMethod::ListScheduling, ScheduleMethod::Exact] {
+            assert!(matches!(
+                graph.schedule_with(&ResourceCapacities::new(), method),
+                Err(WorkflowGraphError::CircularDependency(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_finished_and_dangling_steps_take_no_capacity() {
+        let mut graph = WorkflowGraph::new("Edit".to_string(), "Done".to_string()).unwrap();
+        let write = step(&mut graph, "Write", vec![], 30, "editor");
+        let review = step(&mut graph, "Review", vec![write], 20, "editor");
+        let capacities = ResourceCapacities::new().with("editor", 0);
+        assert!(graph.schedule(&capacities).is_err());
+
+        // The editor has no capacity, but only finished work is left
+        let steps = &mut graph.workflow.steps;
+        steps.get_mut(&write).unwrap().status = StepStatus::Completed;
+        let review_step = steps.get_mut(&review).unwrap();
+        review_step.status = StepStatus::Skipped;
+        review_step.dependencies.push(StepId::new());
+
+        let schedule = graph.schedule(&capacities).unwrap();
+        assert_eq!(schedule.makespan, 0);
+        assert_eq!(schedule.get(review).unwrap().start_minutes, 0);
+    }
+
+    #[test]
+    fn test_earliest_free() {
+        let busy = [(0, 10), (5, 20)];
+        assert_eq!(earliest_free(&busy, 2, 0, 5), 0);
+        assert_eq!(earliest_free(&busy, 2, 0, 10), 10);
+        assert_eq!(earliest_free(&busy, 1, 0, 5), 20);
+        assert_eq!(earliest_free(&busy, 3, 0, 5), 0);
+    }
+}
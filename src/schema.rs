@@ -0,0 +1,355 @@
+//! JSON Schema validation for step configuration
+//!
+//! Step configuration is a free-form map, so a mistyped key such as
+//! `"escalation_hour"` would otherwise only surface when a step runs. The
+//! [`StepConfigSchemaRegistry`] maps each [`StepType`] (or custom handler name)
+//! to a JSON Schema that its `config` map must satisfy. Custom handlers are
+//! keyed as `custom:<handler>`, so they never collide with a built-in type.
+
+use crate::WorkflowGraphError;
+use cim_domain_workflow::value_objects::StepType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Prefix of the registry keys of custom handlers
+pub const CUSTOM_KEY_PREFIX: &str = "custom:";
+
+/// JSON Schema dialect advertised by [`StepConfigSchemaRegistry::export`]
+pub const SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A single schema violation within a step configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigViolation {
+    /// JSON pointer to the offending value inside the config map
+    pub pointer: String,
+    /// Human readable description of the violation
+    pub message: String,
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{pointer}: {}", self.message)
+    }
+}
+
+/// Registry of JSON Schemas for step configuration maps
+#[derive(Debug, Clone, Default)]
+pub struct StepConfigSchemaRegistry {
+    schemas: BTreeMap<String, serde_json::Value>,
+    /// Validators compiled at registration
+    validators: BTreeMap<String, Arc<jsonschema::Validator>>,
+}
+
+impl StepConfigSchemaRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the schema for a step type
+    ///
+    /// `StepType::Custom` steps are keyed by their handler name.
+    pub fn register(
+        &mut self,
+        step_type: &StepType,
+        schema: serde_json::Value,
+    ) -> Result<(), WorkflowGraphError> {
+        self.insert(Self::key_for(step_type), schema)
+    }
+
+    /// Compile and store a schema under its registry key
+    fn insert(&mut self, key: String, schema: serde_json::Value) -> Result<(), WorkflowGraphError> {
+        let validator = jsonschema::validator_for(&schema).map_err(|e| {
+            WorkflowGraphError::InvalidOperation(format!("Invalid schema for {key}: {e}"))
+        })?;
+        self.validators.insert(key.clone(), Arc::new(validator));
+        self.schemas.insert(key, schema);
+        Ok(())
+    }
+
+    /// Register the schema for a custom handler name
+    pub fn register_handler(
+        &mut self,
+        handler: &str,
+        schema: serde_json::Value,
+    ) -> Result<(), WorkflowGraphError> {
+        self.register(&StepType::Custom(handler.to_string()), schema)
+    }
+
+    /// Get the schema registered for a step type
+    pub fn schema_for(&self, step_type: &StepType) -> Option<&serde_json::Value> {
+        self.schemas.get(&Self::key_for(step_type))
+    }
+
+    /// Whether no schemas have been registered
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Check a step configuration against the schema for its type
+    ///
+    /// Step types without a registered schema accept any configuration.
+    pub fn validate(
+        &self,
+        step: &str,
+        step_type: &StepType,
+        config: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), WorkflowGraphError> {
+        let violations = self.violations(step_type, config)?;
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(WorkflowGraphError::InvalidConfig {
+                step: step.to_string(),
+                violations,
+            })
+        }
+    }
+
+    /// Collect every violation of a step configuration
+    pub fn violations(
+        &self,
+        step_type: &StepType,
+        config: &HashMap<String, serde_json::Value>,
+    ) -> Result<Vec<ConfigViolation>, WorkflowGraphError> {
+        let Some(validator) = self.validators.get(&Self::key_for(step_type)) else {
+            return Ok(Vec::new());
+        };
+        let instance = serde_json::to_value(config)
+            .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))?;
+
+        Ok(validator
+            .iter_errors(&instance)
+            .flat_map(|error| {
+                let pointer = error.instance_path.to_string();
+                match &error.kind {
+                    // Point at each unexpected key rather than the object holding it
+                    jsonschema::error::ValidationErrorKind::AdditionalProperties { unexpected } => {
+                        unexpected
+                            .iter()
+                            .map(|key| ConfigViolation {
+                                pointer: format!("{pointer}/{}", escape_pointer(key)),
+                                message: format!("Additional property {key} is not allowed"),
+                            })
+                            .collect()
+                    }
+                    _ => vec![ConfigViolation {
+                        pointer,
+                        message: error.to_string(),
+                    }],
+                }
+            })
+            .collect())
+    }
+
+    /// Export every registered schema as a single JSON Schema document
+    ///
+    /// Each schema is placed under `$defs`, keyed by step type or handler name,
+    /// which editors can use for autocompletion of step configuration.
+    pub fn export(&self) -> serde_json::Value {
+        serde_json::json!({
+            "$schema": SCHEMA_DIALECT,
+            "title": "Workflow step configuration",
+            "$defs": self.schemas,
+        })
+    }
+
+    /// Registry key for a step type
+    pub fn key_for(step_type: &StepType) -> String {
+        match step_type {
+            StepType::Custom(handler) => format!("{CUSTOM_KEY_PREFIX}{handler}"),
+            other => format!("{other:?}"),
+        }
+    }
+}
+
+/// Serialized as its schemas, keyed like [`StepConfigSchemaRegistry::export`]
+impl Serialize for StepConfigSchemaRegistry {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.schemas.serialize(serializer)
+    }
+}
+
+/// Validators are compiled again, so an invalid schema fails to deserialize
+impl<'de> Deserialize<'de> for StepConfigSchemaRegistry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let schemas = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+        let mut registry = Self::new();
+        for (key, schema) in schemas {
+            registry
+                .insert(key, schema)
+                .map_err(serde::de::Error::custom)?;
+        }
+        Ok(registry)
+    }
+}
+
+/// Escape a key for use as a JSON pointer segment
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WorkflowGraph;
+
+    fn approval_schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "escalation_hours": { "type": "integer", "minimum": 1 },
+                "approval_criteria": { "type": "array", "items": { "type": "string" } }
+            },
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn test_reports_pointer_of_each_violation() {
+        let mut registry = StepConfigSchemaRegistry::new();
+        registry
+            .register(&StepType::Approval, approval_schema())
+            .unwrap();
+
+        let mut config = HashMap::new();
+        config.insert("escalation_hours".to_string(), serde_json::json!("soon"));
+        config.insert("approval_criteria".to_string(), serde_json::json!([1]));
+
+        let err = registry
+            .validate("Manager Approval", &StepType::Approval, &config)
+            .unwrap_err();
+        let WorkflowGraphError::InvalidConfig { step, violations } = err else {
+            panic!("expected InvalidConfig");
+        };
+        assert_eq!(step, "Manager Approval");
+
+        let mut pointers: Vec<_> = violations.iter().map(|v| v.pointer.as_str()).collect();
+        pointers.sort();
+        assert_eq!(pointers, vec!["/approval_criteria/0", "/escalation_hours"]);
+    }
+
+    #[test]
+    fn test_add_step_rejects_mistyped_key() {
+        let mut registry = StepConfigSchemaRegistry::new();
+        registry
+            .register(&StepType::Approval, approval_schema())
+            .unwrap();
+
+        let mut workflow_graph =
+            WorkflowGraph::new("Schema Test".to_string(), "Schema validation".to_string()).unwrap();
+        workflow_graph.set_schema_registry(registry);
+
+        let mut config = HashMap::new();
+        config.insert("escalation_hour".to_string(), serde_json::json!(24));
+
+        let result = workflow_graph.add_step(
+            "Manager Approval".to_string(),
+            "Approve".to_string(),
+            StepType::Approval,
+            config,
+            Vec::new(),
+            Some(30),
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(WorkflowGraphError::InvalidConfig { .. })
+        ));
+        assert!(workflow_graph.workflow.steps.is_empty());
+
+        // Step types without a schema are unconstrained
+        assert!(workflow_graph
+            .add_step(
+                "Draft".to_string(),
+                "Draft".to_string(),
+                StepType::Manual,
+                HashMap::from([("anything".to_string(), serde_json::json!(true))]),
+                Vec::new(),
+                None,
+                None,
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_export_contains_custom_handlers() {
+        let mut registry = StepConfigSchemaRegistry::new();
+        registry
+            .register(&StepType::Approval, approval_schema())
+            .unwrap();
+        registry
+            .register_handler("publish", serde_json::json!({ "type": "object" }))
+            .unwrap();
+
+        let exported = registry.export();
+        assert_eq!(exported["$schema"], SCHEMA_DIALECT);
+        assert!(exported["$defs"]["Approval"].is_object());
+        assert!(exported["$defs"]["custom:publish"].is_object());
+        assert!(registry
+            .schema_for(&StepType::Custom("publish".to_string()))
+            .is_some());
+    }
+
+    #[test]
+    fn test_custom_handler_does_not_shadow_builtin_type() {
+        let mut registry = StepConfigSchemaRegistry::new();
+        registry
+            .register(&StepType::Approval, approval_schema())
+            .unwrap();
+        registry
+            .register(
+                &StepType::Custom("Approval".to_string()),
+                serde_json::json!({ "type": "object", "required": ["endpoint"] }),
+            )
+            .unwrap();
+
+        let config = HashMap::from([("escalation_hours".to_string(), serde_json::json!(4))]);
+        assert!(registry
+            .validate("Sign off", &StepType::Approval, &config)
+            .is_ok());
+        assert!(registry
+            .validate(
+                "Call out",
+                &StepType::Custom("Approval".to_string()),
+                &config
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_additional_property_points_at_key() {
+        let mut registry = StepConfigSchemaRegistry::new();
+        registry
+            .register(&StepType::Approval, approval_schema())
+            .unwrap();
+
+        let config = HashMap::from([
+            ("escalation_hour".to_string(), serde_json::json!(24)),
+            ("a/b".to_string(), serde_json::json!(true)),
+        ]);
+        let mut pointers: Vec<String> = registry
+            .violations(&StepType::Approval, &config)
+            .unwrap()
+            .into_iter()
+            .map(|violation| violation.pointer)
+            .collect();
+        pointers.sort();
+        assert_eq!(pointers, ["/a~1b", "/escalation_hour"]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_schema() {
+        let mut registry = StepConfigSchemaRegistry::new();
+        let result = registry.register(&StepType::Manual, serde_json::json!({ "type": 12 }));
+        assert!(result.is_err());
+        assert!(registry.is_empty());
+    }
+}
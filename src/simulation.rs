@@ -0,0 +1,463 @@
+//! Monte Carlo simulation of completion times
+//!
+//! Each run draws a duration for every remaining step and computes when the
+//! workflow finishes and which steps were on its critical path. Durations
+//! come from a [`DurationEstimate`] stored in the step's config under
+//! [`DURATION_ESTIMATE_KEY`], an override given to the simulator, or else
+//! the step's fixed `estimated_duration_minutes`. Completed and skipped
+//! steps take no time.
+//!
+//! Runs are drawn from a seeded ChaCha generator, so the same seed, graph
+//! and estimates always give the same report.
+
+use crate::{topology, WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepStatus, WorkflowStep};
+use rand::distr::{Distribution, Uniform};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Normal, Pert, Triangular};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Step config key holding a [`DurationEstimate`]
+pub const DURATION_ESTIMATE_KEY: &str = "duration_estimate";
+
+/// Uncertain duration of a step, in minutes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum DurationEstimate {
+    /// Always the same duration
+    Fixed { minutes: f64 },
+    /// Three-point estimate drawn from a PERT (beta) distribution
+    ThreePoint {
+        optimistic: f64,
+        likely: f64,
+        pessimistic: f64,
+    },
+    /// Three-point estimate drawn from a triangular distribution
+    Triangular {
+        optimistic: f64,
+        likely: f64,
+        pessimistic: f64,
+    },
+    /// Any duration in a range equally likely
+    Uniform { min: f64, max: f64 },
+    /// Normal distribution, cut off at zero
+    Normal { mean: f64, std_dev: f64 },
+}
+
+impl DurationEstimate {
+    /// Three-point PERT estimate
+    pub fn three_point(optimistic: f64, likely: f64, pessimistic: f64) -> Self {
+        DurationEstimate::ThreePoint {
+            optimistic,
+            likely,
+            pessimistic,
+        }
+    }
+
+    /// Config value for storing under [`DURATION_ESTIMATE_KEY`]
+    pub fn to_config_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("estimates serialize")
+    }
+
+    /// Estimate of a step from its config, if it has one
+    pub fn of_step(step: &WorkflowStep) -> Result<Option<Self>, WorkflowGraphError> {
+        step.config
+            .get(DURATION_ESTIMATE_KEY)
+            .map(|value| {
+                serde_json::from_value(value.clone()).map_err(|e| {
+                    WorkflowGraphError::InvalidOperation(format!(
+                        "Step {} has an invalid duration estimate: {e}",
+                        step.name
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    fn sampler(&self) -> Result<Sampler, String> {
+        let sampler = match *self {
+            DurationEstimate::Fixed { minutes } => Sampler::Fixed(minutes),
+            DurationEstimate::ThreePoint {
+                optimistic,
+                likely,
+                pessimistic,
+            } if optimistic == pessimistic => Sampler::Fixed(likely),
+            DurationEstimate::ThreePoint {
+                optimistic,
+                likely,
+                pessimistic,
+            } => Sampler::Pert(
+                Pert::new(optimistic, pessimistic)
+                    .with_mode(likely)
+                    .map_err(|e| e.to_string())?,
+            ),
+            DurationEstimate::Triangular {
+                optimistic,
+                likely,
+                pessimistic,
+            } if optimistic == pessimistic => Sampler::Fixed(likely),
+            DurationEstimate::Triangular {
+                optimistic,
+                likely,
+                pessimistic,
+            } => Sampler::Triangular(
+                Triangular::new(optimistic, pessimistic, likely).map_err(|e| e.to_string())?,
+            ),
+            DurationEstimate::Uniform { min, max } if min == max => Sampler::Fixed(min),
+            DurationEstimate::Uniform { min, max } => {
+                Sampler::Uniform(Uniform::new_inclusive(min, max).map_err(|e| e.to_string())?)
+            }
+            DurationEstimate::Normal { mean, std_dev } if !(mean.is_finite() && std_dev >= 0.0) => {
+                return Err(format!(
+                    "normal({mean}, {std_dev}) is not a duration distribution"
+                ));
+            }
+            DurationEstimate::Normal { mean, std_dev } => {
+                Sampler::Normal(Normal::new(mean, std_dev).map_err(|e| e.to_string())?)
+            }
+        };
+        if let Sampler::Fixed(minutes) = sampler {
+            if !(minutes.is_finite() && minutes >= 0.0) {
+                return Err(format!("{minutes} is not a duration"));
+            }
+        }
+        Ok(sampler)
+    }
+}
+
+enum Sampler {
+    Fixed(f64),
+    Pert(Pert<f64>),
+    Triangular(Triangular<f64>),
+    Uniform(Uniform<f64>),
+    Normal(Normal<f64>),
+}
+
+impl Sampler {
+    fn sample(&self, rng: &mut ChaCha8Rng) -> f64 {
+        let minutes = match self {
+            Sampler::Fixed(minutes) => *minutes,
+            Sampler::Pert(pert) => pert.sample(rng),
+            Sampler::Triangular(triangular) => triangular.sample(rng),
+            Sampler::Uniform(uniform) => uniform.sample(rng),
+            Sampler::Normal(normal) => normal.sample(rng),
+        };
+        minutes.max(0.0)
+    }
+}
+
+/// Seeded Monte Carlo simulator
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonteCarlo {
+    runs: usize,
+    seed: u64,
+    overrides: HashMap<StepId, DurationEstimate>,
+}
+
+impl MonteCarlo {
+    /// Simulate `runs` times from a seed
+    pub fn new(runs: usize, seed: u64) -> Self {
+        Self {
+            runs,
+            seed,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Use an estimate for a step instead of its own
+    pub fn with_estimate(mut self, step_id: StepId, estimate: DurationEstimate) -> Self {
+        self.overrides.insert(step_id, estimate);
+        self
+    }
+
+    /// Simulate the remaining work of a workflow
+    pub fn run(&self, graph: &WorkflowGraph) -> Result<SimulationReport, WorkflowGraphError> {
+        if self.runs == 0 {
+            return Err(WorkflowGraphError::InvalidOperation(
+                "A simulation needs at least one run".to_string(),
+            ));
+        }
+
+        let steps = &graph.workflow.steps;
+        let order = topology::topological_order(graph);
+        let dependents = topology::dependents(graph);
+        let samplers = order
+            .iter()
+            .map(|id| self.sampler_for(&steps[id]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut completions = Vec::with_capacity(self.runs);
+        let mut critical_runs = vec![0usize; order.len()];
+        let position: HashMap<StepId, usize> =
+            order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+        for _ in 0..self.runs {
+            let durations: Vec<f64> = samplers.iter().map(|s| s.sample(&mut rng)).collect();
+
+            let mut finish = vec![0.0f64; order.len()];
+            for (i, id) in order.iter().enumerate() {
+                let start = steps[id]
+                    .dependencies
+                    .iter()
+                    .filter_map(|dependency| position.get(dependency))
+                    .map(|j| finish[*j])
+                    .fold(0.0, f64::max);
+                finish[i] = start + durations[i];
+            }
+            let end = finish.iter().copied().fold(0.0, f64::max);
+
+            // Latest finish that keeps the end where it is
+            let mut latest = vec![end; order.len()];
+            for (i, id) in order.iter().enumerate().rev() {
+                for dependent in &dependents[id] {
+                    let j = position[dependent];
+                    latest[i] = latest[i].min(latest[j] - durations[j]);
+                }
+            }
+            for i in 0..order.len() {
+                if latest[i] - finish[i] <= 1e-9 * end.max(1.0) {
+                    critical_runs[i] += 1;
+                }
+            }
+            completions.push(end);
+        }
+
+        completions.sort_by(f64::total_cmp);
+        Ok(SimulationReport {
+            runs: self.runs,
+            p50: percentile(&completions, 0.50),
+            p80: percentile(&completions, 0.80),
+            p95: percentile(&completions, 0.95),
+            mean: completions.iter().sum::<f64>() / self.runs as f64,
+            criticality: order
+                .iter()
+                .zip(critical_runs)
+                .map(|(id, count)| (*id, count as f64 / self.runs as f64))
+                .collect(),
+            completions,
+        })
+    }
+
+    fn sampler_for(&self, step: &WorkflowStep) -> Result<Sampler, WorkflowGraphError> {
+        if matches!(step.status, StepStatus::Completed | StepStatus::Skipped) {
+            return Ok(Sampler::Fixed(0.0));
+        }
+        let estimate = match self.overrides.get(&step.id) {
+            Some(estimate) => estimate.clone(),
+            None => DurationEstimate::of_step(step)?.unwrap_or(DurationEstimate::Fixed {
+                minutes: step.estimated_duration_minutes.unwrap_or(0).into(),
+            }),
+        };
+        estimate.sampler().map_err(|e| {
+            WorkflowGraphError::InvalidOperation(format!(
+                "Step {} has an invalid duration estimate: {e}",
+                step.name
+            ))
+        })
+    }
+}
+
+/// Completion times and criticality over all runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationReport {
+    pub runs: usize,
+    /// Minutes within which half of the runs completed
+    pub p50: f64,
+    pub p80: f64,
+    pub p95: f64,
+    pub mean: f64,
+    /// Share of runs in which each step was on the critical path, in
+    /// topological order
+    pub criticality: Vec<(StepId, f64)>,
+    /// Completion minutes of every run, sorted
+    pub completions: Vec<f64>,
+}
+
+impl SimulationReport {
+    /// Minutes within which the given share of runs completed
+    pub fn percentile(&self, share: f64) -> f64 {
+        percentile(&self.completions, share)
+    }
+
+    /// Criticality index of a step
+    pub fn criticality_of(&self, step_id: StepId) -> Option<f64> {
+        self.criticality
+            .iter()
+            .find(|(id, _)| *id == step_id)
+            .map(|(_, index)| *index)
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f64], share: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (share.clamp(0.0, 1.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cim_domain_workflow::value_objects::StepType;
+
+    fn step(
+        graph: &mut WorkflowGraph,
+        name: &str,
+        dependencies: Vec<StepId>,
+        minutes: u32,
+        estimate: Option<DurationEstimate>,
+    ) -> StepId {
+        let config = estimate
+            .map(|e| HashMap::from([(DURATION_ESTIMATE_KEY.to_string(), e.to_config_value())]))
+            .unwrap_or_default();
+        graph
+            .add_step(
+                name.to_string(),
+                String::new(),
+                StepType::Manual,
+                config,
+                dependencies,
+                Some(minutes),
+                None,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fixed_estimates_are_deterministic() {
+        let mut graph = WorkflowGraph::new("Fixed".to_string(), String::new()).unwrap();
+        let a = step(&mut graph, "A", vec![], 30, None);
+        let long = step(&mut graph, "Long", vec![a], 60, None);
+        let short = step(&mut graph, "Short", vec![a], 10, None);
+
+        let report = MonteCarlo::new(50, 7).run(&graph).unwrap();
+        assert_eq!((report.p50, report.p80, report.p95), (90.0, 90.0, 90.0));
+        assert_eq!(report.criticality_of(a), Some(1.0));
+        assert_eq!(report.criticality_of(long), Some(1.0));
+        assert_eq!(report.criticality_of(short), Some(0.0));
+    }
+
+    #[test]
+    fn test_three_point_percentiles_and_seed() {
+        let mut graph = WorkflowGraph::new("Uncertain".to_string(), String::new()).unwrap();
+        let review = step(
+            &mut graph,
+            "Review",
+            vec![],
+            60,
+            Some(DurationEstimate::three_point(30.0, 60.0, 240.0)),
+        );
+        step(&mut graph, "Sign", vec![review], 10, None);
+
+        let report = MonteCarlo::new(2_000, 42).run(&graph).unwrap();
+        assert!(report.p50 < report.p80 && report.p80 < report.p95);
+        assert!(report.p50 > 40.0 && report.p95 <= 250.0);
+        assert_eq!(report.completions.len(), 2_000);
+
+        let again = MonteCarlo::new(2_000, 42).run(&graph).unwrap();
+        assert_eq!(report, again);
+        let other = MonteCarlo::new(2_000, 43).run(&graph).unwrap();
+        assert_ne!(report.completions, other.completions);
+    }
+
+    #[test]
+    fn test_criticality_index_splits_between_branches() {
+        let mut graph = WorkflowGraph::new("Race".to_string(), String::new()).unwrap();
+        let left = step(&mut graph, "Left", vec![], 60, None);
+        let right = step(&mut graph, "Right", vec![], 60, None);
+        step(&mut graph, "Join", vec![left, right], 5, None);
+
+        let report = MonteCarlo::new(1_000, 1)
+            .with_estimate(
+                left,
+                DurationEstimate::Uniform {
+                    min: 40.0,
+                    max: 80.0,
+                },
+            )
+            .with_estimate(
+                right,
+                DurationEstimate::Uniform {
+                    min: 50.0,
+                    max: 70.0,
+                },
+            )
+            .run(&graph)
+            .unwrap();
+        let (l, r) = (
+            report.criticality_of(left).unwrap(),
+            report.criticality_of(right).unwrap(),
+        );
+        assert!(l > 0.3 && r > 0.3);
+        assert!((l + r - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_invalid_estimate_is_rejected() {
+        let mut graph = WorkflowGraph::new("Broken".to_string(), String::new()).unwrap();
+        let bad = DurationEstimate::three_point(90.0, 10.0, 30.0);
+        step(&mut graph, "Bad", vec![], 10, Some(bad));
+
+        assert!(MonteCarlo::new(10, 0).run(&graph).is_err());
+        assert!(MonteCarlo::new(0, 0)
+            .run(&WorkflowGraph::new("Empty".to_string(), String::new()).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_degenerate_graphs_and_estimates() {
+        let empty = WorkflowGraph::new("Empty".to_string(), String::new()).unwrap();
+        let report = MonteCarlo::new(3, 0).run(&empty).unwrap();
+        assert_eq!(report.completions, vec![0.0; 3]);
+        assert!(report.criticality.is_empty());
+
+        // Negative draws are cut off at zero, and bad parameters rejected
+        let mut graph = WorkflowGraph::new("Odd".to_string(), String::new()).unwrap();
+        let normal = DurationEstimate::Normal {
+            mean: -10.0,
+            std_dev: 1.0,
+        };
+        step(&mut graph, "Instant", vec![], 10, Some(normal));
+        assert_eq!(MonteCarlo::new(20, 5).run(&graph).unwrap().p95, 0.0);
+        for bad in [
+            DurationEstimate::Uniform {
+                min: 10.0,
+                max: 5.0,
+            },
+            DurationEstimate::Normal {
+                mean: 10.0,
+                std_dev: -1.0,
+            },
+            DurationEstimate::Fixed { minutes: f64::NAN },
+        ] {
+            let mut graph = WorkflowGraph::new("Bad".to_string(), String::new()).unwrap();
+            step(&mut graph, "Bad", vec![], 10, Some(bad.clone()));
+            assert!(MonteCarlo::new(1, 0).run(&graph).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_dependency_cycle_terminates() {
+        let mut graph = WorkflowGraph::new("Loop".to_string(), String::new()).unwrap();
+        let a = step(&mut graph, "A", vec![], 30, None);
+        let b = step(&mut graph, "B", vec![a], 60, None);
+        graph
+            .workflow
+            .steps
+            .get_mut(&a)
+            .unwrap()
+            .dependencies
+            .push(b);
+
+        // Cycle members run in name order, each after the ones before it
+        let report = MonteCarlo::new(10, 3).run(&graph).unwrap();
+        assert_eq!(report.p95, 90.0);
+        assert_eq!(report.criticality.len(), 2);
+        assert_eq!(report, MonteCarlo::new(10, 3).run(&graph).unwrap());
+    }
+}
@@ -0,0 +1,603 @@
+//! Deadlines and escalation for steps
+//!
+//! A [`StepSla`] gives a step a deadline, either at a fixed time or within
+//! some minutes of the step becoming executable, and a chain of escalations
+//! that fire at set times after a breach. Steps take their SLA from the
+//! [`SLA_CONFIG_KEY`] config entry, from an `escalation_hours` entry meaning
+//! a deadline that many hours after becoming executable, or from
+//! [`SlaMonitor::with_sla`].
+//!
+//! The [`SlaMonitor`] reads time from a [`Clock`], so evaluating the same
+//! workflow at the same clock times always gives the same events.
+//!
+//! Breaches and escalations are [`SlaEvent`]s, not domain events. The domain
+//! has no event for reassigning a step either, so a reassignment is not in
+//! the workflow's event log and replaying the log restores the previous
+//! assignee.
+
+use crate::clock::Clock;
+use crate::impact::is_finished;
+use crate::{topology, WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Duration, Utc};
+use cim_domain_workflow::value_objects::{StepId, WorkflowId, WorkflowStatus, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Step config key holding a [`StepSla`]
+pub const SLA_CONFIG_KEY: &str = "sla";
+
+/// Step config key for a deadline in hours after becoming executable
+pub const ESCALATION_HOURS_KEY: &str = "escalation_hours";
+
+/// When a step must be done
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Deadline {
+    /// At a fixed time
+    At(DateTime<Utc>),
+    /// Within minutes of the step becoming executable
+    WithinMinutes(u64),
+}
+
+/// What happens when an escalation fires
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationAction {
+    /// Assign the step to someone else
+    Reassign(String),
+    /// Tell someone, leaving the assignment alone
+    Notify(String),
+}
+
+/// One level of an escalation chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Escalation {
+    /// Minutes after the deadline
+    pub after_minutes: u64,
+    pub action: EscalationAction,
+}
+
+/// A step's deadline and escalation chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepSla {
+    pub deadline: Deadline,
+    /// Levels in the order they fire
+    #[serde(default)]
+    pub escalations: Vec<Escalation>,
+}
+
+impl StepSla {
+    /// Deadline a duration after the step becomes executable
+    pub fn within(duration: Duration) -> Self {
+        Self {
+            deadline: Deadline::WithinMinutes(duration.num_minutes().max(0) as u64),
+            escalations: Vec::new(),
+        }
+    }
+
+    /// Deadline at a fixed time
+    pub fn at(deadline: DateTime<Utc>) -> Self {
+        Self {
+            deadline: Deadline::At(deadline),
+            escalations: Vec::new(),
+        }
+    }
+
+    /// Add an escalation level firing `after` the deadline
+    pub fn escalate(mut self, after: Duration, action: EscalationAction) -> Self {
+        self.escalations.push(Escalation {
+            after_minutes: after.num_minutes().max(0) as u64,
+            action,
+        });
+        self.escalations
+            .sort_by_key(|escalation| escalation.after_minutes);
+        self
+    }
+
+    /// SLA of a step from its config, if it has one
+    pub fn of_step(step: &WorkflowStep) -> Result<Option<Self>, WorkflowGraphError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            WorkflowGraphError::InvalidOperation(format!(
+                "Step {} has an invalid SLA: {e}",
+                step.name
+            ))
+        };
+
+        if let Some(value) = step.config.get(SLA_CONFIG_KEY) {
+            let mut sla: StepSla =
+                serde_json::from_value(value.clone()).map_err(|e| invalid(&e))?;
+            sla.escalations
+                .sort_by_key(|escalation| escalation.after_minutes);
+            return Ok(Some(sla));
+        }
+        match step.config.get(ESCALATION_HOURS_KEY) {
+            Some(value) => {
+                let hours = value
+                    .as_f64()
+                    .filter(|hours| *hours >= 0.0)
+                    .ok_or_else(|| invalid(&format!("{ESCALATION_HOURS_KEY} is {value}")))?;
+                Ok(Some(StepSla {
+                    deadline: Deadline::WithinMinutes((hours * 60.0).round() as u64),
+                    escalations: Vec::new(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// A step missed its deadline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepSlaBreached {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub deadline: DateTime<Utc>,
+    pub breached_at: DateTime<Utc>,
+}
+
+/// An escalation level fired for a step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepEscalated {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    /// Position in the escalation chain, from zero
+    pub level: usize,
+    pub action: EscalationAction,
+    /// Assignee before the escalation
+    pub previous_assignee: Option<String>,
+    pub escalated_at: DateTime<Utc>,
+}
+
+/// Events raised by the SLA monitor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlaEvent {
+    StepSlaBreached(StepSlaBreached),
+    StepEscalated(StepEscalated),
+}
+
+/// Watches steps' deadlines and fires escalations
+#[derive(Debug, Clone)]
+pub struct SlaMonitor {
+    clock: Arc<dyn Clock>,
+    overrides: HashMap<StepId, StepSla>,
+    tracked: HashMap<(WorkflowId, StepId), Tracked>,
+}
+
+/// What the monitor remembers about a step
+#[derive(Debug, Clone, Default)]
+struct Tracked {
+    executable_since: Option<DateTime<Utc>>,
+    breached: bool,
+    escalations: usize,
+}
+
+impl SlaMonitor {
+    /// Monitor with a clock
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self::with_clock(Arc::new(clock))
+    }
+
+    /// Monitor with a shared clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            overrides: HashMap::new(),
+            tracked: HashMap::new(),
+        }
+    }
+
+    /// Use an SLA for a step instead of its config
+    pub fn with_sla(mut self, step_id: StepId, sla: StepSla) -> Self {
+        self.overrides.insert(step_id, sla);
+        self
+    }
+
+    /// Deadline of a step, once it is known
+    ///
+    /// Relative deadlines are known from when the monitor first sees the
+    /// step executable.
+    pub fn deadline_of(
+        &self,
+        graph: &WorkflowGraph,
+        step_id: StepId,
+    ) -> Result<Option<DateTime<Utc>>, WorkflowGraphError> {
+        let Some(step) = graph.workflow.steps.get(&step_id) else {
+            return Err(WorkflowGraphError::StepNotFound(
+                step_id.as_uuid().to_string(),
+            ));
+        };
+        let Some(sla) = self.sla_of(step)? else {
+            return Ok(None);
+        };
+        let since = self
+            .tracked
+            .get(&(graph.id(), step_id))
+            .and_then(|tracked| tracked.executable_since);
+        Ok(deadline(&sla, since))
+    }
+
+    /// Check every unfinished step of a running workflow at the clock's
+    /// current time, applying due escalations
+    ///
+    /// Each breach and escalation is reported once. If any step's SLA config
+    /// is invalid, nothing is evaluated or changed. Reassignments are not
+    /// domain events, so they are not recorded in the workflow's event log.
+    pub fn evaluate(
+        &mut self,
+        graph: &mut WorkflowGraph,
+    ) -> Result<Vec<SlaEvent>, WorkflowGraphError> {
+        if graph.status() != &WorkflowStatus::Running {
+            return Ok(Vec::new());
+        }
+        let now = self.clock.now();
+        let workflow_id = graph.id();
+        let mut events = Vec::new();
+        let mut reassigned = false;
+
+        // Resolve every SLA first, so a bad config changes nothing
+        let mut slas = Vec::new();
+        for id in topology::topological_order(graph) {
+            let step = &graph.workflow.steps[&id];
+            if is_finished(step) {
+                continue;
+            }
+            if let Some(sla) = self.sla_of(step)? {
+                slas.push((id, sla));
+            }
+        }
+
+        for (id, sla) in slas {
+            let step = &graph.workflow.steps[&id];
+            let tracked = self.tracked.entry((workflow_id, id)).or_default();
+            if tracked.executable_since.is_none() && is_executable(graph, step) {
+                tracked.executable_since = Some(executable_since(graph, step, now));
+            }
+            let Some(deadline) = deadline(&sla, tracked.executable_since) else {
+                continue;
+            };
+            if now <= deadline {
+                continue;
+            }
+
+            if !tracked.breached {
+                tracked.breached = true;
+                events.push(SlaEvent::StepSlaBreached(StepSlaBreached {
+                    workflow_id,
+                    step_id: id,
+                    deadline,
+                    breached_at: now,
+                }));
+            }
+
+            while let Some(escalation) = sla.escalations.get(tracked.escalations) {
+                if now < deadline + Duration::minutes(escalation.after_minutes as i64) {
+                    break;
+                }
+                let step = graph.workflow.steps.get_mut(&id).expect("step exists");
+                let previous_assignee = step.assigned_to.clone();
+                if let EscalationAction::Reassign(assignee) = &escalation.action {
+                    step.assigned_to = Some(assignee.clone());
+                    reassigned = true;
+                }
+                events.push(SlaEvent::StepEscalated(StepEscalated {
+                    workflow_id,
+                    step_id: id,
+                    level: tracked.escalations,
+                    action: escalation.action.clone(),
+                    previous_assignee,
+                    escalated_at: now,
+                }));
+                tracked.escalations += 1;
+            }
+        }
+
+        if reassigned {
+            graph.refresh_context_graph();
+        }
+        Ok(events)
+    }
+
+    fn sla_of(&self, step: &WorkflowStep) -> Result<Option<StepSla>, WorkflowGraphError> {
+        match self.overrides.get(&step.id) {
+            Some(sla) => Ok(Some(sla.clone())),
+            None => StepSla::of_step(step),
+        }
+    }
+}
+
+pub(crate) fn deadline(
+    sla: &StepSla,
+    executable_since: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    match sla.deadline {
+        Deadline::At(at) => Some(at),
+        Deadline::WithinMinutes(minutes) => {
+            executable_since.map(|since| since + Duration::minutes(minutes as i64))
+        }
+    }
+}
+
+fn is_executable(graph: &WorkflowGraph, step: &WorkflowStep) -> bool {
+    step.dependencies
+        .iter()
+        .all(|dependency| graph.workflow.steps.get(dependency).is_none_or(is_finished))
+}
+
+/// When the last dependency completed, or `now` when that is not recorded
+pub(crate) fn executable_since(
+    graph: &WorkflowGraph,
+    step: &WorkflowStep,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let completions: Option<Vec<DateTime<Utc>>> = step
+        .dependencies
+        .iter()
+        .filter_map(|dependency| graph.workflow.steps.get(dependency))
+        .map(|dependency| dependency.completed_at)
+        .collect();
+    match completions {
+        Some(times) if !times.is_empty() => times.into_iter().max().expect("not empty"),
+        _ => now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::{EventStore, InMemoryEventStore};
+    use cim_domain_workflow::value_objects::{StepStatus, StepType};
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    fn approval_workflow(
+        config: HashMap<String, serde_json::Value>,
+    ) -> (WorkflowGraph, StepId, StepId) {
+        let mut graph = WorkflowGraph::new("Expenses".to_string(), "Approval".to_string()).unwrap();
+        let submit = graph
+            .add_step(
+                "Submit".to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                vec![],
+                Some(10),
+                Some("employee".to_string()),
+            )
+            .unwrap();
+        let approve = graph
+            .add_step(
+                "Approve".to_string(),
+                String::new(),
+                StepType::Approval,
+                config,
+                vec![submit],
+                Some(30),
+                Some("manager".to_string()),
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+
+        let step = graph.workflow.steps.get_mut(&submit).unwrap();
+        step.status = StepStatus::Completed;
+        step.completed_at = Some(utc("2025-05-05T09:00:00Z"));
+        (graph, submit, approve)
+    }
+
+    #[test]
+    fn test_relative_deadline_and_escalation_chain() {
+        let (mut graph, _, approve) = approval_workflow(HashMap::new());
+        let clock = ManualClock::new(utc("2025-05-05T12:00:00Z"));
+        let sla = StepSla::within(Duration::hours(8))
+            .escalate(
+                Duration::zero(),
+                EscalationAction::Notify("manager".to_string()),
+            )
+            .escalate(
+                Duration::hours(4),
+                EscalationAction::Reassign("director".to_string()),
+            );
+        let mut monitor = SlaMonitor::new(clock.clone()).with_sla(approve, sla);
+
+        assert!(monitor.evaluate(&mut graph).unwrap().is_empty());
+        assert_eq!(
+            monitor.deadline_of(&graph, approve).unwrap(),
+            Some(utc("2025-05-05T17:00:00Z"))
+        );
+
+        clock.set(utc("2025-05-05T17:01:00Z"));
+        let events = monitor.evaluate(&mut graph).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], SlaEvent::StepSlaBreached(e) if e.step_id == approve));
+        assert!(matches!(&events[1], SlaEvent::StepEscalated(e) if e.level == 0));
+        assert!(monitor.evaluate(&mut graph).unwrap().is_empty());
+
+        clock.advance(Duration::hours(4));
+        let events = monitor.evaluate(&mut graph).unwrap();
+        match &events[..] {
+            [SlaEvent::StepEscalated(escalated)] => {
+                assert_eq!(escalated.level, 1);
+                assert_eq!(escalated.previous_assignee.as_deref(), Some("manager"));
+            }
+            other => panic!("expected one escalation, got {other:?}"),
+        }
+        assert_eq!(
+            graph.workflow.steps[&approve].assigned_to.as_deref(),
+            Some("director")
+        );
+    }
+
+    #[test]
+    fn test_escalation_hours_config() {
+        let config = HashMap::from([(ESCALATION_HOURS_KEY.to_string(), serde_json::json!(24))]);
+        let (mut graph, _, approve) = approval_workflow(config);
+        let clock = ManualClock::new(utc("2025-05-06T08:59:00Z"));
+        let mut monitor = SlaMonitor::new(clock.clone());
+
+        assert!(monitor.evaluate(&mut graph).unwrap().is_empty());
+        clock.advance(Duration::minutes(2));
+        let events = monitor.evaluate(&mut graph).unwrap();
+        assert_eq!(
+            events,
+            vec![SlaEvent::StepSlaBreached(StepSlaBreached {
+                workflow_id: graph.id(),
+                step_id: approve,
+                deadline: utc("2025-05-06T09:00:00Z"),
+                breached_at: utc("2025-05-06T09:01:00Z"),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_absolute_deadline_from_config() {
+        let sla = StepSla::at(utc("2025-05-01T00:00:00Z")).escalate(
+            Duration::zero(),
+            EscalationAction::Reassign("finance".to_string()),
+        );
+        let config = HashMap::from([(
+            SLA_CONFIG_KEY.to_string(),
+            serde_json::to_value(&sla).unwrap(),
+        )]);
+        let (mut graph, _, approve) = approval_workflow(config);
+        let mut monitor = SlaMonitor::new(ManualClock::new(utc("2025-05-05T12:00:00Z")));
+        let cid = graph.cid();
+
+        let events = monitor.evaluate(&mut graph).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            graph.workflow.steps[&approve].assigned_to.as_deref(),
+            Some("finance")
+        );
+        assert_ne!(graph.cid(), cid);
+    }
+
+    #[test]
+    fn test_deadline_boundary_and_late_evaluation() {
+        let (mut graph, _, approve) = approval_workflow(HashMap::new());
+        let clock = ManualClock::new(utc("2025-05-05T10:00:00Z"));
+        let sla = StepSla::within(Duration::hours(1))
+            .escalate(
+                Duration::hours(2),
+                EscalationAction::Reassign("director".to_string()),
+            )
+            .escalate(
+                Duration::zero(),
+                EscalationAction::Notify("manager".to_string()),
+            );
+        let mut monitor = SlaMonitor::new(clock.clone()).with_sla(approve, sla);
+
+        // The deadline itself is still on time
+        assert!(monitor.evaluate(&mut graph).unwrap().is_empty());
+
+        // Evaluating long after fires the breach and every level, in order
+        clock.set(utc("2025-05-06T10:00:00Z"));
+        let levels: Vec<usize> = monitor
+            .evaluate(&mut graph)
+            .unwrap()
+            .iter()
+            .map(|event| match event {
+                SlaEvent::StepSlaBreached(_) => usize::MAX,
+                SlaEvent::StepEscalated(e) => e.level,
+            })
+            .collect();
+        assert_eq!(levels, vec![usize::MAX, 0, 1]);
+
+        graph.cancel("Withdrawn".to_string()).unwrap();
+        clock.advance(Duration::days(1));
+        assert!(monitor.evaluate(&mut graph).unwrap().is_empty());
+
+        let negative = HashMap::from([(ESCALATION_HOURS_KEY.to_string(), serde_json::json!(-1))]);
+        let (mut graph, _, _) = approval_workflow(negative);
+        assert!(monitor.evaluate(&mut graph).is_err());
+    }
+
+    #[test]
+    fn test_steps_in_a_cycle_never_become_executable() {
+        let (mut graph, submit, approve) = approval_workflow(HashMap::new());
+        let step = graph.workflow.steps.get_mut(&submit).unwrap();
+        step.status = StepStatus::Pending;
+        step.completed_at = None;
+        step.dependencies.push(approve);
+        let clock = ManualClock::new(utc("2025-06-01T00:00:00Z"));
+        let mut monitor = SlaMonitor::new(clock.clone())
+            .with_sla(submit, StepSla::within(Duration::minutes(1)))
+            .with_sla(approve, StepSla::at(utc("2025-05-01T00:00:00Z")));
+
+        // Only the absolute deadline can pass
+        let events = monitor.evaluate(&mut graph).unwrap();
+        assert!(matches!(
+            &events[..],
+            [SlaEvent::StepSlaBreached(e)] if e.step_id == approve
+        ));
+        clock.advance(Duration::days(30));
+        assert!(monitor.evaluate(&mut graph).unwrap().is_empty());
+        assert_eq!(monitor.deadline_of(&graph, submit).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_config_changes_nothing() {
+        let (mut graph, _, approve) = approval_workflow(HashMap::new());
+        // A step after the breaching one, added with a negative escalation time
+        let pay = StepId::new();
+        let step = WorkflowStep {
+            id: pay,
+            name: "Pay".to_string(),
+            dependencies: vec![approve],
+            config: HashMap::from([(ESCALATION_HOURS_KEY.to_string(), serde_json::json!(-1))]),
+            ..graph.workflow.steps[&approve].clone()
+        };
+        graph.workflow.steps.insert(pay, step);
+        let clock = ManualClock::new(utc("2025-05-06T12:00:00Z"));
+        let sla = StepSla::within(Duration::hours(1)).escalate(
+            Duration::zero(),
+            EscalationAction::Reassign("director".to_string()),
+        );
+        let mut monitor = SlaMonitor::new(clock).with_sla(approve, sla);
+
+        assert!(monitor.evaluate(&mut graph).is_err());
+        assert_eq!(
+            graph.workflow.steps[&approve].assigned_to.as_deref(),
+            Some("manager")
+        );
+
+        // Once the config is fixed, the breach and escalation are still reported
+        graph
+            .workflow
+            .steps
+            .get_mut(&pay)
+            .unwrap()
+            .config
+            .remove(ESCALATION_HOURS_KEY);
+        let events = monitor.evaluate(&mut graph).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            graph.workflow.steps[&approve].assigned_to.as_deref(),
+            Some("director")
+        );
+    }
+
+    #[test]
+    fn test_reassignment_is_not_evented() {
+        let (mut graph, _, approve) = approval_workflow(HashMap::new());
+        let mut store = InMemoryEventStore::new();
+        store.save(&mut graph).unwrap();
+
+        let sla = StepSla::within(Duration::hours(1)).escalate(
+            Duration::zero(),
+            EscalationAction::Reassign("director".to_string()),
+        );
+        let clock = ManualClock::new(utc("2025-05-06T12:00:00Z"));
+        let mut monitor = SlaMonitor::new(clock).with_sla(approve, sla);
+        assert_eq!(monitor.evaluate(&mut graph).unwrap().len(), 2);
+        assert!(store.save(&mut graph).unwrap().is_empty());
+
+        let replayed = store.replay(graph.id()).unwrap();
+        assert_eq!(
+            replayed.workflow.steps[&approve].assigned_to.as_deref(),
+            Some("manager")
+        );
+    }
+}
@@ -0,0 +1,523 @@
+//! Snapshots of workflow state for fast restoration
+//!
+//! A [`Snapshot`] captures a workflow's event-sourced state after a given
+//! event in the log, together with that event's CID. Restoring loads the
+//! latest snapshot, checks that its event is in the chain and applies only
+//! the events recorded after it, verifying just that part of the chain.
+//!
+//! A snapshot's state is trusted because the store derived it by replay.
+//! Snapshots from elsewhere, such as a persisted copy, go through
+//! [`SnapshotStore::import`], which replays the log up to the snapshot's
+//! event and rejects any state that replay does not reproduce.
+
+use crate::canonical::{canonical_json, Cid};
+use crate::event_store::{verify_chain, verify_chain_after, EventEnvelope, EventStore};
+use crate::{WorkflowEnvironment, WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Utc};
+use cim_domain_workflow::aggregate::Workflow;
+use cim_domain_workflow::value_objects::{
+    WorkflowContext, WorkflowId, WorkflowStatus, WorkflowStep,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Snapshot every 100 events of a workflow unless configured otherwise
+pub const DEFAULT_SNAPSHOT_FREQUENCY: u64 = 100;
+
+/// Serializable state of a workflow aggregate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotState {
+    pub workflow_id: WorkflowId,
+    pub name: String,
+    pub description: String,
+    pub status: WorkflowStatus,
+    /// Steps ordered by ID
+    pub steps: Vec<WorkflowStep>,
+    pub context: WorkflowContext,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub metadata: HashMap<String, serde_json::Value>,
+    pub version: u64,
+}
+
+impl SnapshotState {
+    /// Capture the state of a workflow graph
+    pub fn capture(graph: &WorkflowGraph) -> Self {
+        let workflow = &graph.workflow;
+        let mut steps: Vec<WorkflowStep> = workflow.steps.values().cloned().collect();
+        steps.sort_by_key(|step| *step.id.as_uuid());
+
+        Self {
+            workflow_id: workflow.id,
+            name: workflow.name.clone(),
+            description: workflow.description.clone(),
+            status: workflow.status.clone(),
+            steps,
+            context: workflow.context.clone(),
+            created_by: workflow.created_by.clone(),
+            created_at: workflow.created_at,
+            metadata: workflow.metadata.clone(),
+            version: workflow.version,
+        }
+    }
+
+    /// Content identifier of the canonical state
+    pub fn cid(&self) -> Cid {
+        Cid::for_bytes(canonical_json(&serde_json::to_value(self).unwrap_or_default()).as_bytes())
+    }
+
+    /// Rebuild a workflow graph from this state
+    pub fn to_graph(&self) -> Result<WorkflowGraph, WorkflowGraphError> {
+        let (mut workflow, _events) = Workflow::new(
+            self.name.clone(),
+            self.description.clone(),
+            self.metadata.clone(),
+            self.created_by.clone(),
+        )
+        .map_err(|e| WorkflowGraphError::DomainError(e.to_string()))?;
+
+        workflow.id = self.workflow_id;
+        workflow.status = self.status.clone();
+        workflow.steps = self.steps.iter().map(|s| (s.id, s.clone())).collect();
+        workflow.context = self.context.clone();
+        workflow.created_at = self.created_at;
+        workflow.version = self.version;
+
+        Ok(WorkflowGraph::from_workflow(workflow))
+    }
+}
+
+/// Workflow state after a given event in the log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Log sequence of the last event included in the state
+    pub sequence: u64,
+    /// CID of the last event included in the state
+    pub event_cid: Cid,
+    /// Number of the workflow's events included in the state
+    pub event_count: u64,
+    pub taken_at: DateTime<Utc>,
+    pub state: SnapshotState,
+    /// CID of `state`
+    pub cid: Cid,
+}
+
+/// Snapshots per workflow, taken at a configurable frequency
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    frequency: u64,
+    snapshots: HashMap<WorkflowId, Vec<Snapshot>>,
+    environment: WorkflowEnvironment,
+}
+
+impl Default for SnapshotStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_FREQUENCY)
+    }
+}
+
+impl SnapshotStore {
+    /// Create a store that snapshots every `frequency` events of a workflow
+    ///
+    /// A frequency of 0 disables automatic snapshots.
+    pub fn new(frequency: u64) -> Self {
+        Self {
+            frequency,
+            snapshots: HashMap::new(),
+            environment: WorkflowEnvironment::system(),
+        }
+    }
+
+    /// Take snapshot times from an environment
+    pub fn with_environment(mut self, environment: WorkflowEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Events of a workflow between automatic snapshots
+    pub fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    /// Change the automatic snapshot frequency
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.frequency = frequency;
+    }
+
+    /// Latest snapshot of a workflow
+    pub fn latest(&self, workflow_id: WorkflowId) -> Option<&Snapshot> {
+        self.snapshots.get(&workflow_id).and_then(|s| s.last())
+    }
+
+    /// All snapshots of a workflow, oldest first
+    pub fn snapshots(&self, workflow_id: WorkflowId) -> &[Snapshot] {
+        self.snapshots
+            .get(&workflow_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Snapshot a workflow at the head of the log
+    pub fn create_snapshot(
+        &mut self,
+        store: &impl EventStore,
+        workflow_id: WorkflowId,
+    ) -> Result<&Snapshot, WorkflowGraphError> {
+        let events = store.events()?;
+        let (graph, tail) = self.restore_from(&events, workflow_id)?;
+        let last = tail
+            .last()
+            .copied()
+            .or_else(|| last_event_of(&events, workflow_id))
+            .ok_or_else(|| unknown_workflow(workflow_id))?;
+
+        let state = SnapshotState::capture(&graph);
+        let snapshot = Snapshot {
+            sequence: last.sequence,
+            event_cid: last.cid,
+            event_count: events
+                .iter()
+                .filter(|envelope| envelope.workflow_id == workflow_id)
+                .count() as u64,
+            taken_at: self.environment.now(),
+            cid: state.cid(),
+            state,
+        };
+
+        let snapshots = self.snapshots.entry(workflow_id).or_default();
+        snapshots.push(snapshot);
+        Ok(snapshots.last().expect("snapshot was just pushed"))
+    }
+
+    /// Add a snapshot taken elsewhere, after checking that replaying the log
+    /// up to its event reproduces its state
+    pub fn import(
+        &mut self,
+        store: &impl EventStore,
+        snapshot: Snapshot,
+    ) -> Result<&Snapshot, WorkflowGraphError> {
+        let events = store.events()?;
+        let workflow_id = snapshot.state.workflow_id;
+        verify_snapshot(&snapshot, &events, workflow_id)?;
+
+        let prefix = &events[..=snapshot.sequence as usize];
+        verify_chain(prefix)?;
+        let replayed = WorkflowGraph::from_events(
+            prefix
+                .iter()
+                .filter(|envelope| envelope.workflow_id == workflow_id)
+                .map(|envelope| &envelope.event),
+        )?;
+        if SnapshotState::capture(&replayed).cid() != snapshot.cid {
+            return Err(WorkflowGraphError::SnapshotRejected(format!(
+                "snapshot at event {} does not match the state replayed from the log",
+                snapshot.sequence
+            )));
+        }
+
+        let snapshots = self.snapshots.entry(workflow_id).or_default();
+        let position = snapshots.partition_point(|s| s.sequence <= snapshot.sequence);
+        snapshots.insert(position, snapshot);
+        Ok(&snapshots[position])
+    }
+
+    /// Snapshot a workflow if enough events were recorded since the last one
+    pub fn snapshot_if_due(
+        &mut self,
+        store: &impl EventStore,
+        workflow_id: WorkflowId,
+    ) -> Result<Option<&Snapshot>, WorkflowGraphError> {
+        if self.frequency == 0 {
+            return Ok(None);
+        }
+        let recorded = store.events_for(workflow_id)?.len() as u64;
+        let snapshotted = self.latest(workflow_id).map_or(0, |s| s.event_count);
+        if recorded.saturating_sub(snapshotted) < self.frequency {
+            return Ok(None);
+        }
+        self.create_snapshot(store, workflow_id).map(Some)
+    }
+
+    /// Restore a workflow from its latest snapshot and the events after it
+    pub fn restore(
+        &self,
+        store: &impl EventStore,
+        workflow_id: WorkflowId,
+    ) -> Result<WorkflowGraph, WorkflowGraphError> {
+        let events = store.events()?;
+        self.restore_from(&events, workflow_id)
+            .map(|(graph, _tail)| graph)
+    }
+
+    /// Restore from a log, returning the graph and the events applied after
+    /// the snapshot
+    fn restore_from<'a>(
+        &self,
+        events: &'a [EventEnvelope],
+        workflow_id: WorkflowId,
+    ) -> Result<(WorkflowGraph, Vec<&'a EventEnvelope>), WorkflowGraphError> {
+        let Some(snapshot) = self.latest(workflow_id) else {
+            verify_chain(events)?;
+            let history: Vec<&EventEnvelope> = events
+                .iter()
+                .filter(|envelope| envelope.workflow_id == workflow_id)
+                .collect();
+            if history.is_empty() {
+                return Err(unknown_workflow(workflow_id));
+            }
+            let graph = WorkflowGraph::from_events(history.iter().map(|e| &e.event))?;
+            return Ok((graph, history));
+        };
+
+        verify_snapshot(snapshot, events, workflow_id)?;
+        verify_chain_after(events, snapshot.sequence, snapshot.event_cid)?;
+        let tail: Vec<&EventEnvelope> = events
+            .iter()
+            .filter(|e| e.workflow_id == workflow_id && e.sequence > snapshot.sequence)
+            .collect();
+
+        let mut graph = snapshot.state.to_graph()?;
+        graph.metadata.event_sourced = true;
+        graph.apply_events(tail.iter().map(|e| &e.event))?;
+        Ok((graph, tail))
+    }
+}
+
+/// Check a snapshot against its own CID and the event it was taken after
+fn verify_snapshot(
+    snapshot: &Snapshot,
+    events: &[EventEnvelope],
+    workflow_id: WorkflowId,
+) -> Result<(), WorkflowGraphError> {
+    if snapshot.state.workflow_id != workflow_id || snapshot.state.cid() != snapshot.cid {
+        return Err(WorkflowGraphError::SnapshotRejected(format!(
+            "snapshot at event {} does not match its CID {}",
+            snapshot.sequence, snapshot.cid
+        )));
+    }
+
+    let anchored = events
+        .get(snapshot.sequence as usize)
+        .is_some_and(|envelope| {
+            envelope.cid == snapshot.event_cid
+                && envelope.compute_cid() == envelope.cid
+                && envelope.workflow_id == workflow_id
+        });
+    if !anchored {
+        return Err(WorkflowGraphError::SnapshotRejected(format!(
+            "event {} with CID {} is not in the event chain",
+            snapshot.sequence, snapshot.event_cid
+        )));
+    }
+
+    let included = events[..=snapshot.sequence as usize]
+        .iter()
+        .filter(|envelope| envelope.workflow_id == workflow_id)
+        .count() as u64;
+    if included != snapshot.event_count {
+        return Err(WorkflowGraphError::SnapshotRejected(format!(
+            "snapshot at event {} claims {} events, the chain has {included}",
+            snapshot.sequence, snapshot.event_count
+        )));
+    }
+
+    Ok(())
+}
+
+fn last_event_of(events: &[EventEnvelope], workflow_id: WorkflowId) -> Option<&EventEnvelope> {
+    events
+        .iter()
+        .rev()
+        .find(|envelope| envelope.workflow_id == workflow_id)
+}
+
+fn unknown_workflow(workflow_id: WorkflowId) -> WorkflowGraphError {
+    WorkflowGraphError::InvalidOperation(format!(
+        "No events recorded for workflow {}",
+        workflow_id.as_uuid()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::InMemoryEventStore;
+    use cim_domain_workflow::value_objects::{StepId, StepStatus, StepType};
+
+    fn record(store: &mut InMemoryEventStore) -> (WorkflowGraph, Vec<StepId>) {
+        let mut graph =
+            WorkflowGraph::new("Snapshot".to_string(), "Long running".to_string()).unwrap();
+        let mut steps = Vec::new();
+        for name in ["Collect", "Check", "Approve"] {
+            let dependencies = steps.last().copied().into_iter().collect();
+            let id = graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    StepType::Manual,
+                    HashMap::new(),
+                    dependencies,
+                    Some(10),
+                    None,
+                )
+                .unwrap();
+            steps.push(id);
+        }
+        graph.start(HashMap::new()).unwrap();
+        store.save(&mut graph).unwrap();
+        (graph, steps)
+    }
+
+    fn complete(store: &mut InMemoryEventStore, graph: &mut WorkflowGraph, step: StepId) {
+        let events = graph
+            .workflow
+            .complete_task(step, "tester".to_string(), HashMap::new())
+            .unwrap();
+        store
+            .append_all(graph.id(), events, graph.environment().now())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_frequency() {
+        let mut store = InMemoryEventStore::new();
+        let mut snapshots = SnapshotStore::new(3);
+        let (mut graph, steps) = record(&mut store);
+
+        let taken = snapshots.snapshot_if_due(&store, graph.id()).unwrap();
+        assert_eq!(taken.unwrap().event_count, 5);
+        assert!(snapshots
+            .snapshot_if_due(&store, graph.id())
+            .unwrap()
+            .is_none());
+
+        complete(&mut store, &mut graph, steps[0]);
+        complete(&mut store, &mut graph, steps[1]);
+        assert!(snapshots
+            .snapshot_if_due(&store, graph.id())
+            .unwrap()
+            .is_none());
+        complete(&mut store, &mut graph, steps[2]);
+        assert!(snapshots
+            .snapshot_if_due(&store, graph.id())
+            .unwrap()
+            .is_some());
+        assert_eq!(snapshots.snapshots(graph.id()).len(), 2);
+
+        snapshots.set_frequency(0);
+        assert!(snapshots
+            .snapshot_if_due(&store, graph.id())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_restore_from_snapshot_and_tail() {
+        let mut store = InMemoryEventStore::new();
+        let mut snapshots = SnapshotStore::default();
+        let (mut graph, steps) = record(&mut store);
+        let (other, _) = record(&mut store);
+
+        snapshots.create_snapshot(&store, graph.id()).unwrap();
+        complete(&mut store, &mut graph, steps[0]);
+
+        let restored = snapshots.restore(&store, graph.id()).unwrap();
+        let replayed = store.replay(graph.id()).unwrap();
+        assert_eq!(
+            SnapshotState::capture(&restored),
+            SnapshotState::capture(&replayed)
+        );
+        assert_eq!(
+            restored.workflow.steps[&steps[0]].status,
+            StepStatus::Completed
+        );
+        assert_eq!(restored.get_executable_steps(), vec![steps[1]]);
+        assert_eq!(
+            snapshots.restore(&store, other.id()).unwrap().id(),
+            other.id()
+        );
+    }
+
+    #[test]
+    fn test_tampered_snapshot_is_rejected() {
+        let mut store = InMemoryEventStore::new();
+        let mut snapshots = SnapshotStore::default();
+        let (graph, _) = record(&mut store);
+        snapshots.create_snapshot(&store, graph.id()).unwrap();
+
+        let mut forged = snapshots.clone();
+        let snapshot = forged
+            .snapshots
+            .get_mut(&graph.id())
+            .unwrap()
+            .last_mut()
+            .unwrap();
+        snapshot.state.status = WorkflowStatus::Completed;
+        assert!(matches!(
+            forged.restore(&store, graph.id()),
+            Err(WorkflowGraphError::SnapshotRejected(_))
+        ));
+
+        let mut unanchored = snapshots.clone();
+        let snapshot = unanchored
+            .snapshots
+            .get_mut(&graph.id())
+            .unwrap()
+            .last_mut()
+            .unwrap();
+        snapshot.event_cid = Cid::for_bytes(b"not an event");
+        assert!(matches!(
+            unanchored.restore(&store, graph.id()),
+            Err(WorkflowGraphError::SnapshotRejected(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_verifies_only_events_after_snapshot() {
+        let mut store = InMemoryEventStore::new();
+        let mut snapshots = SnapshotStore::default();
+        let (mut graph, steps) = record(&mut store);
+        snapshots.create_snapshot(&store, graph.id()).unwrap();
+        complete(&mut store, &mut graph, steps[0]);
+
+        let mut events = store.events().unwrap();
+        events[0].recorded_at += chrono::Duration::seconds(1);
+        assert!(snapshots.restore_from(&events, graph.id()).is_ok());
+
+        let last = events.len() - 1;
+        events[last].recorded_at += chrono::Duration::seconds(1);
+        assert!(matches!(
+            snapshots.restore_from(&events, graph.id()),
+            Err(WorkflowGraphError::ChainBroken(_))
+        ));
+    }
+
+    #[test]
+    fn test_import_rejects_forged_state() {
+        let mut store = InMemoryEventStore::new();
+        let mut snapshots = SnapshotStore::default();
+        let (graph, _) = record(&mut store);
+        let genuine = snapshots
+            .create_snapshot(&store, graph.id())
+            .unwrap()
+            .clone();
+
+        let mut forged: Snapshot =
+            serde_json::from_value(serde_json::to_value(&genuine).unwrap()).unwrap();
+        forged.state.status = WorkflowStatus::Completed;
+        forged.cid = forged.state.cid();
+
+        let mut imported = SnapshotStore::default();
+        assert!(matches!(
+            imported.import(&store, forged),
+            Err(WorkflowGraphError::SnapshotRejected(_))
+        ));
+        assert!(imported.latest(graph.id()).is_none());
+
+        imported.import(&store, genuine).unwrap();
+        assert_eq!(
+            SnapshotState::capture(&imported.restore(&store, graph.id()).unwrap()),
+            SnapshotState::capture(&store.replay(graph.id()).unwrap())
+        );
+    }
+}
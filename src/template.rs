@@ -0,0 +1,480 @@
+//! Parameterized workflow templates
+//!
+//! A [`WorkflowTemplate`] describes a workflow once, with named parameters in
+//! place of the values that differ between runs (assignees, notification
+//! lists, ...). [`WorkflowTemplate::instantiate`] produces a fresh
+//! [`WorkflowGraph`] with new identifiers and the parameters substituted.
+//!
+//! Parameters are referenced as `{{name}}`. A string that consists solely of a
+//! reference is replaced by the parameter value as-is, so it can expand to a
+//! number, list or object; references embedded in longer strings are
+//! interpolated as text.
+
+use crate::{WorkflowEnvironment, WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Metadata property recording the template a workflow was instantiated from
+pub const TEMPLATE_ID_PROPERTY: &str = "template_id";
+
+/// Metadata property recording the template version a workflow was instantiated from
+pub const TEMPLATE_VERSION_PROPERTY: &str = "template_version";
+
+/// A declared template parameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateParameter {
+    pub name: String,
+    pub description: String,
+    /// Value used when the parameter is not supplied; `None` makes it required
+    pub default: Option<serde_json::Value>,
+}
+
+impl TemplateParameter {
+    /// Declare a parameter that must be supplied on instantiation
+    pub fn required(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            default: None,
+        }
+    }
+
+    /// Declare a parameter with a default value
+    pub fn optional(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        default: serde_json::Value,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            default: Some(default),
+        }
+    }
+}
+
+/// A step definition inside a template
+///
+/// Dependencies refer to other template steps by `key`, since step IDs are
+/// only assigned on instantiation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateStep {
+    pub key: String,
+    pub name: String,
+    pub description: String,
+    pub step_type: StepType,
+    pub config: HashMap<String, serde_json::Value>,
+    pub dependencies: Vec<String>,
+    pub estimated_duration_minutes: Option<u32>,
+    pub assigned_to: Option<String>,
+}
+
+/// A parameterized workflow definition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowTemplate {
+    pub id: String,
+    pub version: u32,
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub parameters: Vec<TemplateParameter>,
+    pub steps: Vec<TemplateStep>,
+}
+
+impl WorkflowTemplate {
+    /// Create an empty template
+    pub fn new(id: String, version: u32, name: String, description: String) -> Self {
+        Self {
+            id,
+            version,
+            name,
+            description,
+            tags: Vec::new(),
+            parameters: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Declare a parameter
+    pub fn add_parameter(
+        &mut self,
+        parameter: TemplateParameter,
+    ) -> Result<(), WorkflowGraphError> {
+        if self.parameter(&parameter.name).is_some() {
+            return Err(WorkflowGraphError::InvalidParameter(format!(
+                "Parameter {} is already declared",
+                parameter.name
+            )));
+        }
+        self.parameters.push(parameter);
+        Ok(())
+    }
+
+    /// Add a step; its dependencies must already be part of the template
+    pub fn add_step(&mut self, step: TemplateStep) -> Result<(), WorkflowGraphError> {
+        if self.steps.iter().any(|s| s.key == step.key) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Template step {} is already defined",
+                step.key
+            )));
+        }
+        for dep in &step.dependencies {
+            if !self.steps.iter().any(|s| &s.key == dep) {
+                return Err(WorkflowGraphError::InvalidDependency(format!(
+                    "Template step {} depends on undefined step {dep}",
+                    step.key
+                )));
+            }
+        }
+        self.steps.push(step);
+        Ok(())
+    }
+
+    /// Add metadata tag applied to every instance
+    pub fn add_tag(&mut self, tag: String) {
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+    }
+
+    /// Get a declared parameter by name
+    pub fn parameter(&self, name: &str) -> Option<&TemplateParameter> {
+        self.parameters.iter().find(|p| p.name == name)
+    }
+
+    /// Resolve supplied values against the declared parameters and defaults
+    pub fn resolve_parameters(
+        &self,
+        params: &HashMap<String, serde_json::Value>,
+    ) -> Result<HashMap<String, serde_json::Value>, WorkflowGraphError> {
+        if let Some(unknown) = params.keys().find(|name| self.parameter(name).is_none()) {
+            return Err(WorkflowGraphError::InvalidParameter(format!(
+                "Template {} does not declare parameter {unknown}",
+                self.id
+            )));
+        }
+
+        self.parameters
+            .iter()
+            .map(|parameter| {
+                params
+                    .get(&parameter.name)
+                    .or(parameter.default.as_ref())
+                    .map(|value| (parameter.name.clone(), value.clone()))
+                    .ok_or_else(|| {
+                        WorkflowGraphError::InvalidParameter(format!(
+                            "Missing required parameter {}",
+                            parameter.name
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    /// Create a new workflow graph from this template
+    ///
+    /// The instance gets fresh workflow and step IDs, and records the template
+    /// ID and version in its metadata properties.
+    pub fn instantiate(
+        &self,
+        params: HashMap<String, serde_json::Value>,
+    ) -> Result<WorkflowGraph, WorkflowGraphError> {
+        self.instantiate_in(params, WorkflowEnvironment::system())
+    }
+
+    /// Create a new workflow graph from this template, taking IDs and
+    /// timestamps from an environment
+    pub fn instantiate_in(
+        &self,
+        params: HashMap<String, serde_json::Value>,
+        environment: WorkflowEnvironment,
+    ) -> Result<WorkflowGraph, WorkflowGraphError> {
+        let values = self.resolve_parameters(&params)?;
+
+        let mut graph = WorkflowGraph::new_in(
+            substitute_text(&self.name, &values)?,
+            substitute_text(&self.description, &values)?,
+            environment,
+        )?;
+
+        let mut step_ids: HashMap<&str, StepId> = HashMap::new();
+        for step in &self.steps {
+            let config = step
+                .config
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), substitute_value(value, &values)?)))
+                .collect::<Result<HashMap<_, _>, WorkflowGraphError>>()?;
+            // Templates may be deserialized without going through `add_step`
+            let dependencies = step
+                .dependencies
+                .iter()
+                .map(|dep| {
+                    step_ids.get(dep.as_str()).copied().ok_or_else(|| {
+                        WorkflowGraphError::InvalidDependency(format!(
+                            "Template step {} depends on {dep}, which is not defined before it",
+                            step.key
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let assigned_to = step
+                .assigned_to
+                .as_deref()
+                .map(|assignee| substitute_text(assignee, &values))
+                .transpose()?;
+
+            let step_id = graph.add_step(
+                substitute_text(&step.name, &values)?,
+                substitute_text(&step.description, &values)?,
+                step.step_type.clone(),
+                config,
+                dependencies,
+                step.estimated_duration_minutes,
+                assigned_to,
+            )?;
+            step_ids.insert(step.key.as_str(), step_id);
+        }
+
+        for tag in &self.tags {
+            graph.add_tag(tag.clone());
+        }
+        graph.set_property(TEMPLATE_ID_PROPERTY.to_string(), serde_json::json!(self.id));
+        graph.set_property(
+            TEMPLATE_VERSION_PROPERTY.to_string(),
+            serde_json::json!(self.version),
+        );
+
+        Ok(graph)
+    }
+}
+
+/// Substitute parameter references in a JSON value
+fn substitute_value(
+    value: &serde_json::Value,
+    values: &HashMap<String, serde_json::Value>,
+) -> Result<serde_json::Value, WorkflowGraphError> {
+    match value {
+        serde_json::Value::String(text) => match whole_reference(text) {
+            Some(name) => lookup(name, values).cloned(),
+            None => substitute_text(text, values).map(serde_json::Value::String),
+        },
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(|item| substitute_value(item, values))
+            .collect::<Result<Vec<_>, _>>()
+            .map(serde_json::Value::Array),
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, item)| Ok((key.clone(), substitute_value(item, values)?)))
+            .collect::<Result<serde_json::Map<_, _>, WorkflowGraphError>>()
+            .map(serde_json::Value::Object),
+        other => Ok(other.clone()),
+    }
+}
+
+/// Interpolate parameter references into a string
+fn substitute_text(
+    text: &str,
+    values: &HashMap<String, serde_json::Value>,
+) -> Result<String, WorkflowGraphError> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + len].trim();
+        match lookup(name, values)? {
+            serde_json::Value::String(s) => result.push_str(s),
+            other => result.push_str(&other.to_string()),
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Parameter name if the string is exactly one `{{name}}` reference
+fn whole_reference(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix("{{")?.strip_suffix("}}")?;
+    (!inner.contains("{{") && !inner.contains("}}")).then(|| inner.trim())
+}
+
+fn lookup<'a>(
+    name: &str,
+    values: &'a HashMap<String, serde_json::Value>,
+) -> Result<&'a serde_json::Value, WorkflowGraphError> {
+    values.get(name).ok_or_else(|| {
+        WorkflowGraphError::InvalidParameter(format!("Reference to undeclared parameter {name}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approval_template() -> WorkflowTemplate {
+        let mut template = WorkflowTemplate::new(
+            "document-approval".to_string(),
+            3,
+            "Document Approval: {{document}}".to_string(),
+            "Approval of {{document}}".to_string(),
+        );
+        template
+            .add_parameter(TemplateParameter::required("document", "Document title"))
+            .unwrap();
+        template
+            .add_parameter(TemplateParameter::required(
+                "reviewer",
+                "Technical reviewer",
+            ))
+            .unwrap();
+        template
+            .add_parameter(TemplateParameter::optional(
+                "notification_list",
+                "Recipients of the publication notice",
+                serde_json::json!(["all-staff@company.com"]),
+            ))
+            .unwrap();
+
+        template
+            .add_step(TemplateStep {
+                key: "review".to_string(),
+                name: "Technical Review".to_string(),
+                description: "Review {{document}}".to_string(),
+                step_type: StepType::Manual,
+                config: HashMap::new(),
+                dependencies: Vec::new(),
+                estimated_duration_minutes: Some(60),
+                assigned_to: Some("{{reviewer}}".to_string()),
+            })
+            .unwrap();
+        template
+            .add_step(TemplateStep {
+                key: "publish".to_string(),
+                name: "Publish Document".to_string(),
+                description: "Publish {{document}}".to_string(),
+                step_type: StepType::Automated,
+                config: HashMap::from([(
+                    "notification_list".to_string(),
+                    serde_json::json!("{{notification_list}}"),
+                )]),
+                dependencies: vec!["review".to_string()],
+                estimated_duration_minutes: Some(5),
+                assigned_to: None,
+            })
+            .unwrap();
+        template
+    }
+
+    #[test]
+    fn test_instantiate_substitutes_parameters() {
+        let template = approval_template();
+        let graph = template
+            .instantiate(HashMap::from([
+                ("document".to_string(), serde_json::json!("Q3 Report")),
+                ("reviewer".to_string(), serde_json::json!("alice")),
+            ]))
+            .unwrap();
+
+        assert_eq!(graph.name(), "Document Approval: Q3 Report");
+        assert_eq!(
+            graph.get_property(TEMPLATE_ID_PROPERTY),
+            Some(&serde_json::json!("document-approval"))
+        );
+        assert_eq!(
+            graph.get_property(TEMPLATE_VERSION_PROPERTY),
+            Some(&serde_json::json!(3))
+        );
+
+        let review = graph
+            .workflow
+            .steps
+            .values()
+            .find(|s| s.name == "Technical Review")
+            .unwrap();
+        assert_eq!(review.assigned_to.as_deref(), Some("alice"));
+        assert_eq!(review.description, "Review Q3 Report");
+
+        let publish = graph
+            .workflow
+            .steps
+            .values()
+            .find(|s| s.name == "Publish Document")
+            .unwrap();
+        assert_eq!(publish.dependencies, vec![review.id]);
+        assert_eq!(
+            publish.config["notification_list"],
+            serde_json::json!(["all-staff@company.com"])
+        );
+        assert!(graph.validate().is_ok());
+    }
+
+    #[test]
+    fn test_instances_get_fresh_ids() {
+        let template = approval_template();
+        let params = HashMap::from([
+            ("document".to_string(), serde_json::json!("A")),
+            ("reviewer".to_string(), serde_json::json!("bob")),
+        ]);
+        let first = template.instantiate(params.clone()).unwrap();
+        let second = template.instantiate(params).unwrap();
+
+        assert_ne!(first.id(), second.id());
+        assert!(first
+            .workflow
+            .steps
+            .keys()
+            .all(|id| !second.workflow.steps.contains_key(id)));
+    }
+
+    #[test]
+    fn test_parameter_errors() {
+        let template = approval_template();
+
+        let missing = template.instantiate(HashMap::from([(
+            "document".to_string(),
+            serde_json::json!("A"),
+        )]));
+        assert!(matches!(
+            missing,
+            Err(WorkflowGraphError::InvalidParameter(_))
+        ));
+
+        let unknown = template.instantiate(HashMap::from([
+            ("document".to_string(), serde_json::json!("A")),
+            ("reviewer".to_string(), serde_json::json!("bob")),
+            ("reviwer".to_string(), serde_json::json!("bob")),
+        ]));
+        assert!(matches!(
+            unknown,
+            Err(WorkflowGraphError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialized_template_with_bad_dependencies() {
+        let mut value = serde_json::to_value(approval_template()).unwrap();
+        value["steps"][1]["dependencies"] = serde_json::json!(["missing"]);
+        let undefined: WorkflowTemplate = serde_json::from_value(value.clone()).unwrap();
+
+        value["steps"][1]["dependencies"] = serde_json::json!(["review"]);
+        value["steps"].as_array_mut().unwrap().reverse();
+        let out_of_order: WorkflowTemplate = serde_json::from_value(value).unwrap();
+
+        let params = HashMap::from([
+            ("document".to_string(), serde_json::json!("A")),
+            ("reviewer".to_string(), serde_json::json!("bob")),
+        ]);
+        for template in [undefined, out_of_order] {
+            assert!(matches!(
+                template.instantiate(params.clone()),
+                Err(WorkflowGraphError::InvalidDependency(_))
+            ));
+        }
+    }
+}
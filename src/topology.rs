@@ -0,0 +1,147 @@
+//! Graph traversals over a workflow's step dependencies
+//!
+//! Dependencies naming steps that are not in the workflow are ignored.
+
+use crate::WorkflowGraph;
+use cim_domain_workflow::value_objects::{StepId, WorkflowStep};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// Steps that depend directly on each step
+pub(crate) fn dependents(graph: &WorkflowGraph) -> HashMap<StepId, Vec<StepId>> {
+    let steps = &graph.workflow.steps;
+    let mut dependents: HashMap<StepId, Vec<StepId>> =
+        steps.keys().map(|id| (*id, Vec::new())).collect();
+    for step in steps.values() {
+        for dependency in &step.dependencies {
+            if let Some(list) = dependents.get_mut(dependency) {
+                list.push(step.id);
+            }
+        }
+    }
+    dependents
+}
+
+/// Steps the given step transitively depends on
+pub(crate) fn ancestors(graph: &WorkflowGraph, step_id: StepId) -> HashSet<StepId> {
+    let steps = &graph.workflow.steps;
+    reachable(step_id, |id| {
+        steps
+            .get(&id)
+            .map(|step| {
+                step.dependencies
+                    .iter()
+                    .copied()
+                    .filter(|dependency| steps.contains_key(dependency))
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// Steps that transitively depend on the given step
+pub(crate) fn descendants(graph: &WorkflowGraph, step_id: StepId) -> HashSet<StepId> {
+    let dependents = dependents(graph);
+    reachable(step_id, |id| {
+        dependents.get(&id).cloned().unwrap_or_default()
+    })
+}
+
+fn reachable(start: StepId, next: impl Fn(StepId) -> Vec<StepId>) -> HashSet<StepId> {
+    let mut seen = HashSet::new();
+    let mut pending = next(start);
+    while let Some(id) = pending.pop() {
+        if seen.insert(id) {
+            pending.extend(next(id));
+        }
+    }
+    seen.remove(&start);
+    seen
+}
+
+/// All steps with every step after its dependencies
+///
+/// Ties are broken by step name and then ID, so the order is stable. Steps
+/// caught in a dependency cycle come last.
+pub(crate) fn topological_order(graph: &WorkflowGraph) -> Vec<StepId> {
+    topological_order_by(graph, by_name)
+}
+
+/// Step name, then ID
+pub(crate) fn by_name(a: &WorkflowStep, b: &WorkflowStep) -> Ordering {
+    a.name
+        .cmp(&b.name)
+        .then_with(|| a.id.as_uuid().cmp(b.id.as_uuid()))
+}
+
+/// All steps with every step after its dependencies, choosing among ready
+/// steps with `compare`
+///
+/// Steps caught in a dependency cycle come last, also ordered by `compare`.
+pub(crate) fn topological_order_by(
+    graph: &WorkflowGraph,
+    compare: impl Fn(&WorkflowStep, &WorkflowStep) -> Ordering,
+) -> Vec<StepId> {
+    let steps = &graph.workflow.steps;
+    let compare = |a: &StepId, b: &StepId| compare(&steps[a], &steps[b]);
+    let dependents = dependents(graph);
+
+    let mut waiting: HashMap<StepId, usize> = steps
+        .values()
+        .map(|step| {
+            let count = step
+                .dependencies
+                .iter()
+                .filter(|dependency| steps.contains_key(dependency))
+                .count();
+            (step.id, count)
+        })
+        .collect();
+    let mut ready: Vec<StepId> = waiting
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(id, _)| *id)
+        .collect();
+
+    let mut order = Vec::with_capacity(steps.len());
+    while let Some(index) = (0..ready.len()).min_by(|a, b| compare(&ready[*a], &ready[*b])) {
+        let id = ready.swap_remove(index);
+        waiting.remove(&id);
+        order.push(id);
+        for dependent in &dependents[&id] {
+            if let Some(count) = waiting.get_mut(dependent) {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(*dependent);
+                }
+            }
+        }
+    }
+
+    let mut cyclic: Vec<StepId> = waiting.into_keys().collect();
+    cyclic.sort_by(compare);
+    order.extend(cyclic);
+    order
+}
+
+/// Earliest finish of each step, in minutes from the start, when every step
+/// begins as soon as its dependencies finish
+pub(crate) fn finish_times(
+    graph: &WorkflowGraph,
+    duration: impl Fn(&WorkflowStep) -> u64,
+) -> HashMap<StepId, u64> {
+    let steps = &graph.workflow.steps;
+    let mut finish: HashMap<StepId, u64> = HashMap::with_capacity(steps.len());
+    for id in topological_order(graph) {
+        let step = &steps[&id];
+        let start = step
+            .dependencies
+            .iter()
+            .filter_map(|dependency| finish.get(dependency))
+            .max()
+            .copied()
+            .unwrap_or(0);
+        finish.insert(id, start + duration(step));
+    }
+    finish
+}
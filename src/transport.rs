@@ -0,0 +1,461 @@
+//! Message transport for workflow events and commands
+//!
+//! Events are published on subjects of the form
+//! `workflow.{workflow_id}.{action}` or, for step events,
+//! `workflow.{workflow_id}.step.{step_id}.{action}`, e.g.
+//! `workflow.{id}.step.{step_id}.completed`. Commands travel on
+//! `commands.workflow.{command_type}`. Subscriptions accept NATS wildcards:
+//! `*` matches one token and a trailing `>` matches one or more tokens.
+//!
+//! [`InMemoryTransport`] implements the full subject semantics without a
+//! server; a NATS-backed transport is available with the `nats` feature.
+
+use crate::commands::{CommandEnvelope, CorrelationId, WorkflowCommand};
+use crate::history::event_name;
+use crate::WorkflowGraphError;
+use cim_domain_workflow::value_objects::WorkflowId;
+use cim_domain_workflow::WorkflowDomainEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[cfg(feature = "nats")]
+mod nats;
+
+#[cfg(feature = "nats")]
+pub use nats::NatsTransport;
+
+/// Pattern matching every workflow event
+pub const ALL_EVENTS: &str = "workflow.>";
+
+/// Pattern matching every workflow command
+pub const ALL_COMMANDS: &str = "commands.workflow.>";
+
+/// Subject an event of a workflow is published on
+pub fn event_subject(workflow_id: WorkflowId, event: &WorkflowDomainEvent) -> String {
+    let step_id = serde_json::to_value(event).ok().and_then(|value| {
+        value
+            .as_object()?
+            .values()
+            .next()?
+            .get("step_id")?
+            .as_str()
+            .map(str::to_string)
+    });
+    let action = event_action(&event_name(event));
+
+    match step_id {
+        Some(step_id) => format!("workflow.{}.step.{step_id}.{action}", workflow_id.as_uuid()),
+        None => format!("workflow.{}.{action}", workflow_id.as_uuid()),
+    }
+}
+
+/// Pattern matching every event of one workflow
+pub fn workflow_events(workflow_id: WorkflowId) -> String {
+    format!("workflow.{}.>", workflow_id.as_uuid())
+}
+
+/// Subject a command is sent on
+pub fn command_subject(command: &WorkflowCommand) -> String {
+    format!("commands.workflow.{}", command.command_type())
+}
+
+/// Check that a subject can be published on: non-empty tokens without
+/// wildcards or whitespace
+pub fn validate_subject(subject: &str) -> Result<(), WorkflowGraphError> {
+    if subject.is_empty() || subject.split('.').any(|t| !is_literal_token(t)) {
+        return Err(WorkflowGraphError::InvalidOperation(format!(
+            "Cannot publish on subject {subject:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a subscription pattern is well formed: non-empty tokens
+/// without whitespace and `>` only at the end
+pub fn validate_pattern(pattern: &str) -> Result<(), WorkflowGraphError> {
+    let tokens: Vec<&str> = pattern.split('.').collect();
+    let misplaced_tail = tokens[..tokens.len() - 1].contains(&">");
+    let malformed = tokens
+        .iter()
+        .any(|t| !matches!(*t, "*" | ">") && !is_literal_token(t));
+    if misplaced_tail || malformed {
+        return Err(WorkflowGraphError::InvalidOperation(format!(
+            "Invalid subscription pattern {pattern:?}"
+        )));
+    }
+    Ok(())
+}
+
+fn is_literal_token(token: &str) -> bool {
+    !token.is_empty() && token != "*" && token != ">" && !token.chars().any(char::is_whitespace)
+}
+
+/// Whether a subject matches a subscription pattern
+pub fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (expected, Some(actual)) if expected == actual => {}
+            _ => return false,
+        }
+    }
+    subject_tokens.next().is_none()
+}
+
+/// Action token of an event name: `TaskCompleted` becomes `completed`
+fn event_action(name: &str) -> String {
+    let mut words: Vec<String> = Vec::new();
+    for c in name.chars() {
+        if c.is_uppercase() || words.is_empty() {
+            words.push(String::new());
+        }
+        if let Some(word) = words.last_mut() {
+            word.extend(c.to_lowercase());
+        }
+    }
+    if words.len() > 1 && matches!(words[0].as_str(), "workflow" | "step" | "task") {
+        words.remove(0);
+    }
+    words.join("_")
+}
+
+/// A message received from a transport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportMessage {
+    pub subject: String,
+    pub payload: Vec<u8>,
+}
+
+/// Payload of a published workflow event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventMessage {
+    pub workflow_id: WorkflowId,
+    /// Correlation ID of the command that caused the event
+    pub correlation_id: Option<CorrelationId>,
+    pub event: WorkflowDomainEvent,
+}
+
+/// Handle of a subscription on a transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// Publishes workflow events and delivers workflow commands
+pub trait WorkflowTransport {
+    /// Publish a raw payload on a subject
+    fn publish(&self, subject: &str, payload: Vec<u8>) -> Result<(), WorkflowGraphError>;
+
+    /// Subscribe to subjects matching a pattern
+    fn subscribe(&self, pattern: &str) -> Result<SubscriptionId, WorkflowGraphError>;
+
+    /// Take the next message of a subscription, if one is waiting
+    fn next_message(
+        &self,
+        subscription: SubscriptionId,
+    ) -> Result<Option<TransportMessage>, WorkflowGraphError>;
+
+    /// Publish a workflow event on its subject, returning the subject
+    fn publish_event(
+        &self,
+        workflow_id: WorkflowId,
+        event: &WorkflowDomainEvent,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<String, WorkflowGraphError> {
+        let subject = event_subject(workflow_id, event);
+        let message = EventMessage {
+            workflow_id,
+            correlation_id,
+            event: event.clone(),
+        };
+        self.publish(&subject, to_payload(&message)?)?;
+        Ok(subject)
+    }
+
+    /// Send a command on its subject
+    ///
+    /// The command type must be a single subject token, so custom types
+    /// containing `.`, wildcards or whitespace are rejected.
+    fn send_command(&self, envelope: &CommandEnvelope) -> Result<(), WorkflowGraphError> {
+        let command_type = envelope.command.command_type();
+        if !is_literal_token(command_type) || command_type.contains('.') {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Command type {command_type:?} is not a valid subject token"
+            )));
+        }
+        self.publish(&command_subject(&envelope.command), to_payload(envelope)?)
+    }
+
+    /// Take the next event of a subscription, if one is waiting
+    fn next_event(
+        &self,
+        subscription: SubscriptionId,
+    ) -> Result<Option<EventMessage>, WorkflowGraphError> {
+        self.next_message(subscription)?
+            .map(|message| from_payload(&message.payload))
+            .transpose()
+    }
+
+    /// Take the next command of a subscription, if one is waiting
+    fn next_command(
+        &self,
+        subscription: SubscriptionId,
+    ) -> Result<Option<CommandEnvelope>, WorkflowGraphError> {
+        self.next_message(subscription)?
+            .map(|message| from_payload(&message.payload))
+            .transpose()
+    }
+}
+
+fn to_payload(value: &impl Serialize) -> Result<Vec<u8>, WorkflowGraphError> {
+    serde_json::to_vec(value).map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))
+}
+
+fn from_payload<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> Result<T, WorkflowGraphError> {
+    serde_json::from_slice(payload)
+        .map_err(|e| WorkflowGraphError::SerializationError(e.to_string()))
+}
+
+/// In-process transport with full wildcard support
+///
+/// Clones share the same message bus, so one clone can publish while another
+/// consumes.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryTransport {
+    bus: Arc<Mutex<Bus>>,
+}
+
+#[derive(Debug, Default)]
+struct Bus {
+    next_subscription: u64,
+    subscriptions: HashMap<SubscriptionId, (String, VecDeque<TransportMessage>)>,
+    published: usize,
+}
+
+impl InMemoryTransport {
+    /// Create a transport with an empty bus
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of messages published on the bus
+    pub fn published_count(&self) -> usize {
+        self.bus
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .published
+    }
+
+    /// Stop delivering messages to a subscription
+    pub fn unsubscribe(&self, subscription: SubscriptionId) -> bool {
+        self.bus
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .subscriptions
+            .remove(&subscription)
+            .is_some()
+    }
+}
+
+impl WorkflowTransport for InMemoryTransport {
+    fn publish(&self, subject: &str, payload: Vec<u8>) -> Result<(), WorkflowGraphError> {
+        validate_subject(subject)?;
+
+        let mut bus = self.bus.lock().unwrap_or_else(PoisonError::into_inner);
+        bus.published += 1;
+        for (pattern, queue) in bus.subscriptions.values_mut() {
+            if subject_matches(pattern, subject) {
+                queue.push_back(TransportMessage {
+                    subject: subject.to_string(),
+                    payload: payload.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self, pattern: &str) -> Result<SubscriptionId, WorkflowGraphError> {
+        validate_pattern(pattern)?;
+
+        let mut bus = self.bus.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = SubscriptionId(bus.next_subscription);
+        bus.next_subscription += 1;
+        bus.subscriptions
+            .insert(id, (pattern.to_string(), VecDeque::new()));
+        Ok(id)
+    }
+
+    fn next_message(
+        &self,
+        subscription: SubscriptionId,
+    ) -> Result<Option<TransportMessage>, WorkflowGraphError> {
+        let mut bus = self.bus.lock().unwrap_or_else(PoisonError::into_inner);
+        let (_, queue) = bus.subscriptions.get_mut(&subscription).ok_or_else(|| {
+            WorkflowGraphError::InvalidOperation(format!("Unknown subscription {}", subscription.0))
+        })?;
+        Ok(queue.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Actor, WorkflowGraph};
+    use cim_domain_workflow::value_objects::StepType;
+
+    #[test]
+    fn test_subject_matching() {
+        assert!(subject_matches(
+            "workflow.*.created",
+            "workflow.abc.created"
+        ));
+        assert!(subject_matches(
+            "workflow.>",
+            "workflow.abc.step.def.completed"
+        ));
+        assert!(subject_matches(
+            "workflow.*.step.*.completed",
+            "workflow.a.step.b.completed"
+        ));
+        assert!(!subject_matches("workflow.>", "workflow"));
+        assert!(!subject_matches("workflow.*", "workflow.a.created"));
+        assert!(!subject_matches("workflow.*.created", "workflow.a.started"));
+        assert!(!subject_matches(
+            "workflow.a.created.more",
+            "workflow.a.created"
+        ));
+    }
+
+    #[test]
+    fn test_event_subjects() {
+        let mut graph = WorkflowGraph::new("Subjects".to_string(), String::new()).unwrap();
+        let step_id = graph
+            .add_step(
+                "Review".to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        graph
+            .complete_step(step_id, "reviewer".to_string(), HashMap::new())
+            .unwrap();
+
+        let id = graph.id().as_uuid().to_string();
+        let step = step_id.as_uuid().to_string();
+        let subjects: Vec<String> = graph
+            .uncommitted_events
+            .iter()
+            .map(|event| event_subject(graph.id(), event))
+            .collect();
+        assert_eq!(
+            subjects,
+            vec![
+                format!("workflow.{id}.created"),
+                format!("workflow.{id}.step.{step}.added"),
+                format!("workflow.{id}.started"),
+                format!("workflow.{id}.step.{step}.completed"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_in_memory_transport_routes_by_wildcard() {
+        let transport = InMemoryTransport::new();
+        let consumer = transport.clone();
+        let completions = consumer.subscribe("workflow.*.step.*.completed").unwrap();
+        let everything = consumer.subscribe(ALL_EVENTS).unwrap();
+        let commands = consumer.subscribe(ALL_COMMANDS).unwrap();
+
+        let mut graph = WorkflowGraph::new("Bus".to_string(), String::new()).unwrap();
+        let step_id = graph
+            .add_step(
+                "Only".to_string(),
+                String::new(),
+                StepType::Automated,
+                HashMap::new(),
+                Vec::new(),
+                None,
+                None,
+            )
+            .unwrap();
+        graph.start(HashMap::new()).unwrap();
+        graph
+            .complete_step(step_id, "robot".to_string(), HashMap::new())
+            .unwrap();
+        let correlation_id = CorrelationId::new();
+        for event in graph.take_uncommitted_events() {
+            transport
+                .publish_event(graph.id(), &event, Some(correlation_id))
+                .unwrap();
+        }
+        transport
+            .send_command(&CommandEnvelope::new(
+                Actor::system(),
+                WorkflowCommand::CancelWorkflow {
+                    workflow_id: graph.id(),
+                    reason: "done".to_string(),
+                },
+            ))
+            .unwrap();
+
+        let completed = consumer.next_event(completions).unwrap().unwrap();
+        assert_eq!(completed.correlation_id, Some(correlation_id));
+        assert!(matches!(
+            completed.event,
+            WorkflowDomainEvent::TaskCompleted(_)
+        ));
+        assert!(consumer.next_event(completions).unwrap().is_none());
+
+        let mut all = 0;
+        while consumer.next_event(everything).unwrap().is_some() {
+            all += 1;
+        }
+        assert_eq!(all, 4);
+
+        let command = consumer.next_command(commands).unwrap().unwrap();
+        assert_eq!(command.command.workflow_id(), Some(graph.id()));
+        assert_eq!(transport.published_count(), 5);
+        assert!(transport.publish("workflow.*", Vec::new()).is_err());
+        assert!(transport.subscribe("workflow.>.x").is_err());
+    }
+
+    #[test]
+    fn test_invalid_subjects_and_command_types() {
+        let transport = InMemoryTransport::new();
+        for subject in [
+            "",
+            "workflow..created",
+            "workflow.a b.created",
+            "workflow.>",
+        ] {
+            assert!(
+                transport.publish(subject, Vec::new()).is_err(),
+                "{subject:?}"
+            );
+        }
+        for pattern in ["", "workflow.>.x", "workflow. .>", "workflow..*"] {
+            assert!(transport.subscribe(pattern).is_err(), "{pattern:?}");
+        }
+
+        for command_type in ["archive.workflow", "archive workflow", "*", ""] {
+            let envelope = CommandEnvelope::new(
+                Actor::system(),
+                WorkflowCommand::Other {
+                    command_type: command_type.to_string(),
+                    payload: serde_json::json!({}),
+                },
+            );
+            assert!(
+                transport.send_command(&envelope).is_err(),
+                "{command_type:?}"
+            );
+        }
+        assert_eq!(transport.published_count(), 0);
+    }
+}
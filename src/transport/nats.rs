@@ -0,0 +1,152 @@
+//! NATS-backed workflow transport
+
+use super::{
+    validate_pattern, validate_subject, SubscriptionId, TransportMessage, WorkflowTransport,
+};
+use crate::WorkflowGraphError;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// How long [`WorkflowTransport::next_message`] waits for a message by default
+pub const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Workflow transport over a NATS connection
+///
+/// The client runs on a runtime owned by the transport, so it can be used
+/// from synchronous code. Each subscription has its own lock, so waiting on
+/// an idle subscription does not hold up the others.
+pub struct NatsTransport {
+    runtime: tokio::runtime::Runtime,
+    client: async_nats::Client,
+    subscriptions: Mutex<HashMap<SubscriptionId, Arc<tokio::sync::Mutex<async_nats::Subscriber>>>>,
+    next_subscription: AtomicU64,
+    poll_timeout: Duration,
+}
+
+impl NatsTransport {
+    /// Connect to a NATS server, e.g. `nats://localhost:4222`
+    pub fn connect(url: &str) -> Result<Self, WorkflowGraphError> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(nats_error)?;
+        let client = runtime
+            .block_on(async_nats::connect(url))
+            .map_err(nats_error)?;
+
+        Ok(Self {
+            runtime,
+            client,
+            subscriptions: Mutex::new(HashMap::new()),
+            next_subscription: AtomicU64::new(0),
+            poll_timeout: DEFAULT_POLL_TIMEOUT,
+        })
+    }
+
+    /// Set how long `next_message` waits for a message
+    pub fn with_poll_timeout(mut self, poll_timeout: Duration) -> Self {
+        self.poll_timeout = poll_timeout;
+        self
+    }
+}
+
+impl WorkflowTransport for NatsTransport {
+    fn publish(&self, subject: &str, payload: Vec<u8>) -> Result<(), WorkflowGraphError> {
+        validate_subject(subject)?;
+        self.runtime.block_on(async {
+            self.client
+                .publish(subject.to_string(), payload.into())
+                .await
+                .map_err(nats_error)?;
+            self.client.flush().await.map_err(nats_error)
+        })
+    }
+
+    fn subscribe(&self, pattern: &str) -> Result<SubscriptionId, WorkflowGraphError> {
+        validate_pattern(pattern)?;
+        let subscriber = self
+            .runtime
+            .block_on(self.client.subscribe(pattern.to_string()))
+            .map_err(nats_error)?;
+        let id = SubscriptionId(self.next_subscription.fetch_add(1, Ordering::Relaxed));
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, Arc::new(tokio::sync::Mutex::new(subscriber)));
+        Ok(id)
+    }
+
+    fn next_message(
+        &self,
+        subscription: SubscriptionId,
+    ) -> Result<Option<TransportMessage>, WorkflowGraphError> {
+        let subscriber = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&subscription)
+            .cloned()
+            .ok_or_else(|| {
+                WorkflowGraphError::InvalidOperation(format!(
+                    "Unknown subscription {}",
+                    subscription.0
+                ))
+            })?;
+
+        let received = self.runtime.block_on(async {
+            let mut subscriber = subscriber.lock().await;
+            tokio::time::timeout(self.poll_timeout, subscriber.next()).await
+        });
+        Ok(match received {
+            Ok(Some(message)) => Some(TransportMessage {
+                subject: message.subject.to_string(),
+                payload: message.payload.to_vec(),
+            }),
+            Ok(None) | Err(_) => None,
+        })
+    }
+}
+
+fn nats_error(error: impl std::fmt::Display) -> WorkflowGraphError {
+    WorkflowGraphError::InvalidOperation(format!("NATS transport failed: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{workflow_events, ALL_EVENTS};
+    use crate::WorkflowGraph;
+
+    /// Connect to the server named by `NATS_URL`
+    fn connect() -> NatsTransport {
+        let url = std::env::var("NATS_URL").expect("NATS_URL names a NATS server");
+        NatsTransport::connect(&url)
+            .unwrap()
+            .with_poll_timeout(Duration::from_millis(500))
+    }
+
+    #[test]
+    #[ignore = "needs a NATS server at NATS_URL"]
+    fn test_nats_transport_round_trip() {
+        let transport = connect();
+        let mut graph = WorkflowGraph::new("Nats".to_string(), String::new()).unwrap();
+        let idle = transport.subscribe("workflow.nobody.>").unwrap();
+        let events = transport.subscribe(&workflow_events(graph.id())).unwrap();
+
+        assert!(transport.next_message(idle).unwrap().is_none());
+        for event in graph.take_uncommitted_events() {
+            transport.publish_event(graph.id(), &event, None).unwrap();
+        }
+        let received = transport.next_event(events).unwrap().unwrap();
+        assert_eq!(received.workflow_id, graph.id());
+
+        assert!(transport.publish("workflow.*", Vec::new()).is_err());
+        assert!(transport.publish("workflow.a b", Vec::new()).is_err());
+        assert!(transport.subscribe("workflow.>.x").is_err());
+        assert!(transport.subscribe(ALL_EVENTS).is_ok());
+    }
+}
@@ -0,0 +1,508 @@
+//! Definition versioning and migration of running instances
+//!
+//! Every [`WorkflowGraph`] carries a definition version and a content hash in
+//! its [`WorkflowGraphMetadata`](crate::WorkflowGraphMetadata). When a
+//! definition changes while instances of it are running,
+//! [`WorkflowGraph::migrate_to`] maps an instance's step states onto the new
+//! definition according to explicit [`MigrationRules`].
+
+use crate::diff::{match_steps, sorted_steps, StepRef};
+use crate::{WorkflowGraph, WorkflowGraphError};
+use cim_domain_workflow::value_objects::{StepId, StepStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What to do with steps of the running instance that the new definition removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RemovedStepRule {
+    /// Drop removed steps that are pending or finished
+    #[default]
+    Discard,
+    /// Reject the migration if a removed step has already run
+    RejectIfStarted,
+}
+
+/// Initial state of steps that only exist in the new definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AddedStepRule {
+    /// Added steps start out pending; reject the migration if a step that
+    /// depends on one has already started
+    #[default]
+    Pending,
+    /// Added steps whose dependents have already started are skipped,
+    /// all others start out pending
+    SkipIfPassed,
+}
+
+/// Rules applied when migrating a running instance to a new definition
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MigrationRules {
+    pub removed_steps: RemovedStepRule,
+    pub added_steps: AddedStepRule,
+    /// Explicit mapping from instance steps to new definition steps, for steps
+    /// that were both renamed and re-created
+    pub step_mapping: HashMap<StepId, StepId>,
+}
+
+/// Summary of how an instance was migrated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Instance steps carried over, with the state they kept
+    pub mapped_steps: Vec<(StepRef, StepRef, StepStatus)>,
+    /// Instance steps that no longer exist in the new definition
+    pub dropped_steps: Vec<(StepRef, StepStatus)>,
+    /// New definition steps and the state they were given
+    pub added_steps: Vec<(StepRef, StepStatus)>,
+}
+
+/// A migrated instance together with its report
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub graph: WorkflowGraph,
+    pub report: MigrationReport,
+}
+
+impl WorkflowGraph {
+    /// Get the definition version
+    pub fn definition_version(&self) -> u32 {
+        self.metadata.version
+    }
+
+    /// Get the definition content hash
+    pub fn content_hash(&self) -> &str {
+        &self.metadata.content_hash
+    }
+
+    /// Increment the definition version, returning the new version
+    pub fn bump_version(&mut self) -> u32 {
+        self.metadata.version += 1;
+        self.metadata.version
+    }
+
+    /// Map this running instance onto a newer version of its definition
+    ///
+    /// Steps are matched by ID, then by `rules.step_mapping`, then by name.
+    /// Matched steps keep their state; removed and added steps follow the
+    /// rules. A migration that would orphan an in-progress step, either by
+    /// removing it or by giving it an unfinished dependency, is rejected, as
+    /// are targets with dangling dependencies and mappings that send two
+    /// steps to one. The migrated instance keeps its own audit log and
+    /// policy, and starts without uncommitted events.
+    ///
+    /// Migrations have no domain events, so event sourced instances are
+    /// rejected: their log would still replay the old definition. Keep
+    /// instances that may be migrated in a
+    /// [`WorkflowRepository`](crate::WorkflowRepository).
+    pub fn migrate_to(
+        &self,
+        target: &WorkflowGraph,
+        rules: &MigrationRules,
+    ) -> Result<Migration, WorkflowGraphError> {
+        if self.metadata.event_sourced {
+            return Err(WorkflowGraphError::MigrationRejected(format!(
+                "Workflow {} is event sourced and its log cannot record a migration",
+                self.workflow.id.as_uuid()
+            )));
+        }
+        if target.metadata.version <= self.metadata.version {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Cannot migrate from version {} to version {}",
+                self.metadata.version, target.metadata.version
+            )));
+        }
+
+        for step in target.workflow.steps.values() {
+            if let Some(dep) = step
+                .dependencies
+                .iter()
+                .find(|dep| !target.workflow.steps.contains_key(dep))
+            {
+                return Err(WorkflowGraphError::MigrationRejected(format!(
+                    "Step {} of version {} depends on unknown step {}",
+                    step.name,
+                    target.metadata.version,
+                    dep.as_uuid()
+                )));
+            }
+        }
+
+        let mut pairs: HashMap<StepId, StepId> = HashMap::new();
+        for (from, to) in &rules.step_mapping {
+            if !self.workflow.steps.contains_key(from) || !target.workflow.steps.contains_key(to) {
+                return Err(WorkflowGraphError::StepNotFound(format!(
+                    "Step mapping {} -> {} refers to an unknown step",
+                    from.as_uuid(),
+                    to.as_uuid()
+                )));
+            }
+            if pairs.values().any(|mapped| mapped == to) {
+                return Err(WorkflowGraphError::MigrationRejected(format!(
+                    "Step mapping sends several steps to {}",
+                    target.workflow.steps[to].name
+                )));
+            }
+            pairs.insert(*from, *to);
+        }
+        for (from, (to, _)) in match_steps(self, target) {
+            if !pairs.contains_key(&from) && !pairs.values().any(|mapped| *mapped == to) {
+                pairs.insert(from, to);
+            }
+        }
+
+        let mut report = MigrationReport {
+            from_version: self.metadata.version,
+            to_version: target.metadata.version,
+            mapped_steps: Vec::new(),
+            dropped_steps: Vec::new(),
+            added_steps: Vec::new(),
+        };
+
+        let mut migrated = target.clone();
+        migrated.workflow.id = self.workflow.id;
+        migrated.workflow.status = self.workflow.status.clone();
+        migrated.workflow.context = self.workflow.context.clone();
+        migrated.metadata.properties = self.metadata.properties.clone();
+        migrated.metadata.tags = self.metadata.tags.clone();
+        migrated.metadata.event_sourced = self.metadata.event_sourced;
+        // The instance keeps its own history, not the target definition's
+        migrated.uncommitted_events.clear();
+        migrated.audit_log = self.audit_log.clone();
+        migrated.authorization_policy = self.authorization_policy.clone();
+        migrated.environment = self.environment.clone();
+
+        for step in sorted_steps(self) {
+            match pairs.get(&step.id) {
+                Some(to) => {
+                    let new_step = migrated.workflow.steps.get_mut(to).expect("mapped step");
+                    new_step.status = step.status.clone();
+                    new_step.started_at = step.started_at;
+                    new_step.completed_at = step.completed_at;
+                    report.mapped_steps.push((
+                        StepRef::of(step),
+                        StepRef::of(new_step),
+                        step.status.clone(),
+                    ));
+                }
+                None => {
+                    if is_in_progress(&step.status) {
+                        return Err(WorkflowGraphError::MigrationRejected(format!(
+                            "In-progress step {} does not exist in version {}",
+                            step.name, target.metadata.version
+                        )));
+                    }
+                    if rules.removed_steps == RemovedStepRule::RejectIfStarted
+                        && step.status != StepStatus::Pending
+                    {
+                        return Err(WorkflowGraphError::MigrationRejected(format!(
+                            "Removed step {} has already run",
+                            step.name
+                        )));
+                    }
+                    report
+                        .dropped_steps
+                        .push((StepRef::of(step), step.status.clone()));
+                }
+            }
+        }
+
+        let mapped_targets: Vec<StepId> = pairs.values().copied().collect();
+        let added: Vec<StepId> = sorted_steps(target)
+            .into_iter()
+            .map(|step| step.id)
+            .filter(|id| !mapped_targets.contains(id))
+            .collect();
+        for id in added {
+            let passed = migrated
+                .workflow
+                .steps
+                .values()
+                .any(|s| s.dependencies.contains(&id) && s.status != StepStatus::Pending);
+            let status = match (passed, rules.added_steps) {
+                (false, _) => StepStatus::Pending,
+                (true, AddedStepRule::SkipIfPassed) => StepStatus::Skipped,
+                (true, AddedStepRule::Pending) => {
+                    return Err(WorkflowGraphError::MigrationRejected(format!(
+                        "Added step {} precedes steps that have already started",
+                        migrated.workflow.steps[&id].name
+                    )));
+                }
+            };
+            let step = migrated.workflow.steps.get_mut(&id).expect("added step");
+            step.status = status.clone();
+            step.started_at = None;
+            step.completed_at = None;
+            report.added_steps.push((StepRef::of(step), status));
+        }
+
+        // Every step that has started must still have its dependencies finished
+        for step in migrated.workflow.steps.values() {
+            if step.status == StepStatus::Pending {
+                continue;
+            }
+            if let Some(dep) = step
+                .dependencies
+                .iter()
+                .map(|dep| &migrated.workflow.steps[dep])
+                .find(|dep| !is_finished(&dep.status))
+            {
+                return Err(WorkflowGraphError::MigrationRejected(format!(
+                    "Step {} would depend on unfinished step {}",
+                    step.name, dep.name
+                )));
+            }
+        }
+
+        migrated.refresh_context_graph();
+
+        Ok(Migration {
+            graph: migrated,
+            report,
+        })
+    }
+}
+
+/// Content identifier of the canonical definition, as a string
+pub(crate) fn content_hash(graph: &WorkflowGraph) -> String {
+    graph.cid().to_string()
+}
+
+fn is_in_progress(status: &StepStatus) -> bool {
+    matches!(
+        status,
+        StepStatus::Running | StepStatus::InProgress | StepStatus::WaitingApproval
+    )
+}
+
+fn is_finished(status: &StepStatus) -> bool {
+    matches!(status, StepStatus::Completed | StepStatus::Skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::NEW_REVISION;
+    use crate::{EventStore, InMemoryEventStore, InMemoryWorkflowRepository, WorkflowRepository};
+    use cim_domain_workflow::value_objects::{StepType, WorkflowStep};
+
+    fn definition() -> (WorkflowGraph, StepId, StepId) {
+        let mut graph =
+            WorkflowGraph::new("Versioned".to_string(), "Versioning test".to_string()).unwrap();
+        let draft = graph
+            .add_step(
+                "Create Draft".to_string(),
+                "Write".to_string(),
+                StepType::Manual,
+                HashMap::new(),
+                Vec::new(),
+                Some(60),
+                None,
+            )
+            .unwrap();
+        let review = graph
+            .add_step(
+                "Review".to_string(),
+                "Review".to_string(),
+                StepType::Manual,
+                HashMap::new(),
+                vec![draft],
+                Some(30),
+                None,
+            )
+            .unwrap();
+        (graph, draft, review)
+    }
+
+    fn new_step(graph: &mut WorkflowGraph, name: &str, dependencies: Vec<StepId>) -> StepId {
+        let id = StepId::new();
+        let template = graph.workflow.steps.values().next().unwrap().clone();
+        graph.workflow.steps.insert(
+            id,
+            WorkflowStep {
+                id,
+                name: name.to_string(),
+                dependencies,
+                ..template
+            },
+        );
+        graph.refresh_context_graph();
+        id
+    }
+
+    #[test]
+    fn test_content_hash_tracks_definition_changes() {
+        let (mut graph, _, review) = definition();
+        assert_eq!(graph.definition_version(), 1);
+        assert!(graph.content_hash().parse::<crate::Cid>().is_ok());
+
+        let before = graph.content_hash().to_string();
+        graph.add_tag("unrelated".to_string());
+        graph.refresh_context_graph();
+        assert_eq!(graph.content_hash(), before);
+
+        graph
+            .workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .estimated_duration_minutes = Some(45);
+        graph.refresh_context_graph();
+        assert_ne!(graph.content_hash(), before);
+        assert_eq!(graph.bump_version(), 2);
+    }
+
+    #[test]
+    fn test_migrate_keeps_states_and_adds_steps() {
+        let (mut instance, draft, review) = definition();
+        let mut target = instance.clone();
+
+        instance.start(HashMap::new()).unwrap();
+        instance.workflow.steps.get_mut(&draft).unwrap().status = StepStatus::Completed;
+        instance.workflow.steps.get_mut(&review).unwrap().status = StepStatus::InProgress;
+
+        let publish = new_step(&mut target, "Publish", vec![review]);
+        target.bump_version();
+
+        let migration = instance
+            .migrate_to(&target, &MigrationRules::default())
+            .unwrap();
+        let graph = &migration.graph;
+        assert_eq!(graph.id(), instance.id());
+        assert_eq!(graph.definition_version(), 2);
+        assert_eq!(graph.content_hash(), target.content_hash());
+        assert_eq!(graph.workflow.steps[&review].status, StepStatus::InProgress);
+        assert_eq!(graph.workflow.steps[&publish].status, StepStatus::Pending);
+        assert_eq!(migration.report.mapped_steps.len(), 2);
+        assert_eq!(migration.report.added_steps[0].0.id, publish);
+    }
+
+    #[test]
+    fn test_migration_rejects_orphaned_in_progress_step() {
+        let (mut instance, draft, review) = definition();
+        let mut target = instance.clone();
+        instance.workflow.steps.get_mut(&draft).unwrap().status = StepStatus::Completed;
+        instance.workflow.steps.get_mut(&review).unwrap().status = StepStatus::InProgress;
+
+        target.workflow.steps.remove(&review);
+        target.bump_version();
+
+        let result = instance.migrate_to(&target, &MigrationRules::default());
+        assert!(matches!(
+            result,
+            Err(WorkflowGraphError::MigrationRejected(_))
+        ));
+    }
+
+    #[test]
+    fn test_added_upstream_step_rules() {
+        let (mut instance, draft, review) = definition();
+        let mut target = instance.clone();
+        instance.workflow.steps.get_mut(&draft).unwrap().status = StepStatus::Completed;
+        instance.workflow.steps.get_mut(&review).unwrap().status = StepStatus::InProgress;
+
+        // Insert a check between draft and review
+        let check = new_step(&mut target, "Plagiarism Check", vec![draft]);
+        target
+            .workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .dependencies
+            .push(check);
+        target.bump_version();
+
+        let rejected = instance.migrate_to(&target, &MigrationRules::default());
+        assert!(matches!(
+            rejected,
+            Err(WorkflowGraphError::MigrationRejected(_))
+        ));
+
+        let rules = MigrationRules {
+            added_steps: AddedStepRule::SkipIfPassed,
+            ..Default::default()
+        };
+        let migration = instance.migrate_to(&target, &rules).unwrap();
+        assert_eq!(
+            migration.graph.workflow.steps[&check].status,
+            StepStatus::Skipped
+        );
+    }
+
+    #[test]
+    fn test_migration_rejects_bad_targets_and_leaves_target_history() {
+        let (instance, draft, review) = definition();
+        let mut target = instance.clone();
+        target
+            .add_step(
+                "Publish".to_string(),
+                String::new(),
+                StepType::Automated,
+                HashMap::new(),
+                vec![review],
+                None,
+                None,
+            )
+            .unwrap();
+        target.bump_version();
+
+        let migration = instance
+            .migrate_to(&target, &MigrationRules::default())
+            .unwrap();
+        assert!(!target.uncommitted_events.is_empty());
+        assert!(migration.graph.uncommitted_events.is_empty());
+        assert_eq!(migration.graph.audit_log(), instance.audit_log());
+        assert_ne!(target.audit_log(), instance.audit_log());
+
+        let rules = MigrationRules {
+            step_mapping: HashMap::from([(draft, review), (review, review)]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            instance.migrate_to(&target, &rules),
+            Err(WorkflowGraphError::MigrationRejected(_))
+        ));
+
+        target
+            .workflow
+            .steps
+            .get_mut(&review)
+            .unwrap()
+            .dependencies
+            .push(StepId::new());
+        assert!(matches!(
+            instance.migrate_to(&target, &MigrationRules::default()),
+            Err(WorkflowGraphError::MigrationRejected(_))
+        ));
+    }
+
+    #[test]
+    fn test_event_sourced_instances_cannot_migrate() {
+        let (mut instance, _, review) = definition();
+        let mut target = instance.clone();
+        new_step(&mut target, "Publish", vec![review]);
+        target.bump_version();
+        let kept = instance.clone();
+
+        let mut store = InMemoryEventStore::new();
+        store.save(&mut instance).unwrap();
+        let replayed = store.replay(instance.id()).unwrap();
+        for graph in [&instance, &replayed] {
+            assert!(matches!(
+                graph.migrate_to(&target, &MigrationRules::default()),
+                Err(WorkflowGraphError::MigrationRejected(_))
+            ));
+        }
+
+        // An instance kept in a repository migrates, and stays migrated
+        let mut repository = InMemoryWorkflowRepository::new();
+        let migration = kept
+            .migrate_to(&target, &MigrationRules::default())
+            .unwrap();
+        repository.save(&migration.graph, NEW_REVISION).unwrap();
+        let (loaded, _) = repository.load(kept.id()).unwrap();
+        assert!(!loaded.metadata.event_sourced);
+        assert_eq!(loaded.definition_version(), target.definition_version());
+        assert!(loaded.workflow.steps.values().any(|s| s.name == "Publish"));
+    }
+}
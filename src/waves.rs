@@ -0,0 +1,209 @@
+//! Parallel execution waves over the whole plan
+//!
+//! A step's wave is the length of the longest dependency chain leading to
+//! it, so every step in a wave can run alongside the others once the earlier
+//! waves are done. The number of waves equals
+//! [`WorkflowGraphStatistics::max_depth`](crate::WorkflowGraphStatistics).
+
+use crate::{topology, WorkflowGraph};
+use cim_domain_workflow::value_objects::{StepId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// How to order steps that are equally ready
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TieBreak {
+    /// Step name, then ID
+    #[default]
+    Name,
+    /// Step ID
+    Id,
+    /// Longest estimated duration first, then name
+    LongestFirst,
+    /// Shortest estimated duration first, then name
+    ShortestFirst,
+    /// A custom comparison
+    Custom(fn(&WorkflowStep, &WorkflowStep) -> Ordering),
+}
+
+impl TieBreak {
+    /// Compare two steps
+    pub fn compare(&self, a: &WorkflowStep, b: &WorkflowStep) -> Ordering {
+        let duration = |step: &WorkflowStep| step.estimated_duration_minutes.unwrap_or(0);
+        match self {
+            TieBreak::Name => topology::by_name(a, b),
+            TieBreak::Id => a.id.as_uuid().cmp(b.id.as_uuid()),
+            TieBreak::LongestFirst => duration(b)
+                .cmp(&duration(a))
+                .then_with(|| topology::by_name(a, b)),
+            TieBreak::ShortestFirst => duration(a)
+                .cmp(&duration(b))
+                .then_with(|| topology::by_name(a, b)),
+            TieBreak::Custom(compare) => compare(a, b),
+        }
+    }
+}
+
+/// Steps grouped by dependency depth
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionWaves {
+    /// Steps of each wave, ordered by the tie-break
+    pub waves: Vec<Vec<StepId>>,
+    /// Size of the largest wave, the peak parallelism
+    pub max_width: usize,
+    /// Every step after its dependencies, ties broken by the tie-break
+    pub order: Vec<StepId>,
+}
+
+impl ExecutionWaves {
+    /// Number of waves
+    pub fn depth(&self) -> usize {
+        self.waves.len()
+    }
+}
+
+impl WorkflowGraph {
+    /// Steps grouped by dependency depth, ties broken by step name
+    pub fn execution_waves(&self) -> ExecutionWaves {
+        self.execution_waves_by(TieBreak::default())
+    }
+
+    /// Steps grouped by dependency depth with a chosen tie-break
+    ///
+    /// Steps in or after a dependency cycle belong to no wave; they come
+    /// last in `order`.
+    pub fn execution_waves_by(&self, tie_break: TieBreak) -> ExecutionWaves {
+        let steps = &self.workflow.steps;
+        let order = topology::topological_order_by(self, |a, b| tie_break.compare(a, b));
+
+        let mut levels: HashMap<StepId, usize> = HashMap::with_capacity(steps.len());
+        let mut waves: Vec<Vec<StepId>> = Vec::new();
+        for id in &order {
+            let mut level = 0;
+            let mut placed = true;
+            for dependency in &steps[id].dependencies {
+                match levels.get(dependency) {
+                    Some(depth) => level = level.max(depth + 1),
+                    None if steps.contains_key(dependency) => placed = false,
+                    None => {}
+                }
+            }
+            if !placed {
+                continue;
+            }
+            levels.insert(*id, level);
+            if waves.len() <= level {
+                waves.resize_with(level + 1, Vec::new);
+            }
+            waves[level].push(*id);
+        }
+
+        for wave in &mut waves {
+            wave.sort_by(|a, b| tie_break.compare(&steps[a], &steps[b]));
+        }
+        ExecutionWaves {
+            max_width: waves.iter().map(Vec::len).max().unwrap_or(0),
+            waves,
+            order,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cim_domain_workflow::value_objects::StepType;
+
+    fn step(
+        graph: &mut WorkflowGraph,
+        name: &str,
+        dependencies: Vec<StepId>,
+        minutes: u32,
+    ) -> StepId {
+        graph
+            .add_step(
+                name.to_string(),
+                String::new(),
+                StepType::Manual,
+                HashMap::new(),
+                dependencies,
+                Some(minutes),
+                None,
+            )
+            .unwrap()
+    }
+
+    /// plan -> {code, tests, docs} -> release, with audit beside code
+    fn plan() -> (WorkflowGraph, [StepId; 6]) {
+        let mut graph = WorkflowGraph::new("Plan".to_string(), "Waves".to_string()).unwrap();
+        let plan = step(&mut graph, "Plan", vec![], 10);
+        let code = step(&mut graph, "Code", vec![plan], 90);
+        let tests = step(&mut graph, "Tests", vec![plan], 40);
+        let docs = step(&mut graph, "Docs", vec![plan], 20);
+        let release = step(&mut graph, "Release", vec![code, tests, docs], 5);
+        let audit = step(&mut graph, "Audit", vec![], 15);
+        (graph, [plan, code, tests, docs, release, audit])
+    }
+
+    #[test]
+    fn test_waves_by_depth() {
+        let (graph, [plan, code, tests, docs, release, audit]) = plan();
+
+        let waves = graph.execution_waves();
+        assert_eq!(
+            waves.waves,
+            vec![vec![audit, plan], vec![code, docs, tests], vec![release]]
+        );
+        assert_eq!(waves.max_width, 3);
+        assert_eq!(waves.depth(), graph.statistics().max_depth);
+    }
+
+    #[test]
+    fn test_topological_order_tie_breaks() {
+        let (graph, [plan, code, tests, docs, release, audit]) = plan();
+
+        assert_eq!(
+            graph.execution_waves().order,
+            vec![audit, plan, code, docs, tests, release]
+        );
+        assert_eq!(
+            graph.execution_waves_by(TieBreak::LongestFirst).order,
+            vec![audit, plan, code, tests, docs, release]
+        );
+
+        let reverse_name = TieBreak::Custom(|a, b| b.name.cmp(&a.name));
+        let waves = graph.execution_waves_by(reverse_name);
+        assert_eq!(waves.order, vec![plan, tests, docs, code, release, audit]);
+        assert_eq!(waves.waves[1], vec![tests, docs, code]);
+    }
+
+    #[test]
+    fn test_empty_graph() {
+        let graph = WorkflowGraph::new("Empty".to_string(), String::new()).unwrap();
+
+        let waves = graph.execution_waves();
+        assert!(waves.waves.is_empty());
+        assert_eq!(waves.max_width, 0);
+        assert_eq!(waves.depth(), graph.statistics().max_depth);
+    }
+
+    #[test]
+    fn test_cycle_and_dangling_dependency() {
+        let (mut graph, [plan, code, tests, docs, release, audit]) = plan();
+        let steps = &mut graph.workflow.steps;
+        steps.get_mut(&tests).unwrap().dependencies.push(code);
+        steps.get_mut(&code).unwrap().dependencies.push(tests);
+        steps
+            .get_mut(&docs)
+            .unwrap()
+            .dependencies
+            .push(StepId::new());
+
+        // Code and Tests wait on each other, and Release waits on both
+        let waves = graph.execution_waves();
+        assert_eq!(waves.waves, vec![vec![audit, plan], vec![docs]]);
+        assert_eq!(waves.max_width, 2);
+        assert_eq!(waves.order, vec![audit, plan, docs, code, release, tests]);
+    }
+}
@@ -0,0 +1,664 @@
+//! Inboxes of human tasks
+//!
+//! Executable [`StepType::Manual`] and [`StepType::Approval`] steps of
+//! running workflows are work items. A work item is held by whoever it was
+//! delegated to, else whoever claimed it, else the step's assignee; items
+//! nobody holds can be claimed by anyone. Priorities come from the
+//! workflow's [`PRIORITY_PROPERTY`], due dates from the step's SLA, and
+//! every action returns a [`WorklistEvent`] stamped by the graph's
+//! environment. Approvals with an [`ApprovalPolicy`] show up for each of
+//! their approvers and are decided by votes, not completed here.
+
+use crate::approval::ApprovalPolicy;
+use crate::authorization::Actor;
+use crate::sla::{self, StepSla};
+use crate::{WorkflowGraph, WorkflowGraphError};
+use chrono::{DateTime, Utc};
+use cim_domain_workflow::value_objects::{StepId, StepType, WorkflowId, WorkflowStep};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Workflow property holding the priority of its tasks
+pub const PRIORITY_PROPERTY: &str = "priority";
+
+/// How urgent a workflow's tasks are
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    /// Priority from a workflow's properties, `Normal` when missing or
+    /// unrecognized
+    pub fn of_graph(graph: &WorkflowGraph) -> Self {
+        graph
+            .get_property(PRIORITY_PROPERTY)
+            .and_then(|value| value.as_str())
+            .and_then(|text| match text.to_ascii_lowercase().as_str() {
+                "low" => Some(Self::Low),
+                "normal" | "medium" => Some(Self::Normal),
+                "high" => Some(Self::High),
+                "urgent" | "critical" => Some(Self::Urgent),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// A human task in someone's inbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkItem {
+    pub workflow_id: WorkflowId,
+    pub workflow_name: String,
+    pub step_id: StepId,
+    pub step_name: String,
+    pub step_type: StepType,
+    /// Who holds the task, if anyone
+    pub holder: Option<String>,
+    /// Who claimed the task, if it is claimed
+    pub claimed_by: Option<String>,
+    /// Who handed the task on, if it was delegated
+    pub delegated_by: Option<String>,
+    /// Who votes on the task, for approvals with a policy
+    pub approvers: Vec<String>,
+    pub priority: Priority,
+    pub due_at: Option<DateTime<Utc>>,
+    /// When the task became available
+    pub offered_at: DateTime<Utc>,
+}
+
+/// A task became available
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkItemOffered {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub holder: Option<String>,
+    pub offered_at: DateTime<Utc>,
+}
+
+/// Someone took a task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkItemClaimed {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub claimed_by: String,
+    pub claimed_at: DateTime<Utc>,
+}
+
+/// Someone put a claimed task back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkItemUnclaimed {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub unclaimed_by: String,
+    pub unclaimed_at: DateTime<Utc>,
+}
+
+/// A task was handed to someone else
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkItemDelegated {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub delegated_by: String,
+    pub delegated_to: String,
+    pub delegated_at: DateTime<Utc>,
+}
+
+/// A task was done through the worklist
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkItemCompleted {
+    pub workflow_id: WorkflowId,
+    pub step_id: StepId,
+    pub completed_by: String,
+    pub completed_at: DateTime<Utc>,
+}
+
+/// Events raised by worklist actions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorklistEvent {
+    WorkItemOffered(WorkItemOffered),
+    WorkItemClaimed(WorkItemClaimed),
+    WorkItemUnclaimed(WorkItemUnclaimed),
+    WorkItemDelegated(WorkItemDelegated),
+    WorkItemCompleted(WorkItemCompleted),
+}
+
+/// Claims and delegations of human tasks across workflows
+#[derive(Debug, Clone, Default)]
+pub struct Worklist {
+    tasks: HashMap<(WorkflowId, StepId), TaskState>,
+}
+
+/// What the worklist remembers about a task
+#[derive(Debug, Clone, Default)]
+struct TaskState {
+    offered_at: Option<DateTime<Utc>>,
+    claimed_by: Option<String>,
+    delegated_to: Option<String>,
+    delegated_by: Option<String>,
+}
+
+impl Worklist {
+    /// Empty worklist
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record newly available tasks and forget finished ones
+    ///
+    /// Each task is offered once.
+    pub fn sync<'a>(
+        &mut self,
+        graphs: impl IntoIterator<Item = &'a WorkflowGraph>,
+    ) -> Vec<WorklistEvent> {
+        let mut open = HashSet::new();
+        let mut events = Vec::new();
+
+        for graph in graphs {
+            let now = graph.environment().now();
+            for step in human_tasks(graph) {
+                let key = (graph.id(), step.id);
+                open.insert(key);
+                let state = self.tasks.entry(key).or_default();
+                if state.offered_at.is_none() {
+                    let offered_at = sla::executable_since(graph, step, now);
+                    state.offered_at = Some(offered_at);
+                    events.push(WorklistEvent::WorkItemOffered(WorkItemOffered {
+                        workflow_id: graph.id(),
+                        step_id: step.id,
+                        holder: holder(state, step),
+                        offered_at,
+                    }));
+                }
+            }
+        }
+
+        self.tasks.retain(|key, _| open.contains(key));
+        events
+    }
+
+    /// Tasks held by someone or awaiting their vote, most urgent first
+    pub fn tasks_for<'a>(
+        &self,
+        graphs: impl IntoIterator<Item = &'a WorkflowGraph>,
+        assignee: &str,
+    ) -> Result<Vec<WorkItem>, WorkflowGraphError> {
+        self.items(graphs, |item| {
+            item.holder.as_deref() == Some(assignee)
+                || item.approvers.iter().any(|approver| approver == assignee)
+        })
+    }
+
+    /// Tasks nobody holds or votes on, most urgent first
+    pub fn unassigned<'a>(
+        &self,
+        graphs: impl IntoIterator<Item = &'a WorkflowGraph>,
+    ) -> Result<Vec<WorkItem>, WorkflowGraphError> {
+        self.items(graphs, |item| {
+            item.holder.is_none() && item.approvers.is_empty()
+        })
+    }
+
+    /// Take a task that nobody else holds
+    pub fn claim(
+        &mut self,
+        graph: &WorkflowGraph,
+        step_id: StepId,
+        user: &str,
+    ) -> Result<WorklistEvent, WorkflowGraphError> {
+        let step = open_task(graph, step_id)?;
+        let state = self.tasks.entry((graph.id(), step_id)).or_default();
+        if let Some(claimed_by) = &state.claimed_by {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Task {} is already claimed by {claimed_by}",
+                step.name
+            )));
+        }
+        if let Some(other) = holder(state, step).filter(|holder| holder != user) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Task {} is held by {other}",
+                step.name
+            )));
+        }
+
+        state.claimed_by = Some(user.to_string());
+        Ok(WorklistEvent::WorkItemClaimed(WorkItemClaimed {
+            workflow_id: graph.id(),
+            step_id,
+            claimed_by: user.to_string(),
+            claimed_at: graph.environment().now(),
+        }))
+    }
+
+    /// Put back a task the user claimed
+    pub fn unclaim(
+        &mut self,
+        graph: &WorkflowGraph,
+        step_id: StepId,
+        user: &str,
+    ) -> Result<WorklistEvent, WorkflowGraphError> {
+        let step = open_task(graph, step_id)?;
+        let state = self.tasks.entry((graph.id(), step_id)).or_default();
+        if state.claimed_by.as_deref() != Some(user) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Task {} is not claimed by {user}",
+                step.name
+            )));
+        }
+
+        state.claimed_by = None;
+        Ok(WorklistEvent::WorkItemUnclaimed(WorkItemUnclaimed {
+            workflow_id: graph.id(),
+            step_id,
+            unclaimed_by: user.to_string(),
+            unclaimed_at: graph.environment().now(),
+        }))
+    }
+
+    /// Hand a task the user holds to someone else, releasing any claim
+    pub fn delegate(
+        &mut self,
+        graph: &WorkflowGraph,
+        step_id: StepId,
+        from: &str,
+        to: &str,
+    ) -> Result<WorklistEvent, WorkflowGraphError> {
+        let step = open_task(graph, step_id)?;
+        let state = self.tasks.entry((graph.id(), step_id)).or_default();
+        if holder(state, step).as_deref() != Some(from) {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Task {} is not held by {from}",
+                step.name
+            )));
+        }
+
+        state.claimed_by = None;
+        state.delegated_to = Some(to.to_string());
+        state.delegated_by = Some(from.to_string());
+        Ok(WorklistEvent::WorkItemDelegated(WorkItemDelegated {
+            workflow_id: graph.id(),
+            step_id,
+            delegated_by: from.to_string(),
+            delegated_to: to.to_string(),
+            delegated_at: graph.environment().now(),
+        }))
+    }
+
+    /// Complete a task the actor claimed, completing its step as the actor
+    ///
+    /// Delegation does not change the step's assignee, so a policy granting
+    /// completion to the assignee refuses a delegate.
+    pub fn complete(
+        &mut self,
+        graph: &mut WorkflowGraph,
+        step_id: StepId,
+        actor: &Actor,
+        data: HashMap<String, serde_json::Value>,
+    ) -> Result<WorklistEvent, WorkflowGraphError> {
+        let user = actor.id.as_str();
+        let step = open_task(graph, step_id)?;
+        if ApprovalPolicy::of_step(step)?.is_some() {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Approval {} is decided by its approvers' votes",
+                step.name
+            )));
+        }
+        let key = (graph.id(), step_id);
+        if self
+            .tasks
+            .get(&key)
+            .and_then(|state| state.claimed_by.as_deref())
+            != Some(user)
+        {
+            return Err(WorkflowGraphError::InvalidOperation(format!(
+                "Task {} must be claimed by {user} before it is completed",
+                step.name
+            )));
+        }
+
+        graph.complete_step_as(actor, step_id, data)?;
+        self.tasks.remove(&key);
+        Ok(WorklistEvent::WorkItemCompleted(WorkItemCompleted {
+            workflow_id: key.0,
+            step_id,
+            completed_by: user.to_string(),
+            completed_at: graph.environment().now(),
+        }))
+    }
+
+    fn items<'a>(
+        &self,
+        graphs: impl IntoIterator<Item = &'a WorkflowGraph>,
+        keep: impl Fn(&WorkItem) -> bool,
+    ) -> Result<Vec<WorkItem>, WorkflowGraphError> {
+        let mut items = Vec::new();
+        for graph in graphs {
+            let now = graph.environment().now();
+            let priority = Priority::of_graph(graph);
+            for step in human_tasks(graph) {
+                let state = self
+                    .tasks
+                    .get(&(graph.id(), step.id))
+                    .cloned()
+                    .unwrap_or_default();
+                let offered_at = state
+                    .offered_at
+                    .unwrap_or_else(|| sla::executable_since(graph, step, now));
+                let item = WorkItem {
+                    workflow_id: graph.id(),
+                    workflow_name: graph.name().to_string(),
+                    step_id: step.id,
+                    step_name: step.name.clone(),
+                    step_type: step.step_type.clone(),
+                    holder: holder(&state, step),
+                    claimed_by: state.claimed_by.clone(),
+                    delegated_by: state.delegated_by.clone(),
+                    approvers: ApprovalPolicy::of_step(step)?
+                        .map(|policy| policy.approvers)
+                        .unwrap_or_default(),
+                    priority,
+                    due_at: StepSla::of_step(step)?
+                        .and_then(|sla| sla::deadline(&sla, Some(offered_at))),
+                    offered_at,
+                };
+                if keep(&item) {
+                    items.push(item);
+                }
+            }
+        }
+
+        items.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| (a.due_at.is_none(), a.due_at).cmp(&(b.due_at.is_none(), b.due_at)))
+                .then_with(|| a.offered_at.cmp(&b.offered_at))
+                .then_with(|| a.workflow_name.cmp(&b.workflow_name))
+                .then_with(|| a.step_name.cmp(&b.step_name))
+        });
+        Ok(items)
+    }
+}
+
+/// Executable manual and approval steps
+fn human_tasks(graph: &WorkflowGraph) -> Vec<&WorkflowStep> {
+    graph
+        .workflow
+        .get_executable_steps()
+        .into_iter()
+        .filter(|step| matches!(step.step_type, StepType::Manual | StepType::Approval))
+        .collect()
+}
+
+/// A step that is currently a work item
+fn open_task(graph: &WorkflowGraph, step_id: StepId) -> Result<&WorkflowStep, WorkflowGraphError> {
+    let Some(step) = graph.workflow.steps.get(&step_id) else {
+        return Err(WorkflowGraphError::StepNotFound(
+            step_id.as_uuid().to_string(),
+        ));
+    };
+    if !human_tasks(graph).iter().any(|task| task.id == step_id) {
+        return Err(WorkflowGraphError::InvalidOperation(format!(
+            "Step {} is not an open human task",
+            step.name
+        )));
+    }
+    Ok(step)
+}
+
+fn holder(state: &TaskState, step: &WorkflowStep) -> Option<String> {
+    state
+        .delegated_to
+        .clone()
+        .or_else(|| state.claimed_by.clone())
+        .or_else(|| step.assigned_to.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthorizationPolicy, Grant, Operation, StepSla, WorkflowEnvironment};
+    use chrono::Duration;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    /// Started workflow: Review (manual) -> Approve (approval), beside an
+    /// automated Notify step
+    fn expense(name: &str, assignee: Option<&str>) -> (WorkflowGraph, StepId, StepId) {
+        let environment = WorkflowEnvironment::deterministic(utc("2025-03-03T09:00:00Z"));
+        let mut graph =
+            WorkflowGraph::new_in(name.to_string(), String::new(), environment).unwrap();
+        let mut add = |name: &str, step_type: StepType, dependencies: Vec<StepId>| {
+            graph
+                .add_step(
+                    name.to_string(),
+                    String::new(),
+                    step_type,
+                    HashMap::new(),
+                    dependencies,
+                    Some(30),
+                    assignee.map(str::to_string),
+                )
+                .unwrap()
+        };
+        let review = add("Review", StepType::Manual, vec![]);
+        let approve = add("Approve", StepType::Approval, vec![review]);
+        add("Notify", StepType::Automated, vec![]);
+        graph.start(HashMap::new()).unwrap();
+        (graph, review, approve)
+    }
+
+    #[test]
+    fn test_inbox_orders_by_priority_then_due_date() {
+        let (mut routine, routine_review, _) = expense("Routine", Some("alice"));
+        let (mut urgent, urgent_review, _) = expense("Urgent", Some("alice"));
+        let (later, later_review, _) = expense("Later", Some("alice"));
+        urgent.set_property(PRIORITY_PROPERTY.to_string(), serde_json::json!("high"));
+        let sla = StepSla::within(Duration::hours(4));
+        routine
+            .workflow
+            .steps
+            .get_mut(&routine_review)
+            .unwrap()
+            .config
+            .insert(
+                sla::SLA_CONFIG_KEY.to_string(),
+                serde_json::to_value(&sla).unwrap(),
+            );
+
+        let worklist = Worklist::new();
+        let inbox = worklist
+            .tasks_for([&later, &routine, &urgent], "alice")
+            .unwrap();
+        let order: Vec<StepId> = inbox.iter().map(|item| item.step_id).collect();
+        assert_eq!(order, [urgent_review, routine_review, later_review]);
+        assert_eq!(inbox[0].priority, Priority::High);
+        assert_eq!(inbox[1].due_at, Some(utc("2025-03-03T13:00:00Z")));
+        assert!(worklist
+            .unassigned([&later, &routine, &urgent])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_claim_unclaim_and_delegate() {
+        let (graph, review, approve) = expense("Expense", None);
+        let mut worklist = Worklist::new();
+
+        let offered = worklist.sync([&graph]);
+        assert_eq!(offered.len(), 1);
+        assert!(worklist.sync([&graph]).is_empty());
+        assert_eq!(worklist.unassigned([&graph]).unwrap().len(), 1);
+        assert!(worklist.claim(&graph, approve, "bob").is_err());
+
+        let claimed = worklist.claim(&graph, review, "bob").unwrap();
+        assert!(matches!(
+            claimed,
+            WorklistEvent::WorkItemClaimed(WorkItemClaimed { ref claimed_by, claimed_at, .. })
+                if claimed_by == "bob" && claimed_at == utc("2025-03-03T09:00:00Z")
+        ));
+        assert!(worklist.claim(&graph, review, "carol").is_err());
+        assert!(worklist.unclaim(&graph, review, "carol").is_err());
+
+        worklist.delegate(&graph, review, "bob", "carol").unwrap();
+        let inbox = worklist.tasks_for([&graph], "carol").unwrap();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].delegated_by.as_deref(), Some("bob"));
+        assert_eq!(inbox[0].claimed_by, None);
+        assert!(worklist.tasks_for([&graph], "bob").unwrap().is_empty());
+
+        worklist.claim(&graph, review, "carol").unwrap();
+        worklist.unclaim(&graph, review, "carol").unwrap();
+        assert_eq!(worklist.tasks_for([&graph], "carol").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_complete_moves_work_along() {
+        let (mut graph, review, approve) = expense("Expense", Some("dana"));
+        let mut worklist = Worklist::new();
+        worklist.sync([&graph]);
+
+        assert!(worklist
+            .complete(&mut graph, review, &Actor::new("dana"), HashMap::new())
+            .is_err());
+        worklist.claim(&graph, review, "dana").unwrap();
+        let done = worklist
+            .complete(&mut graph, review, &Actor::new("dana"), HashMap::new())
+            .unwrap();
+        assert!(matches!(done, WorklistEvent::WorkItemCompleted(_)));
+
+        let offered = worklist.sync([&graph]);
+        assert!(matches!(
+            offered.as_slice(),
+            [WorklistEvent::WorkItemOffered(WorkItemOffered { step_id, .. })] if *step_id == approve
+        ));
+        let inbox = worklist.tasks_for([&graph], "dana").unwrap();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].step_type, StepType::Approval);
+    }
+
+    #[test]
+    fn test_priority_from_properties() {
+        let (mut graph, _, _) = expense("Expense", None);
+        for (value, priority) in [
+            (serde_json::json!("CRITICAL"), Priority::Urgent),
+            (serde_json::json!("medium"), Priority::Normal),
+            (serde_json::json!("soon"), Priority::Normal),
+            (serde_json::json!(3), Priority::Normal),
+        ] {
+            graph.set_property(PRIORITY_PROPERTY.to_string(), value);
+            assert_eq!(Priority::of_graph(&graph), priority);
+        }
+    }
+
+    #[test]
+    fn test_actions_on_tasks_that_are_not_open_or_not_held() {
+        let (mut graph, review, approve) = expense("Expense", Some("dana"));
+        let notify = graph
+            .workflow
+            .steps
+            .values()
+            .find(|step| step.name == "Notify")
+            .unwrap()
+            .id;
+        let mut worklist = Worklist::new();
+
+        assert!(matches!(
+            worklist.claim(&graph, StepId::new(), "dana"),
+            Err(WorkflowGraphError::StepNotFound(_))
+        ));
+        assert!(worklist.claim(&graph, notify, "dana").is_err());
+        // Assigned tasks are held by the assignee until delegated
+        assert!(worklist.claim(&graph, review, "bob").is_err());
+        assert!(worklist.delegate(&graph, review, "bob", "erin").is_err());
+
+        // A delegate can hand the task on again
+        worklist.delegate(&graph, review, "dana", "carol").unwrap();
+        worklist.delegate(&graph, review, "carol", "erin").unwrap();
+        let inbox = worklist.tasks_for([&graph], "erin").unwrap();
+        assert_eq!(inbox[0].delegated_by.as_deref(), Some("carol"));
+        assert!(worklist.tasks_for([&graph], "dana").unwrap().is_empty());
+
+        worklist.claim(&graph, review, "erin").unwrap();
+        worklist
+            .complete(&mut graph, review, &Actor::new("erin"), HashMap::new())
+            .unwrap();
+        assert!(worklist.claim(&graph, review, "erin").is_err());
+
+        // Approvals with a policy are decided by votes
+        graph
+            .workflow
+            .steps
+            .get_mut(&approve)
+            .unwrap()
+            .config
+            .insert(
+                crate::APPROVAL_POLICY_KEY.to_string(),
+                serde_json::to_value(ApprovalPolicy::new(["dana"], crate::Quorum::Any)).unwrap(),
+            );
+        worklist.claim(&graph, approve, "dana").unwrap();
+        assert!(worklist
+            .complete(&mut graph, approve, &Actor::new("dana"), HashMap::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_sync_forgets_tasks_of_cancelled_workflows() {
+        let (mut graph, review, _) = expense("Expense", None);
+        let mut worklist = Worklist::new();
+        worklist.sync([&graph]);
+        worklist.claim(&graph, review, "bob").unwrap();
+
+        graph.cancel("Withdrawn".to_string()).unwrap();
+        assert!(worklist.sync([&graph]).is_empty());
+        assert!(worklist.tasks_for([&graph], "bob").unwrap().is_empty());
+        assert!(worklist.claim(&graph, review, "bob").is_err());
+        assert!(worklist.tasks.is_empty());
+    }
+
+    #[test]
+    fn test_delegate_completing_under_an_assignee_policy() {
+        let (mut graph, review, _) = expense("Expense", Some("dana"));
+        graph.set_authorization_policy(
+            AuthorizationPolicy::deny_all().allow(Operation::CompleteStep, Grant::Assignee),
+        );
+        let mut worklist = Worklist::new();
+        worklist.delegate(&graph, review, "dana", "carol").unwrap();
+        worklist.claim(&graph, review, "carol").unwrap();
+
+        let carol = Actor::new("carol");
+        assert!(matches!(
+            worklist.complete(&mut graph, review, &carol, HashMap::new()),
+            Err(WorkflowGraphError::Unauthorized { ref actor, .. }) if actor == "carol"
+        ));
+        let denied = graph.audit_log().last().unwrap();
+        assert_eq!((denied.actor.as_str(), denied.allowed), ("carol", false));
+        assert_eq!(
+            worklist.tasks_for([&graph], "carol").unwrap()[0].claimed_by,
+            Some("carol".to_string())
+        );
+
+        // Granting completion to a role lets the delegate finish the task
+        graph.set_authorization_policy(
+            AuthorizationPolicy::deny_all()
+                .allow(Operation::CompleteStep, Grant::Assignee)
+                .allow(Operation::CompleteStep, Grant::Role("clerk".to_string())),
+        );
+        worklist
+            .complete(
+                &mut graph,
+                review,
+                &carol.with_role("clerk"),
+                HashMap::new(),
+            )
+            .unwrap();
+    }
+}